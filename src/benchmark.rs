@@ -0,0 +1,131 @@
+use crate::monitor::sleep_unless_stopped;
+use crate::xid::XidWatch;
+use crate::Traced;
+use log::{info, warn};
+use nvml_wrapper::enum_wrappers::device::TemperatureSensor;
+use nvml_wrapper::{Device, Nvml};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings for stepping the core clock offset to find the highest stable one.
+pub struct Benchmark {
+    pub start: i32,
+    pub end: i32,
+    pub step: u32,
+    /// How long each offset is held before it is considered stable
+    pub dwell: Duration,
+}
+
+/// Outcome of holding a single offset.
+pub enum Status {
+    Stable,
+    Unstable(String),
+    Interrupted,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Stable => write!(f, "stable"),
+            Status::Unstable(reason) => write!(f, "unstable: {}", reason),
+            Status::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl Benchmark {
+    /// Applies each offset from `start` to `end` in turn, stopping at the
+    /// first one that fails to apply, raises an XID error or makes NVML
+    /// queries fail. The original offset is restored afterwards.
+    pub fn run(&self, nvml: &Nvml, device: &mut Device) -> std::io::Result<Vec<(i32, Status)>> {
+        let stop = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&stop))?;
+
+        let original = device.gpc_clock_vf_offset().unwrap_or(0);
+        let xids = XidWatch::register(nvml, [&*device]);
+
+        let mut results = Vec::new();
+        let mut offset = self.start;
+        while offset <= self.end {
+            info!(
+                "Testing GPU core clock offset {:+} MHz for {} s...",
+                offset,
+                self.dwell.as_secs()
+            );
+            let status = match device
+                .set_gpc_clock_vf_offset(offset)
+                .traced("nvmlDeviceSetGpcClkVfOffset")
+            {
+                Ok(()) => self.dwell(device, xids.as_ref(), &stop),
+                Err(e) => Status::Unstable(format!("failed to apply ({:?})", e)),
+            };
+            let done = !matches!(status, Status::Stable);
+            results.push((offset, status));
+            if done {
+                break;
+            }
+            offset = offset.saturating_add_unsigned(self.step);
+        }
+
+        if let Err(e) = device.set_gpc_clock_vf_offset(original) {
+            warn!(
+                "Failed to restore GPU core clock offset {:+} MHz: {:?}",
+                original, e
+            );
+        }
+
+        Ok(results)
+    }
+
+    /// Holds the current offset for the dwell time, watching for XID errors
+    /// and failing queries.
+    fn dwell(&self, device: &Device, xids: Option<&XidWatch>, stop: &AtomicBool) -> Status {
+        let deadline = Instant::now() + self.dwell;
+        while Instant::now() < deadline {
+            if stop.load(Ordering::Relaxed) {
+                return Status::Interrupted;
+            }
+
+            if let Err(e) = device
+                .utilization_rates()
+                .and_then(|_| device.temperature(TemperatureSensor::Gpu))
+            {
+                return Status::Unstable(format!("query failed ({:?})", e));
+            }
+
+            match xids {
+                Some(xids) => match xids.next(POLL_INTERVAL) {
+                    Ok(Some(event)) => return Status::Unstable(event.to_string()),
+                    Ok(None) => {}
+                    Err(e) => return Status::Unstable(format!("event wait failed ({:?})", e)),
+                },
+                None => sleep_unless_stopped(POLL_INTERVAL, stop),
+            }
+        }
+        Status::Stable
+    }
+}
+
+/// Prints the offsets tried and their outcome, followed by the highest
+/// stable offset.
+pub fn print_summary(results: &[(i32, Status)]) {
+    println!("{:>10}  Status", "Offset");
+    for (offset, status) in results {
+        println!("{:>+6} MHz  {}", offset, status);
+    }
+
+    let stable = results
+        .iter()
+        .filter(|(_, status)| matches!(status, Status::Stable))
+        .map(|(offset, _)| offset)
+        .max();
+    match stable {
+        Some(offset) => println!("Highest stable offset: {:+} MHz", offset),
+        None => println!("No stable offset found."),
+    }
+}
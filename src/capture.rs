@@ -0,0 +1,183 @@
+// Reads the settings a GPU is running with into a config file entry, so values
+// tuned interactively with `set`, the TUI or the GUI can be kept without
+// writing the config file by hand.
+
+use crate::nvml_raw::{self, TargetTemperatureLimit};
+use crate::status::compute_mode_name;
+use crate::{config_value, retried, Config, GpuSelector};
+use log::debug;
+use nvml_wrapper::enum_wrappers::device::ComputeMode;
+use nvml_wrapper::enums::device::FanControlPolicy;
+use nvml_wrapper::Device;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Adds `key` to `entry` if the value could be read.
+fn add<T: Into<Value>, E: fmt::Debug>(
+    entry: &mut Map<String, Value>,
+    key: &str,
+    value: Result<T, E>,
+) {
+    match value {
+        Ok(value) => {
+            entry.insert(key.to_string(), value.into());
+        }
+        Err(e) => debug!("Leaving out {}: {:?}", key, e),
+    }
+}
+
+/// Reads the offsets, power limit, target temperatures, fan state, compute
+/// mode and persistence mode of `device` as a GPU entry of the config file,
+/// keyed like `Sets`. Values the GPU cannot report are left out, so applying
+/// the entry touches nothing that was not read.
+pub fn capture(device: &Device) -> Map<String, Value> {
+    let mut entry = Map::new();
+    add(
+        &mut entry,
+        "freqOffset",
+        retried("nvmlDeviceGetGpcClkVfOffset", || {
+            device.gpc_clock_vf_offset()
+        }),
+    );
+    add(
+        &mut entry,
+        "memOffset",
+        retried("nvmlDeviceGetMemClkVfOffset", || {
+            device.mem_clock_vf_offset()
+        }),
+    );
+    add(
+        &mut entry,
+        "powerLimit",
+        retried("nvmlDeviceGetPowerManagementLimit", || {
+            device.power_management_limit()
+        }),
+    );
+    add(
+        &mut entry,
+        "targetTempMin",
+        nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Min),
+    );
+    add(
+        &mut entry,
+        "targetTempMax",
+        nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Max),
+    );
+
+    // The policy of the first fan stands for the rest; manual fans running
+    // at different speeds are kept as `fanSpeeds`.
+    match retried("nvmlDeviceGetNumFans", || device.num_fans()) {
+        Ok(0) => {}
+        Ok(count) => match retried("nvmlDeviceGetFanControlPolicy_v2", || {
+            device.fan_control_policy(0)
+        }) {
+            Ok(FanControlPolicy::TemperatureContinousSw) => {
+                entry.insert("fanAuto".to_string(), true.into());
+            }
+            Ok(FanControlPolicy::Manual) => match (0..count)
+                .map(|fan| retried("nvmlDeviceGetFanSpeed_v2", || device.fan_speed(fan)))
+                .collect::<Result<Vec<u32>, _>>()
+            {
+                Ok(speeds) if speeds.iter().all(|speed| *speed == speeds[0]) => {
+                    entry.insert("fanSpeed".to_string(), speeds[0].into());
+                }
+                Ok(speeds) => {
+                    let speeds: Map<String, Value> = speeds
+                        .into_iter()
+                        .enumerate()
+                        .map(|(fan, speed)| (fan.to_string(), speed.into()))
+                        .collect();
+                    entry.insert("fanSpeeds".to_string(), Value::Object(speeds));
+                }
+                Err(e) => debug!("Leaving out the fan state: {:?}", e),
+            },
+            Err(e) => debug!("Leaving out the fan state: {:?}", e),
+        },
+        Err(e) => debug!("Leaving out the fan state: {:?}", e),
+    }
+
+    match retried("nvmlDeviceGetComputeMode", || device.compute_mode()) {
+        // Not accepted by current drivers, so not a valid `computeMode`.
+        Ok(ComputeMode::ExclusiveThread) => debug!("Leaving out computeMode: exclusive-thread"),
+        mode => add(&mut entry, "computeMode", mode.map(compute_mode_name)),
+    }
+    add(
+        &mut entry,
+        "persistenceMode",
+        retried("nvmlDeviceGetPersistenceMode", || {
+            device.is_in_persistent_mode()
+        }),
+    );
+    entry
+}
+
+/// Adds `entries` to the config file `existing`, or to an empty one, under
+/// the profile `profile` or the top-level `sets`. An entry replaces the one
+/// its GPU already had, under the key the file used for it: existing keys are
+/// matched to the GPU of an entry with `same_gpu`, so a GPU keyed by UUID or
+/// PCI bus ID is recognized when captured by index. Everything else in the
+/// file is kept, except for comments. The format follows the extension of `path` like `Config::parse`,
+/// JSON when there is no path. Returns the new contents, checked to be a
+/// valid config file.
+pub fn merge(
+    path: Option<&str>,
+    existing: Option<&str>,
+    entries: Vec<(GpuSelector, Map<String, Value>)>,
+    profile: Option<&str>,
+    same_gpu: impl Fn(&GpuSelector, &GpuSelector) -> bool,
+) -> Result<String, String> {
+    let extension = path
+        .and_then(|path| std::path::Path::new(path).extension())
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    let existing = existing.filter(|contents| !contents.trim().is_empty());
+
+    let mut config = match existing {
+        Some(contents) => config_value(path, contents)?,
+        None => Value::Object(Map::new()),
+    };
+
+    let mut gpus = config
+        .as_object_mut()
+        .ok_or("the config file is not a map of settings")?;
+    let keys = match profile {
+        Some(profile) => vec!["profiles", profile],
+        None => vec!["sets"],
+    };
+    for key in keys {
+        gpus = gpus
+            .entry(key)
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .ok_or_else(|| format!("`{}` in the config file is not a map", key))?;
+    }
+    for (index, entry) in entries {
+        let existing: Vec<String> = gpus
+            .keys()
+            .filter(|key| {
+                key.parse::<GpuSelector>()
+                    .is_ok_and(|selector| same_gpu(&selector, &index))
+            })
+            .cloned()
+            .collect();
+        for key in &existing {
+            gpus.remove(key);
+        }
+        let key = existing
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| index.to_string());
+        gpus.insert(key, Value::Object(entry));
+    }
+
+    let contents = match extension.as_deref() {
+        Some("toml") => toml::to_string_pretty(&config).map_err(|e| e.to_string())?,
+        Some("yaml" | "yml") => serde_yaml::to_string(&config).map_err(|e| e.to_string())?,
+        _ => format!(
+            "{}\n",
+            serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?
+        ),
+    };
+    Config::parse(path.unwrap_or_default(), &contents)?;
+    Ok(contents)
+}
@@ -0,0 +1,249 @@
+// Compares two profiles by applying each in turn and recording the GPU under
+// the same external workload, so the effect of a change to an overclock shows
+// as numbers instead of readings noted down by hand between runs.
+
+use crate::monitor::{sleep_unless_stopped, Sample};
+use crate::table::Table;
+use crate::{ApplyError, Sets, Snapshot, Traced};
+use log::{error, info, warn};
+use nvml_wrapper::Device;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Average GPU utilization in percent below which a recording is not
+/// considered to be taken under load.
+const LOAD_THRESHOLD: f64 = 50.0;
+
+/// Average of the readings of one value, skipping readings that failed.
+#[derive(Default)]
+struct Average {
+    sum: u64,
+    count: u64,
+}
+
+impl Average {
+    fn push(&mut self, value: Option<u32>) {
+        if let Some(value) = value {
+            self.sum += u64::from(value);
+            self.count += 1;
+        }
+    }
+
+    fn get(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+    }
+}
+
+/// Averages of the readings taken while one profile was applied.
+#[derive(Default)]
+pub struct Telemetry {
+    graphics_clock: Average,
+    memory_clock: Average,
+    /// Power draw in milliwatts
+    power_usage: Average,
+    temperature: Average,
+    gpu_utilization: Average,
+}
+
+impl Telemetry {
+    fn add(&mut self, sample: &Sample) {
+        self.graphics_clock.push(sample.graphics_clock);
+        self.memory_clock.push(sample.memory_clock);
+        self.power_usage.push(sample.power_usage);
+        self.temperature.push(sample.temperature);
+        self.gpu_utilization.push(sample.gpu_utilization);
+    }
+
+    /// The values compared, each with its name, unit and precision.
+    fn values(&self) -> [(&'static str, Option<f64>, &'static str, usize); 6] {
+        let watts = self.power_usage.get().map(|mw| mw / 1000.0);
+        let efficiency = match (self.graphics_clock.get(), watts) {
+            (Some(clock), Some(watts)) if watts > 0.0 => Some(clock / watts),
+            _ => None,
+        };
+        [
+            ("Core clock", self.graphics_clock.get(), "MHz", 0),
+            ("Memory clock", self.memory_clock.get(), "MHz", 0),
+            ("Power draw", watts, "W", 1),
+            ("Temperature", self.temperature.get(), "°C", 0),
+            ("GPU utilization", self.gpu_utilization.get(), "%", 0),
+            ("Core clock per watt", efficiency, "MHz/W", 2),
+        ]
+    }
+}
+
+/// How a comparison ended.
+pub enum Outcome {
+    /// The telemetry recorded under each profile, in order
+    Recorded([Telemetry; 2]),
+    /// A profile was rejected or failed to apply
+    Failed {
+        profile: String,
+        error: ApplyError,
+    },
+    Interrupted,
+}
+
+/// Settings for comparing two profiles.
+pub struct Comparison {
+    /// How long to wait after applying a profile before recording, so the
+    /// clocks and temperature can settle
+    pub settle: Duration,
+    /// How long to record each profile for
+    pub duration: Duration,
+    pub interval: Duration,
+    /// Apply offsets beyond the safety limit
+    pub force: bool,
+}
+
+impl Comparison {
+    /// Applies each of `profiles` in turn, named by the first element, and
+    /// records the telemetry of `device` under it, printing a line per
+    /// reading. Both profiles are validated before anything is applied. The
+    /// parameters a profile changes are restored before the next one is
+    /// applied, so each is recorded on top of the original settings, and
+    /// after the last one, also when a profile fails to apply or on SIGINT or
+    /// SIGTERM. Fans set to a fixed speed are handed back to automatic
+    /// control each time.
+    pub fn run(
+        &self,
+        device: &mut Device,
+        profiles: [(&str, &Sets); 2],
+    ) -> std::io::Result<Outcome> {
+        let stop = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&stop))?;
+
+        let failed = |name: &str, error| Outcome::Failed {
+            profile: name.to_string(),
+            error,
+        };
+        let mut snapshots = Vec::new();
+        for (name, sets) in profiles {
+            if let Some(problem) = sets.validate(device, self.force).into_iter().next() {
+                return Ok(failed(name, problem));
+            }
+            match sets.snapshot(device) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => return Ok(failed(name, e)),
+            }
+        }
+
+        let mut recorded: [Telemetry; 2] = Default::default();
+        for (i, ((name, sets), telemetry)) in profiles.into_iter().zip(&mut recorded).enumerate() {
+            if i > 0 {
+                restore(device, &profiles[i - 1..i], &snapshots[i - 1..i]);
+            }
+            let applied = &profiles[i..=i];
+            let snapshot = &snapshots[i..=i];
+            info!("Applying profile `{}`...", name);
+            if let Err(e) = sets.apply(device, self.force) {
+                restore(device, applied, snapshot);
+                return Ok(failed(name, e));
+            }
+            info!("Letting the GPU settle for {} s...", self.settle.as_secs());
+            sleep_unless_stopped(self.settle, &stop);
+
+            info!(
+                "Recording profile `{}` for {} s...",
+                name,
+                self.duration.as_secs()
+            );
+            let start = Instant::now();
+            while !stop.load(Ordering::Relaxed) && start.elapsed() < self.duration {
+                let sample = Sample::read(device);
+                println!("{}", sample);
+                telemetry.add(&sample);
+                sleep_unless_stopped(self.interval, &stop);
+            }
+            if stop.load(Ordering::Relaxed) {
+                restore(device, applied, snapshot);
+                return Ok(Outcome::Interrupted);
+            }
+        }
+
+        restore(device, &profiles[1..], &snapshots[1..]);
+        Ok(Outcome::Recorded(recorded))
+    }
+}
+
+/// Writes back the values `snapshots` took before `profiles` were applied
+/// and hands the fans those profiles set back to the driver.
+fn restore(device: &mut Device, profiles: &[(&str, &Sets)], snapshots: &[Snapshot]) {
+    info!("Restoring the original GPU parameters...");
+    for snapshot in snapshots {
+        snapshot.restore(device);
+    }
+
+    let sets_fans = |sets: &Sets| {
+        sets.fan_speed.is_some() || sets.fan_policy.is_some() || sets.fan_speeds.is_some()
+    };
+    for (_, sets) in profiles.iter().filter(|(_, sets)| sets_fans(sets)) {
+        match sets.fans(device) {
+            Ok(fans) => {
+                for fan in fans {
+                    match device
+                        .set_default_fan_speed(fan)
+                        .traced("nvmlDeviceSetDefaultFanSpeed_v2")
+                    {
+                        Ok(()) => info!("Restored automatic control of fan {}", fan),
+                        Err(e) => error!(
+                            "Failed to restore automatic control of fan {}: {:?}",
+                            fan, e
+                        ),
+                    }
+                }
+            }
+            Err(e) => error!("Failed to get GPU fan count: {:?}", e),
+        }
+    }
+}
+
+/// Formats an average with its unit, `N/A` if nothing was read.
+fn cell(value: Option<f64>, unit: &str, precision: usize) -> String {
+    match value {
+        Some(value) => format!("{:.*} {}", precision, value, unit),
+        None => "N/A".to_string(),
+    }
+}
+
+/// Prints the averages recorded under the two profiles side by side, with the
+/// change from the first to the second.
+pub fn print(names: [&str; 2], recorded: &[Telemetry; 2]) {
+    let mut table = Table::new(&["", names[0], names[1], "Change"]);
+    for ((name, first, unit, precision), (_, second, _, _)) in
+        recorded[0].values().into_iter().zip(recorded[1].values())
+    {
+        let change = match (first, second) {
+            (Some(first), Some(second)) if first != 0.0 => format!(
+                "{:+.*} {} ({:+.1}%)",
+                precision,
+                second - first,
+                unit,
+                (second / first - 1.0) * 100.0
+            ),
+            (Some(first), Some(second)) => format!("{:+.*} {}", precision, second - first, unit),
+            _ => "N/A".to_string(),
+        };
+        table.row(vec![
+            name.to_string(),
+            cell(first, unit, precision),
+            cell(second, unit, precision),
+            change,
+        ]);
+    }
+    print!("{}", table);
+
+    if recorded.iter().any(|telemetry| {
+        telemetry
+            .gpu_utilization
+            .get()
+            .is_none_or(|utilization| utilization < LOAD_THRESHOLD)
+    }) {
+        warn!(
+            "The GPU averaged below {}% utilization under a profile. Keep a steady workload such as a game or benchmark running while `bench-compare` runs.",
+            LOAD_THRESHOLD
+        );
+    }
+}
@@ -0,0 +1,798 @@
+use crate::monitor::sleep_unless_stopped;
+use crate::xid::XidWatch;
+use crate::{power_supply, pstate, retried, Config, GpuSelector, PowerLimit, Sets, Traced};
+use log::{debug, error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use nvml_wrapper::enum_wrappers::device::{Clock, TemperatureSensor};
+use nvml_wrapper::{Device, Nvml};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long the config file must stay unchanged before it is reloaded, so an
+/// editor saving it in several steps triggers a single reload.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How long to wait before trying again when reapplying reverted settings
+/// failed, so a GPU that keeps refusing them does not flood the log.
+const REAPPLY_RETRY: Duration = Duration::from_secs(30);
+
+/// Checks that a fan curve has at least one point, that its temperatures are
+/// strictly increasing and that every speed is a valid percentage.
+pub fn validate_fan_curve(curve: &[(u32, u32)]) -> Result<(), String> {
+    if curve.is_empty() {
+        return Err("fan curve has no points".to_string());
+    }
+    if let Some((temp, speed)) = curve.iter().find(|(_, speed)| *speed > 100) {
+        return Err(format!(
+            "fan curve point {}°C has speed {}%, must be between 0 and 100",
+            temp, speed
+        ));
+    }
+    if let Some(pair) = curve.windows(2).find(|pair| pair[0].0 >= pair[1].0) {
+        return Err(format!(
+            "fan curve temperatures must be increasing, but {}°C is followed by {}°C",
+            pair[0].0, pair[1].0
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the fan speed for `temperature`, interpolating linearly between
+/// the surrounding curve points and clamping to the first and last points.
+pub fn fan_speed_for(curve: &[(u32, u32)], temperature: u32) -> u32 {
+    let (first_temp, first_speed) = curve[0];
+    if temperature <= first_temp {
+        return first_speed;
+    }
+    for pair in curve.windows(2) {
+        let ((t0, s0), (t1, s1)) = (pair[0], pair[1]);
+        if temperature <= t1 {
+            let ratio = (temperature - t0) as f64 / (t1 - t0) as f64;
+            return (s0 as f64 + ratio * (s1 as f64 - s0 as f64)).round() as u32;
+        }
+    }
+    curve[curve.len() - 1].1
+}
+
+/// A GPU whose fans follow a fan curve, along with the speed last applied.
+struct CurveState {
+    index: GpuSelector,
+    curve: Vec<(u32, u32)>,
+    last_speed: Option<u32>,
+}
+
+/// Core clock offset and power limit that follow GPU utilization: the `load`
+/// values once the GPU has been busy for `hold_seconds`, the `idle` ones once
+/// it has been idle as long. Utilization between the two thresholds keeps the
+/// current values, so a load hovering around one threshold does not make
+/// the settings flip back and forth.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AutoOc {
+    /// GPU utilization in percent from which the GPU counts as busy
+    #[serde(default = "AutoOc::default_high_utilization")]
+    pub high_utilization: u32,
+    /// GPU utilization in percent below which the GPU counts as idle
+    #[serde(default = "AutoOc::default_low_utilization")]
+    pub low_utilization: u32,
+    /// How long the utilization must stay past a threshold before switching
+    #[serde(default = "AutoOc::default_hold_seconds")]
+    pub hold_seconds: u64,
+    /// Core clock offset in MHz while idle
+    pub idle_offset: i32,
+    /// Core clock offset in MHz while busy
+    pub load_offset: i32,
+    /// Power limit while idle, left alone if not set
+    pub idle_power_limit: Option<PowerLimit>,
+    /// Power limit while busy, left alone if not set
+    pub load_power_limit: Option<PowerLimit>,
+}
+
+impl AutoOc {
+    fn default_high_utilization() -> u32 {
+        80
+    }
+
+    fn default_low_utilization() -> u32 {
+        20
+    }
+
+    fn default_hold_seconds() -> u64 {
+        10
+    }
+
+    /// Checks that the thresholds are percentages with the idle one below
+    /// the busy one, and that the busy offset is not below the idle one.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.high_utilization > 100 {
+            return Err(format!(
+                "highUtilization {}% must be between 0 and 100",
+                self.high_utilization
+            ));
+        }
+        if self.low_utilization >= self.high_utilization {
+            return Err(format!(
+                "lowUtilization {}% must be below highUtilization {}%",
+                self.low_utilization, self.high_utilization
+            ));
+        }
+        if self.idle_offset > self.load_offset {
+            return Err(format!(
+                "idleOffset {:+} MHz is above loadOffset {:+} MHz",
+                self.idle_offset, self.load_offset
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sets the core clock offset and power limit for a busy or idle GPU.
+    fn apply(&self, device: &mut Device, busy: bool) -> Result<(), String> {
+        let (offset, limit) = match busy {
+            true => (self.load_offset, self.load_power_limit),
+            false => (self.idle_offset, self.idle_power_limit),
+        };
+        retried("nvmlDeviceSetGpcClkVfOffset", || {
+            device.set_gpc_clock_vf_offset(offset)
+        })
+        .map_err(|e| format!("Failed to set GPU frequency offset: {:?}", e))?;
+        if let Some(limit) = limit {
+            let limit = limit
+                .milliwatts(device)
+                .map_err(|e| format!("Failed to get GPU power limit range: {:?}", e))?;
+            retried("nvmlDeviceSetPowerManagementLimit", || {
+                device.set_power_management_limit(limit)
+            })
+            .map_err(|e| format!("Failed to set GPU power limit: {:?}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// A GPU under auto overclocking, along with whether the busy settings are
+/// applied and since when the utilization has pointed the other way.
+struct AutoOcState {
+    index: GpuSelector,
+    settings: AutoOc,
+    busy: Option<bool>,
+    crossed_at: Option<Instant>,
+}
+
+impl AutoOcState {
+    /// Returns whether the busy (`true`) or idle (`false`) settings are due
+    /// once `utilization` has stayed on the other side of a threshold for the
+    /// hold time, or `None` to keep the current ones. The first reading picks
+    /// the matching settings right away.
+    fn due(&mut self, utilization: u32, now: Instant) -> Option<bool> {
+        let wanted = match self.busy {
+            Some(true) => utilization >= self.settings.low_utilization,
+            Some(false) | None => utilization >= self.settings.high_utilization,
+        };
+        if self.busy == Some(wanted) {
+            self.crossed_at = None;
+            return None;
+        }
+        if self.busy.is_some() {
+            let crossed_at = *self.crossed_at.get_or_insert(now);
+            if now.duration_since(crossed_at) < Duration::from_secs(self.settings.hold_seconds) {
+                return None;
+            }
+        }
+        Some(wanted)
+    }
+
+    /// Applies the idle or busy settings when [`AutoOcState::due`] says so.
+    fn update(&mut self, device: &mut Device, utilization: u32) {
+        let Some(wanted) = self.due(utilization, Instant::now()) else {
+            return;
+        };
+        match self.settings.apply(device, wanted) {
+            Ok(()) => {
+                info!(
+                    "GPU {}: {}% utilization, applied the {} settings",
+                    self.index,
+                    utilization,
+                    if wanted { "load" } else { "idle" }
+                );
+                self.busy = Some(wanted);
+                self.crossed_at = None;
+            }
+            Err(e) => error!("GPU {}: {}", self.index, e),
+        }
+    }
+}
+
+/// A GPU whose settings are applied again when its clock offsets revert to
+/// zero, as after suspend/resume or a driver reload.
+struct StickyState {
+    index: GpuSelector,
+    sets: Sets,
+    /// When reapplying last failed, if it did
+    failed_at: Option<Instant>,
+}
+
+impl StickyState {
+    /// The configured non-zero clock offsets that `device` now reports as
+    /// zero. The core offset of auto overclocking changes with the load, so
+    /// only a fixed one is checked.
+    fn reverted(&self, device: &Device) -> Vec<&'static str> {
+        let freq_offset = self
+            .sets
+            .freq_offset
+            .filter(|_| self.sets.auto_oc.is_none());
+        let mut reverted = Vec::new();
+        for (name, wanted, current) in [
+            (
+                "core clock offset",
+                freq_offset,
+                device
+                    .gpc_clock_vf_offset()
+                    .traced("nvmlDeviceGetGpcClkVfOffset"),
+            ),
+            (
+                "memory clock offset",
+                self.sets.mem_offset,
+                device
+                    .mem_clock_vf_offset()
+                    .traced("nvmlDeviceGetMemClkVfOffset"),
+            ),
+        ] {
+            match (wanted, current) {
+                (Some(wanted), Ok(0)) if wanted != 0 => reverted.push(name),
+                (Some(_), Err(e)) => {
+                    debug!("GPU {}: Failed to check the {}: {:?}", self.index, name, e)
+                }
+                _ => {}
+            }
+        }
+        reverted
+    }
+
+    /// Applies the settings again if the offsets reverted, logging when it
+    /// happened. Returns whether they were reapplied.
+    fn update(&mut self, device: &mut Device) -> bool {
+        let reverted = self.reverted(device);
+        if reverted.is_empty()
+            || self
+                .failed_at
+                .is_some_and(|failed_at| failed_at.elapsed() < REAPPLY_RETRY)
+        {
+            return false;
+        }
+        let time = humantime::format_rfc3339_seconds(SystemTime::now());
+        warn!(
+            "[{}] GPU {}: The {} reverted to zero, reapplying the settings",
+            time,
+            self.index,
+            reverted.join(" and ")
+        );
+        match self.sets.apply(device, false) {
+            Ok(()) => {
+                info!("[{}] GPU {}: Reapplied the settings", time, self.index);
+                self.failed_at = None;
+                true
+            }
+            Err(e) => {
+                error!(
+                    "[{}] GPU {}: Failed to reapply the settings: {}",
+                    time, self.index, e
+                );
+                self.failed_at = Some(Instant::now());
+                false
+            }
+        }
+    }
+}
+
+/// Profiles to switch between as the machine changes power source. A
+/// missing profile stands for the top-level `sets`.
+pub struct PowerProfiles {
+    pub ac: Option<String>,
+    pub battery: Option<String>,
+    /// Directory listing the power supplies in sysfs
+    pub supply_path: PathBuf,
+}
+
+impl PowerProfiles {
+    fn on_battery(&self) -> bool {
+        power_supply::on_battery(&self.supply_path)
+    }
+
+    fn profile(&self, on_battery: bool) -> Option<&str> {
+        if on_battery {
+            self.battery.as_deref()
+        } else {
+            self.ac.as_deref()
+        }
+    }
+}
+
+/// The per-GPU settings of the profile `name`, falling back to the top-level
+/// `sets` if the config file lacks it.
+fn profile<'a>(config: &'a Config, name: Option<&str>) -> &'a HashMap<GpuSelector, Sets> {
+    config.profile(name).unwrap_or_else(|e| {
+        error!("{}, using the top-level settings", e);
+        &config.sets
+    })
+}
+
+/// What the daemon keeps driving after applying a config.
+struct Active<'nvml> {
+    curves: Vec<CurveState>,
+    auto_ocs: Vec<AutoOcState>,
+    sticky: Vec<StickyState>,
+    xids: Option<XidWatch<'nvml>>,
+}
+
+/// Applies `gpus` and starts following their fan curves, utilization and XID
+/// errors, and with `reapply` their clock offsets.
+fn activate<'nvml>(
+    nvml: &'nvml Nvml,
+    gpus: &HashMap<GpuSelector, Sets>,
+    reapply: bool,
+) -> Active<'nvml> {
+    if !crate::apply_all(gpus, nvml) {
+        error!("Some GPU parameters could not be set.");
+    }
+    Active {
+        curves: fan_curves(nvml, gpus),
+        auto_ocs: auto_ocs(nvml, gpus),
+        sticky: match reapply {
+            true => sticky(nvml, gpus),
+            false => Vec::new(),
+        },
+        xids: watch_xids(nvml, gpus),
+    }
+}
+
+/// Whether `apply_all` skipped the entry for not matching its GPU.
+fn skipped(nvml: &Nvml, index: &GpuSelector, sets: &Sets) -> bool {
+    sets.require_gpu.is_some()
+        && index
+            .device(nvml)
+            .is_ok_and(|device| sets.check_gpu(&device).is_err())
+}
+
+/// The valid fan curves of `gpus`, reporting the invalid ones. Entries that
+/// `apply_all` skipped for not matching their GPU are left out.
+fn fan_curves(nvml: &Nvml, gpus: &HashMap<GpuSelector, Sets>) -> Vec<CurveState> {
+    let mut curves = Vec::new();
+    for (index, sets) in gpus {
+        let Some(curve) = &sets.fan_curve else {
+            continue;
+        };
+        if skipped(nvml, index, sets) {
+            continue;
+        }
+        match validate_fan_curve(curve) {
+            Ok(()) => curves.push(CurveState {
+                index: index.clone(),
+                curve: curve.clone(),
+                last_speed: None,
+            }),
+            Err(e) => error!("GPU {}: invalid fan curve: {}", index, e),
+        }
+    }
+    curves
+}
+
+/// The valid auto overclocking settings of `gpus`, reporting the invalid
+/// ones. Entries that `apply_all` skipped are left out.
+fn auto_ocs(nvml: &Nvml, gpus: &HashMap<GpuSelector, Sets>) -> Vec<AutoOcState> {
+    let mut auto_ocs = Vec::new();
+    for (index, sets) in gpus {
+        let Some(settings) = &sets.auto_oc else {
+            continue;
+        };
+        if skipped(nvml, index, sets) {
+            continue;
+        }
+        match settings.validate() {
+            Ok(()) => auto_ocs.push(AutoOcState {
+                index: index.clone(),
+                settings: settings.clone(),
+                busy: None,
+                crossed_at: None,
+            }),
+            Err(e) => error!("GPU {}: invalid auto overclocking settings: {}", index, e),
+        }
+    }
+    auto_ocs
+}
+
+/// The entries of `gpus` with clock offsets to keep an eye on. Entries that
+/// `apply_all` skipped are left out.
+fn sticky(nvml: &Nvml, gpus: &HashMap<GpuSelector, Sets>) -> Vec<StickyState> {
+    gpus.iter()
+        .filter(|(_, sets)| sets.freq_offset.is_some() || sets.mem_offset.is_some())
+        .filter(|(index, sets)| !skipped(nvml, index, sets))
+        .map(|(index, sets)| StickyState {
+            index: index.clone(),
+            sets: sets.clone(),
+            failed_at: None,
+        })
+        .collect()
+}
+
+/// Registers for the XID errors of the GPUs in `gpus`.
+fn watch_xids<'nvml>(
+    nvml: &'nvml Nvml,
+    gpus: &HashMap<GpuSelector, Sets>,
+) -> Option<XidWatch<'nvml>> {
+    let devices: Vec<_> = gpus
+        .keys()
+        .filter_map(|index| index.device(nvml).ok())
+        .collect();
+    XidWatch::register(nvml, &devices)
+}
+
+/// Hands the fans of `gpus` back to the driver and clears the clock offsets
+/// they set, so stopping the daemon does not leave a fan stuck at a fixed
+/// speed. Power limits driven by auto overclocking go back to the default.
+fn restore(nvml: &Nvml, gpus: &HashMap<GpuSelector, Sets>) {
+    for (index, sets) in gpus {
+        let mut device = match index.device(nvml) {
+            Ok(device) => device,
+            Err(e) => {
+                error!("GPU {}: Failed to get GPU: {:?}", index, e);
+                continue;
+            }
+        };
+        if sets.check_gpu(&device).is_err() {
+            continue;
+        }
+        let report = |name: &str, result: Result<(), nvml_wrapper::error::NvmlError>| match result {
+            Ok(()) => info!("GPU {}: Restored {}", index, name),
+            Err(e) => error!("GPU {}: Failed to restore {}: {:?}", index, name, e),
+        };
+
+        if sets.fan_curve.is_some()
+            || sets.fan_speed.is_some()
+            || sets.fan_policy.is_some()
+            || sets.fan_speeds.is_some()
+        {
+            match sets.fans(&device) {
+                Ok(fans) => {
+                    for fan in fans {
+                        report(
+                            &format!("automatic control of fan {}", fan),
+                            device
+                                .set_default_fan_speed(fan)
+                                .traced("nvmlDeviceSetDefaultFanSpeed_v2"),
+                        );
+                    }
+                }
+                Err(e) => error!("GPU {}: Failed to get GPU fan count: {:?}", index, e),
+            }
+        }
+        if sets.freq_offset.is_some() || sets.auto_oc.is_some() {
+            report(
+                "core clock offset",
+                device
+                    .set_gpc_clock_vf_offset(0)
+                    .traced("nvmlDeviceSetGpcClkVfOffset"),
+            );
+        }
+        if sets.mem_offset.is_some() {
+            report(
+                "memory clock offset",
+                device
+                    .set_mem_clock_vf_offset(0)
+                    .traced("nvmlDeviceSetMemClkVfOffset"),
+            );
+        }
+        if sets.auto_oc.as_ref().is_some_and(|auto_oc| {
+            auto_oc.idle_power_limit.is_some() || auto_oc.load_power_limit.is_some()
+        }) {
+            report(
+                "power limit",
+                device
+                    .power_management_limit_default()
+                    .and_then(|limit| device.set_power_management_limit(limit))
+                    .traced("nvmlDeviceSetPowerManagementLimit"),
+            );
+        }
+        for (clock, name, offsets) in [
+            (Clock::Graphics, "core", sets.pstate_freq_offsets()),
+            (Clock::Memory, "memory", sets.pstate_mem_offsets()),
+        ] {
+            for (number, _) in offsets {
+                report(
+                    &format!("P{} {} clock offset", number, name),
+                    pstate(number)
+                        .and_then(|state| device.set_clock_offset(clock, state, 0))
+                        .traced("nvmlDeviceSetClockOffsets"),
+                );
+            }
+        }
+    }
+}
+
+/// Watches the directory containing `path` for changes, since editors often
+/// replace the file rather than write to it. The watcher stops when dropped.
+fn watch_config(
+    path: &Path,
+) -> notify::Result<(RecommendedWatcher, Receiver<notify::Result<notify::Event>>)> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok((watcher, receiver))
+}
+
+/// Reads and validates the config file at `path`. Returns `None` with the
+/// reason logged if it cannot be used, in which case the current settings
+/// are kept.
+fn reload(nvml: &Nvml, path: &str) -> Option<Config> {
+    let config = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| Config::parse(path, &contents))
+        .map_err(|e| {
+            error!(
+                "Failed to reload {}: {}, keeping the current settings",
+                path, e
+            )
+        })
+        .ok()?;
+    if !config.validate(nvml) {
+        error!(
+            "Failed to reload {}: invalid settings, keeping the current ones",
+            path
+        );
+        return None;
+    }
+    info!("Reloaded {}", path);
+    Some(config)
+}
+
+/// Applies the config and drives the fans of every GPU with a configured fan
+/// curve, and the core offset and power limit of every GPU with auto
+/// overclocking, re-reading temperature and utilization every `interval`
+/// until SIGINT or SIGTERM is received. XID errors of the configured GPUs are logged, and reset the
+/// affected GPU if `revert_on_xid` is set. When the config file at `path`
+/// changes, it is validated and applied again. With `power` set, the profile
+/// for the current power source is applied instead of the top-level `sets`,
+/// switching whenever the power source changes. Before a reloaded config or
+/// another profile is applied, the settings it replaces are restored as on
+/// exit, so fans and auto overclocking it no longer drives are released.
+/// With `reapply`, the settings of a GPU are applied again whenever its
+/// configured clock offsets read as zero, as after suspend/resume or a driver
+/// reload. On SIGINT or SIGTERM, fans return to automatic control and clock
+/// offsets to zero unless `keep_on_exit` is set.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    nvml: &Nvml,
+    path: &str,
+    mut config: Config,
+    interval: Duration,
+    revert_on_xid: bool,
+    power: Option<&PowerProfiles>,
+    reapply: bool,
+    keep_on_exit: bool,
+) -> std::io::Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&stop))?;
+
+    let mut on_battery = power.is_some_and(PowerProfiles::on_battery);
+    let active = |on_battery| power.and_then(|power| power.profile(on_battery));
+    let mut driven = activate(nvml, profile(&config, active(on_battery)), reapply);
+    let file_name = Path::new(path).file_name();
+    let changes = watch_config(Path::new(path))
+        .map_err(|e| warn!("Failed to watch {} for changes: {}", path, e))
+        .ok();
+
+    if driven.curves.is_empty()
+        && driven.auto_ocs.is_empty()
+        && driven.sticky.is_empty()
+        && driven.xids.is_none()
+        && changes.is_none()
+        && power.is_none()
+    {
+        info!("No fan curves or auto overclocking configured, nothing to do.");
+        return Ok(());
+    }
+
+    let mut changed_at = None;
+    while !stop.load(Ordering::Relaxed) {
+        if let Some((_, events)) = &changes {
+            for event in events.try_iter() {
+                match event {
+                    Ok(event)
+                        if !event.kind.is_access()
+                            && event.paths.iter().any(|p| p.file_name() == file_name) =>
+                    {
+                        changed_at = Some(Instant::now());
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to watch {} for changes: {}", path, e),
+                }
+            }
+        }
+        if changed_at.is_some_and(|time| time.elapsed() >= RELOAD_DEBOUNCE) {
+            changed_at = None;
+            if let Some(reloaded) = reload(nvml, path) {
+                restore(nvml, profile(&config, active(on_battery)));
+                config = reloaded;
+                driven = activate(nvml, profile(&config, active(on_battery)), reapply);
+            }
+        }
+        if let Some(power) = power {
+            if power.on_battery() != on_battery {
+                restore(nvml, profile(&config, active(on_battery)));
+                on_battery = !on_battery;
+                info!(
+                    "Switched to {} power, applying {}",
+                    if on_battery { "battery" } else { "AC" },
+                    active(on_battery).map_or("the top-level settings".to_string(), |name| {
+                        format!("profile `{}`", name)
+                    })
+                );
+                driven = activate(nvml, profile(&config, active(on_battery)), reapply);
+            }
+        }
+
+        if let Some(xids) = &driven.xids {
+            xids.drain(revert_on_xid);
+        }
+        for sticky in driven.sticky.iter_mut() {
+            let mut device = match sticky.index.device(nvml) {
+                Ok(device) => device,
+                Err(e) => {
+                    debug!("GPU {}: Failed to get GPU: {:?}", sticky.index, e);
+                    continue;
+                }
+            };
+            if sticky.update(&mut device) {
+                // The reset may have taken the fan speed and the auto
+                // overclocking settings along, so they are set again too.
+                for curve in driven.curves.iter_mut() {
+                    if curve.index == sticky.index {
+                        curve.last_speed = None;
+                    }
+                }
+                for auto_oc in driven.auto_ocs.iter_mut() {
+                    if auto_oc.index == sticky.index {
+                        auto_oc.busy = None;
+                    }
+                }
+            }
+        }
+        for auto_oc in driven.auto_ocs.iter_mut() {
+            let mut device = match auto_oc.index.device(nvml) {
+                Ok(device) => device,
+                Err(e) => {
+                    error!("GPU {}: Failed to get GPU: {:?}", auto_oc.index, e);
+                    continue;
+                }
+            };
+            match device
+                .utilization_rates()
+                .traced("nvmlDeviceGetUtilizationRates")
+            {
+                Ok(utilization) => auto_oc.update(&mut device, utilization.gpu),
+                Err(e) => error!(
+                    "GPU {}: Failed to get GPU utilization: {:?}",
+                    auto_oc.index, e
+                ),
+            }
+        }
+        for CurveState {
+            index,
+            curve,
+            last_speed,
+        } in driven.curves.iter_mut()
+        {
+            let mut device = match index.device(nvml) {
+                Ok(device) => device,
+                Err(e) => {
+                    error!("GPU {}: Failed to get GPU: {:?}", index, e);
+                    continue;
+                }
+            };
+            let temperature = match device
+                .temperature(TemperatureSensor::Gpu)
+                .traced("nvmlDeviceGetTemperature")
+            {
+                Ok(temperature) => temperature,
+                Err(e) => {
+                    error!("GPU {}: Failed to get GPU temperature: {:?}", index, e);
+                    continue;
+                }
+            };
+
+            let speed = fan_speed_for(curve, temperature);
+            if *last_speed == Some(speed) {
+                continue;
+            }
+
+            let result = device
+                .num_fans()
+                .traced("nvmlDeviceGetNumFans")
+                .and_then(|fans| {
+                    (0..fans).try_for_each(|fan| {
+                        device
+                            .set_fan_speed(fan, speed)
+                            .traced("nvmlDeviceSetFanSpeed_v2")
+                    })
+                });
+            match result {
+                Ok(()) => {
+                    info!(
+                        "GPU {}: {}°C, fan speed set to {}%",
+                        index, temperature, speed
+                    );
+                    *last_speed = Some(speed);
+                }
+                Err(e) => error!("GPU {}: Failed to set GPU fan speed: {:?}", index, e),
+            }
+        }
+        sleep_unless_stopped(interval, &stop);
+    }
+
+    if !keep_on_exit {
+        info!("Stopping, restoring automatic fan control and clock offsets");
+        restore(nvml, profile(&config, active(on_battery)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> AutoOcState {
+        AutoOcState {
+            index: GpuSelector::Index(0),
+            settings: serde_json::from_str(r#"{ "idleOffset": 0, "loadOffset": 100 }"#).unwrap(),
+            busy: None,
+            crossed_at: None,
+        }
+    }
+
+    #[test]
+    fn first_reading_picks_the_settings_right_away() {
+        let start = Instant::now();
+        assert_eq!(state().due(50, start), Some(false));
+        assert_eq!(state().due(90, start), Some(true));
+    }
+
+    #[test]
+    fn utilization_between_the_thresholds_keeps_the_settings() {
+        let start = Instant::now();
+        let mut state = state();
+        state.busy = Some(true);
+        assert_eq!(state.due(50, start + Duration::from_secs(60)), None);
+        state.busy = Some(false);
+        assert_eq!(state.due(50, start + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn switching_waits_for_the_hold_time() {
+        let start = Instant::now();
+        let mut state = state();
+        state.busy = Some(false);
+        assert_eq!(state.due(90, start), None);
+        assert_eq!(state.due(95, start + Duration::from_secs(9)), None);
+        assert_eq!(state.due(85, start + Duration::from_secs(10)), Some(true));
+    }
+
+    #[test]
+    fn dipping_back_restarts_the_hold_time() {
+        let start = Instant::now();
+        let mut state = state();
+        state.busy = Some(true);
+        assert_eq!(state.due(10, start), None);
+        assert_eq!(state.due(30, start + Duration::from_secs(5)), None);
+        assert_eq!(state.due(10, start + Duration::from_secs(8)), None);
+        assert_eq!(state.due(10, start + Duration::from_secs(17)), None);
+        assert_eq!(state.due(10, start + Duration::from_secs(18)), Some(false));
+    }
+}
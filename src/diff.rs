@@ -0,0 +1,256 @@
+// Compares the settings of a config file with the live values of a GPU, to
+// verify that a boot service applied everything and to detect drift.
+
+use crate::nvml_raw::{self, TargetTemperatureLimit};
+use crate::status::compute_mode_name;
+use crate::{pstate, Sets};
+use log::debug;
+use nvml_wrapper::enum_wrappers::device::{Clock, TemperatureThreshold};
+use nvml_wrapper::enums::device::FanControlPolicy;
+use nvml_wrapper::Device;
+use std::fmt;
+
+/// A parameter whose live value differs from the desired one.
+pub struct Difference {
+    pub parameter: String,
+    pub desired: String,
+    /// `None` when the live value could not be read
+    pub current: Option<String>,
+    /// How far the live value is from the desired one, for numeric values
+    pub delta: Option<String>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.current {
+            Some(current) => write!(
+                f,
+                "{} is {}, config has {}",
+                self.parameter, current, self.desired
+            )?,
+            None => write!(
+                f,
+                "{} is unknown, config has {}",
+                self.parameter, self.desired
+            )?,
+        }
+        if let Some(delta) = &self.delta {
+            write!(f, " (off by {})", delta)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Differences(Vec<Difference>);
+
+impl Differences {
+    /// Compares a numeric parameter. Offsets are shown with a sign.
+    fn number<E: fmt::Debug>(
+        &mut self,
+        parameter: &str,
+        desired: Option<i64>,
+        current: impl FnOnce() -> Result<i64, E>,
+        unit: &str,
+        offset: bool,
+    ) {
+        let Some(desired) = desired else {
+            return;
+        };
+        let format = |value: i64| match offset {
+            true => format!("{:+} {}", value, unit),
+            false => format!("{} {}", value, unit),
+        };
+        match current() {
+            Ok(current) if current == desired => {}
+            Ok(current) => self.0.push(Difference {
+                parameter: parameter.to_string(),
+                desired: format(desired),
+                current: Some(format(current)),
+                delta: Some(format!("{:+} {}", current - desired, unit)),
+            }),
+            Err(e) => self.unknown(parameter, format(desired), e),
+        }
+    }
+
+    /// Compares a parameter by its displayed value.
+    fn value<E: fmt::Debug>(
+        &mut self,
+        parameter: &str,
+        desired: Option<String>,
+        current: impl FnOnce() -> Result<String, E>,
+    ) {
+        let Some(desired) = desired else {
+            return;
+        };
+        match current() {
+            Ok(current) if current == desired => {}
+            Ok(current) => self.0.push(Difference {
+                parameter: parameter.to_string(),
+                desired,
+                current: Some(current),
+                delta: None,
+            }),
+            Err(e) => self.unknown(parameter, desired, e),
+        }
+    }
+
+    fn unknown(&mut self, parameter: &str, desired: String, error: impl fmt::Debug) {
+        debug!("Query for {} failed: {:?}", parameter, error);
+        self.0.push(Difference {
+            parameter: parameter.to_string(),
+            desired,
+            current: None,
+            delta: None,
+        });
+    }
+}
+
+/// Lists the parameters of `sets` whose live value on `device` differs.
+/// Locked clocks and relative power changes cannot be compared and are
+/// skipped.
+pub fn diff(sets: &Sets, device: &Device) -> Vec<Difference> {
+    let mut differences = Differences::default();
+
+    differences.number(
+        "GPU core clock offset",
+        sets.freq_offset.map(i64::from),
+        || device.gpc_clock_vf_offset().map(i64::from),
+        "MHz",
+        true,
+    );
+    for (number, offset) in sets.pstate_freq_offsets() {
+        differences.number(
+            &format!("GPU P{} core clock offset", number),
+            Some(offset.into()),
+            || {
+                pstate(number)
+                    .and_then(|state| device.clock_offset(Clock::Graphics, state))
+                    .map(|offset| offset.clock_offset_mhz.into())
+            },
+            "MHz",
+            true,
+        );
+    }
+    differences.number(
+        "GPU memory clock offset",
+        sets.mem_offset.map(i64::from),
+        || device.mem_clock_vf_offset().map(i64::from),
+        "MHz",
+        true,
+    );
+    for (number, offset) in sets.pstate_mem_offsets() {
+        differences.number(
+            &format!("GPU P{} memory clock offset", number),
+            Some(offset.into()),
+            || {
+                pstate(number)
+                    .and_then(|state| device.clock_offset(Clock::Memory, state))
+                    .map(|offset| offset.clock_offset_mhz.into())
+            },
+            "MHz",
+            true,
+        );
+    }
+
+    if let Some(limit) = sets.power_limit {
+        match limit.milliwatts(device) {
+            Ok(limit) => differences.number(
+                "GPU power limit",
+                Some(limit.into()),
+                || device.power_management_limit().map(i64::from),
+                "mW",
+                false,
+            ),
+            Err(e) => differences.unknown("GPU power limit", limit.to_string(), e),
+        }
+    }
+
+    for (parameter, limit, celsius) in [
+        (
+            "GPU minimum target temperature",
+            TargetTemperatureLimit::Min,
+            sets.target_temp_min,
+        ),
+        (
+            "GPU maximum target temperature",
+            TargetTemperatureLimit::Max,
+            sets.target_temp_max,
+        ),
+    ] {
+        differences.number(
+            parameter,
+            celsius.map(i64::from),
+            || nvml_raw::get_target_temperature_limit(device, limit).map(i64::from),
+            "°C",
+            false,
+        );
+    }
+    differences.number(
+        "GPU max temperature",
+        sets.max_temp_limit.map(i64::from),
+        || {
+            device
+                .temperature_threshold(TemperatureThreshold::GpuMax)
+                .map(i64::from)
+        },
+        "°C",
+        false,
+    );
+
+    differences.value(
+        "GPU compute mode",
+        sets.compute_mode.map(|mode| mode.to_string()),
+        || {
+            device
+                .compute_mode()
+                .map(|mode| compute_mode_name(mode).to_string())
+        },
+    );
+    let enabled = |enabled: bool| if enabled { "enabled" } else { "disabled" }.to_string();
+    differences.value(
+        "GPU persistence mode",
+        sets.persistence_mode.map(enabled),
+        || device.is_in_persistent_mode().map(enabled),
+    );
+
+    if sets.fan_speed.is_some() || sets.fan_auto || sets.fan_policy.is_some() {
+        match sets.fans(device) {
+            Ok(fans) => {
+                for fan in fans {
+                    differences.number(
+                        &format!("GPU fan {} speed", fan),
+                        sets.fan_speed.map(i64::from),
+                        || device.fan_speed(fan).map(i64::from),
+                        "%",
+                        false,
+                    );
+                    differences.value(
+                        &format!("GPU fan {} control", fan),
+                        sets.fan_auto
+                            .then(|| "automatic".to_string())
+                            .or_else(|| sets.fan_policy.map(|policy| policy.to_string())),
+                        || {
+                            device.fan_control_policy(fan).map(|policy| match policy {
+                                FanControlPolicy::TemperatureContinousSw => "automatic".to_string(),
+                                FanControlPolicy::Manual => "manual".to_string(),
+                            })
+                        },
+                    );
+                }
+            }
+            Err(e) => differences.unknown("GPU fans", "configured fan settings".to_string(), e),
+        }
+    }
+    for (fan, speed) in sets.sorted_fan_speeds() {
+        differences.number(
+            &format!("GPU fan {} speed", fan),
+            Some(speed.into()),
+            || device.fan_speed(fan).map(i64::from),
+            "%",
+            false,
+        );
+    }
+
+    differences.0
+}
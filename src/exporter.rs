@@ -0,0 +1,214 @@
+// Serves GPU metrics in the Prometheus text format over a minimal HTTP/1.1
+// handler, so a homelab Prometheus can scrape them without extra
+// dependencies. Every scrape queries NVML afresh.
+
+use crate::monitor::Sample;
+use crate::GpuSelector;
+use log::{debug, info, warn};
+use nvml_wrapper::Nvml;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+/// How long a client may take to send its request before the connection is
+/// dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The readings of one GPU taken for a scrape.
+struct Reading {
+    index: u32,
+    sample: Sample,
+    freq_offset: Option<i32>,
+    mem_offset: Option<i32>,
+    /// Enforced power limit in mW
+    power_limit: Option<u32>,
+}
+
+/// Answers scrapes on `address`:`port` until the process is stopped. Exports
+/// the GPU selected by `gpu`, or every GPU when it is `None`. Each connection
+/// is answered on its own thread, so a client that stalls does not hold up
+/// the scrapes of others.
+pub fn serve(nvml: &Nvml, gpu: Option<&GpuSelector>, address: IpAddr, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((address, port))?;
+    info!(
+        "Serving GPU metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            scope.spawn(move || {
+                if let Err(e) = handle(nvml, gpu, stream) {
+                    debug!("Failed to answer request: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(nvml: &Nvml, gpu: Option<&GpuSelector>, mut stream: TcpStream) -> io::Result<()> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut reader = BufReader::new(&stream);
+    // The headers are not needed, but must be read before answering. A
+    // client still sending them at the deadline is dropped.
+    let mut request_line = String::new();
+    let mut line = String::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        reader.get_ref().set_read_timeout(Some(remaining))?;
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if request_line.is_empty() {
+            request_line = line.clone();
+        }
+    }
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", metrics(&readings(nvml, gpu)))
+    } else {
+        (
+            "404 Not Found",
+            "Metrics are served at /metrics\n".to_string(),
+        )
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Reads every exported GPU, skipping those that cannot be opened.
+fn readings(nvml: &Nvml, gpu: Option<&GpuSelector>) -> Vec<Reading> {
+    let devices = match gpu {
+        Some(gpu) => vec![gpu.device(nvml)],
+        None => {
+            let count = nvml.device_count().unwrap_or_else(|e| {
+                warn!("Failed to get GPU count: {:?}", e);
+                0
+            });
+            (0..count)
+                .map(|index| nvml.device_by_index(index))
+                .collect()
+        }
+    };
+
+    devices
+        .into_iter()
+        .filter_map(|device| {
+            device
+                .and_then(|device| Ok((device.index()?, device)))
+                .map_err(|e| warn!("Failed to get GPU: {:?}", e))
+                .ok()
+        })
+        .map(|(index, device)| Reading {
+            index,
+            sample: Sample::read(&device),
+            freq_offset: device.gpc_clock_vf_offset().ok(),
+            mem_offset: device.mem_clock_vf_offset().ok(),
+            power_limit: device.enforced_power_limit().ok(),
+        })
+        .collect()
+}
+
+/// Formats the readings as Prometheus gauges labelled with the GPU index.
+fn metrics(readings: &[Reading]) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, values: &dyn Fn(&Reading) -> Vec<(String, f64)>| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for reading in readings {
+            for (labels, value) in values(reading) {
+                let _ = writeln!(
+                    out,
+                    "{}{{gpu=\"{}\"{}}} {}",
+                    name, reading.index, labels, value
+                );
+            }
+        }
+    };
+    let plain = |value: Option<f64>| {
+        value
+            .map(|value| (String::new(), value))
+            .into_iter()
+            .collect()
+    };
+
+    gauge("zelos_gpu_temperature_celsius", "GPU temperature", &|r| {
+        plain(r.sample.temperature.map(f64::from))
+    });
+    gauge(
+        "zelos_gpu_memory_temperature_celsius",
+        "GPU memory junction temperature",
+        &|r| plain(r.sample.memory_temperature.map(f64::from)),
+    );
+    gauge("zelos_gpu_utilization_percent", "GPU utilization", &|r| {
+        plain(r.sample.gpu_utilization.map(f64::from))
+    });
+    gauge(
+        "zelos_gpu_memory_utilization_percent",
+        "GPU memory controller utilization",
+        &|r| plain(r.sample.memory_utilization.map(f64::from)),
+    );
+    gauge(
+        "zelos_gpu_encoder_utilization_percent",
+        "GPU video encoder (NVENC) utilization",
+        &|r| plain(r.sample.encoder_utilization.map(f64::from)),
+    );
+    gauge(
+        "zelos_gpu_decoder_utilization_percent",
+        "GPU video decoder (NVDEC) utilization",
+        &|r| plain(r.sample.decoder_utilization.map(f64::from)),
+    );
+    gauge("zelos_gpu_power_usage_watts", "GPU power draw", &|r| {
+        plain(r.sample.power_usage.map(|mw| mw as f64 / 1000.0))
+    });
+    gauge(
+        "zelos_gpu_power_limit_watts",
+        "Enforced GPU power limit",
+        &|r| plain(r.power_limit.map(|mw| mw as f64 / 1000.0)),
+    );
+    gauge("zelos_gpu_clock_mhz", "Current GPU clock", &|r| {
+        [
+            ("graphics", r.sample.graphics_clock),
+            ("memory", r.sample.memory_clock),
+        ]
+        .into_iter()
+        .filter_map(|(clock, value)| Some((format!(",clock=\"{}\"", clock), value? as f64)))
+        .collect()
+    });
+    gauge("zelos_gpu_clock_offset_mhz", "GPU clock offset", &|r| {
+        [("graphics", r.freq_offset), ("memory", r.mem_offset)]
+            .into_iter()
+            .filter_map(|(clock, value)| Some((format!(",clock=\"{}\"", clock), value? as f64)))
+            .collect()
+    });
+    gauge("zelos_gpu_fan_speed_percent", "GPU fan speed", &|r| {
+        r.sample
+            .fan_speeds
+            .iter()
+            .enumerate()
+            .filter_map(|(fan, speed)| Some((format!(",fan=\"{}\"", fan), (*speed)? as f64)))
+            .collect()
+    });
+
+    out
+}
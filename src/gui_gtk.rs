@@ -11,7 +11,7 @@ pub mod imp {
 
     fn build_command(gpu_index: &str, power: i32, freq: i32, mem: i32, min_clock: i32, max_clock: i32) -> String {
         let prog = std::env::current_exe().map(|p| p.display().to_string()).unwrap_or_else(|_| "zelos".to_string());
-        format!("{} set --index {} --power-limit {} --freq-offset {} --mem-offset {} --min-clock {} --max-clock {}", prog, gpu_index, power, freq, mem, min_clock, max_clock)
+        format!("{} set --yes --index {} --power-limit {} --freq-offset {} --mem-offset {} --min-clock {} --max-clock {}", prog, gpu_index, power, freq, mem, min_clock, max_clock)
     }
 
     fn list_nvidia_gpus() -> Vec<(String, String)> {
@@ -0,0 +1,3480 @@
+//! Overclocking and monitoring of NVIDIA GPUs through NVML. The `zelos`
+//! binary is a command line front end to this library.
+
+use clap::Args;
+use log::{debug, error, info, warn};
+use nvml_raw::TargetTemperatureLimit;
+use nvml_wrapper::enum_wrappers::device::{Clock, PerformanceState, TemperatureThreshold};
+use nvml_wrapper::enums::device::FanControlPolicy;
+use nvml_wrapper::{error::NvmlError, Device, Nvml};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+pub mod benchmark;
+pub mod capture;
+pub mod compare;
+pub mod daemon;
+pub mod diff;
+pub mod exporter;
+pub mod monitor;
+pub mod nvml_raw;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod power_supply;
+pub mod status;
+pub mod stress;
+pub mod table;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod undo;
+pub mod watchdog;
+pub mod xid;
+
+/// Identifies a GPU by its NVML index, UUID (`GPU-...`) or PCI bus ID
+/// (`00000000:01:00.0`). Indices can change across reboots, the UUID and PCI
+/// bus ID do not.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GpuSelector {
+    Index(u32),
+    Uuid(String),
+    PciBusId(String),
+}
+
+impl GpuSelector {
+    /// Looks up the selected GPU.
+    pub fn device<'nvml>(&self, nvml: &'nvml Nvml) -> Result<Device<'nvml>, NvmlError> {
+        wait_for_driver(&format!("GPU {} lookup", self), || match self {
+            GpuSelector::Index(index) => retried("nvmlDeviceGetHandleByIndex", || {
+                nvml.device_by_index(*index)
+            }),
+            GpuSelector::Uuid(uuid) => retried("nvmlDeviceGetHandleByUUID", || {
+                nvml.device_by_uuid(uuid.as_str())
+            }),
+            GpuSelector::PciBusId(bus_id) => retried("nvmlDeviceGetHandleByPciBusId", || {
+                nvml.device_by_pci_bus_id(bus_id.as_str())
+            }),
+        })
+    }
+}
+
+impl FromStr for GpuSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(index) = s.parse() {
+            Ok(GpuSelector::Index(index))
+        } else if s.len() > 4 && s.get(..4).is_some_and(|p| p.eq_ignore_ascii_case("GPU-")) {
+            Ok(GpuSelector::Uuid(s.to_string()))
+        } else if s.contains(':') {
+            Ok(GpuSelector::PciBusId(s.to_string()))
+        } else {
+            Err(format!(
+                "`{}` is not a GPU index, UUID (GPU-...) or PCI bus ID (0000:01:00.0)",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for GpuSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuSelector::Index(index) => write!(f, "{}", index),
+            GpuSelector::Uuid(id) | GpuSelector::PciBusId(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for GpuSelector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = GpuSelector;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a GPU index, UUID or PCI bus ID")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<GpuSelector, E> {
+                u32::try_from(v)
+                    .map(GpuSelector::Index)
+                    .map_err(|_| E::custom(format!("GPU index {} is out of range", v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<GpuSelector, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl Serialize for GpuSelector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// One or more GPUs, given as a comma-separated list of selectors where
+/// indices may also be ranges (`0,2,3`, `0-3`, `GPU-...,1`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GpuSelectors(pub Vec<GpuSelector>);
+
+impl FromStr for GpuSelectors {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut selectors = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let range = part.split_once('-').and_then(|(first, last)| {
+                Some((
+                    first.trim().parse::<u32>().ok()?,
+                    last.trim().parse::<u32>().ok()?,
+                ))
+            });
+            match range {
+                Some((first, last)) if first > last => {
+                    return Err(format!("GPU index range `{}` ends before it starts", part))
+                }
+                Some((first, last)) => selectors.extend((first..=last).map(GpuSelector::Index)),
+                None => selectors.push(part.parse()?),
+            }
+        }
+        let mut unique = Vec::with_capacity(selectors.len());
+        for selector in selectors {
+            if !unique.contains(&selector) {
+                unique.push(selector);
+            }
+        }
+        Ok(GpuSelectors(unique))
+    }
+}
+
+/// The compute modes that can be set. NVML's exclusive-thread mode is no
+/// longer supported by the driver and is left out.
+#[derive(Clone, Copy, Debug, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ComputeMode {
+    /// Any number of processes may use the GPU
+    Default,
+    /// Only one process at a time may use the GPU
+    ExclusiveProcess,
+    /// No process may use the GPU for compute
+    Prohibited,
+}
+
+impl fmt::Display for ComputeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComputeMode::Default => write!(f, "default"),
+            ComputeMode::ExclusiveProcess => write!(f, "exclusive-process"),
+            ComputeMode::Prohibited => write!(f, "prohibited"),
+        }
+    }
+}
+
+/// Who controls a fan's speed.
+#[derive(Clone, Copy, Debug, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FanPolicy {
+    /// The driver adjusts the speed to the temperature
+    Automatic,
+    /// The fan keeps the speed it was last set to
+    Manual,
+}
+
+impl fmt::Display for FanPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanPolicy::Automatic => write!(f, "automatic"),
+            FanPolicy::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+impl From<FanPolicy> for FanControlPolicy {
+    fn from(policy: FanPolicy) -> Self {
+        match policy {
+            FanPolicy::Automatic => Self::TemperatureContinousSw,
+            FanPolicy::Manual => Self::Manual,
+        }
+    }
+}
+
+impl From<ComputeMode> for nvml_wrapper::enum_wrappers::device::ComputeMode {
+    fn from(mode: ComputeMode) -> Self {
+        match mode {
+            ComputeMode::Default => Self::Default,
+            ComputeMode::ExclusiveProcess => Self::ExclusiveProcess,
+            ComputeMode::Prohibited => Self::Prohibited,
+        }
+    }
+}
+
+/// A power limit in milliwatts, or as a percentage of the GPU's maximum power
+/// limit (`80%`), which carries over between cards.
+#[derive(Clone, Copy, Debug)]
+pub enum PowerLimit {
+    Milliwatts(u32),
+    Percent(u32),
+}
+
+impl PowerLimit {
+    /// The limit in milliwatts for `device`.
+    pub fn milliwatts(&self, device: &Device) -> Result<u32, NvmlError> {
+        match *self {
+            PowerLimit::Milliwatts(limit) => Ok(limit),
+            PowerLimit::Percent(percent) => {
+                let max = device.power_management_limit_constraints()?.max_limit;
+                Ok((max as u64 * percent as u64 / 100) as u32)
+            }
+        }
+    }
+
+    /// Rejects a limit no GPU could run at: 0 mW, or a percentage of the
+    /// maximum outside 1-100%.
+    fn checked(self) -> Result<PowerLimit, String> {
+        match self {
+            PowerLimit::Milliwatts(0) => Err("power limit must be above 0 mW".to_string()),
+            PowerLimit::Percent(percent) if !(1..=100).contains(&percent) => Err(format!(
+                "power limit {}% must be between 1% and 100% of the maximum",
+                percent
+            )),
+            limit => Ok(limit),
+        }
+    }
+}
+
+impl FromStr for PowerLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with('-') {
+            return Err(format!("power limit `{}` must not be negative", s));
+        }
+        match s.strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse()
+                .map(PowerLimit::Percent)
+                .map_err(|_| format!("invalid power limit percentage `{}`", s)),
+            None => s
+                .parse()
+                .map(PowerLimit::Milliwatts)
+                .map_err(|_| format!("`{}` is not a power limit in mW or a percentage", s)),
+        }?
+        .checked()
+    }
+}
+
+impl fmt::Display for PowerLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerLimit::Milliwatts(limit) => write!(f, "{} mW", limit),
+            PowerLimit::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PowerLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = PowerLimit;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a power limit in milliwatts or a percentage like \"80%\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<PowerLimit, E> {
+                u32::try_from(v)
+                    .map_err(|_| format!("power limit {} mW is out of range", v))
+                    .and_then(|v| PowerLimit::Milliwatts(v).checked())
+                    .map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<PowerLimit, E> {
+                u64::try_from(v)
+                    .map_err(|_| E::custom(format!("power limit {} mW is negative", v)))
+                    .and_then(|v| self.visit_u64(v))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<PowerLimit, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// A change to the current power limit in milliwatts, written with a `W` or
+/// `mW` suffix (`+20W`, `-500mW`).
+#[derive(Clone, Copy, Debug)]
+pub struct PowerDelta(pub i64);
+
+impl PowerDelta {
+    /// The limit in milliwatts the change results in for `device`, clamped to
+    /// its supported range.
+    pub fn milliwatts(&self, device: &Device) -> Result<u32, NvmlError> {
+        let current = retried("nvmlDeviceGetEnforcedPowerLimit", || {
+            device.enforced_power_limit()
+        })?;
+        let constraints = device.power_management_limit_constraints()?;
+        let limit = (current as i64 + self.0)
+            .clamp(constraints.min_limit as i64, constraints.max_limit as i64);
+        Ok(limit as u32)
+    }
+}
+
+impl FromStr for PowerDelta {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, scale) = if let Some(number) = s.strip_suffix("mW") {
+            (number, 1)
+        } else if let Some(number) = s.strip_suffix('W') {
+            (number, 1000)
+        } else {
+            (s, 1)
+        };
+        number
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .and_then(|number| number.checked_mul(scale))
+            .map(PowerDelta)
+            .ok_or_else(|| format!("`{}` is not a power change like `+20W` or `-500mW`", s))
+    }
+}
+
+impl fmt::Display for PowerDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:+} mW", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for PowerDelta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = PowerDelta;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a power change in milliwatts or a string like \"+20W\"")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<PowerDelta, E> {
+                Ok(PowerDelta(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<PowerDelta, E> {
+                i64::try_from(v)
+                    .map(PowerDelta)
+                    .map_err(|_| E::custom(format!("power change {} mW is out of range", v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<PowerDelta, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// A point of the GPU core clock curve to move: the core clock offset in MHz
+/// of one P-state, written as `PSTATE:OFFSET` (`0:150` or `P0:150`). NVML
+/// exposes the curve per P-state through `nvmlDeviceGetClockOffsets`, not
+/// per voltage.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct VfPoint {
+    pub pstate: u32,
+    /// Offset in MHz
+    pub offset: i32,
+}
+
+impl FromStr for VfPoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pstate, offset) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected PSTATE:OFFSET, got `{}`", s))?;
+        let pstate = pstate.trim();
+        let number = pstate
+            .strip_prefix(['P', 'p'])
+            .unwrap_or(pstate)
+            .parse()
+            .ok()
+            .filter(|number| *number <= 15)
+            .ok_or_else(|| format!("invalid P-state `{}`", pstate))?;
+        Ok(VfPoint {
+            pstate: number,
+            offset: offset
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid offset `{}`", offset))?,
+        })
+    }
+}
+
+/// The parameters to set on a GPU, from the command line or one GPU entry of
+/// the config file. Parameters left as `None` are not touched.
+#[derive(Args, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[group(required = true, multiple = true)]
+pub struct Sets {
+    /// GPU frequency offset
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub freq_offset: Option<i32>,
+    /// GPU memory frequency offset
+    #[arg(long, allow_hyphen_values = true)]
+    pub mem_offset: Option<i32>,
+    /// GPU power limit in milliwatts, or in percent of the maximum with a `%` suffix
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub power_limit: Option<PowerLimit>,
+    /// Change the current power limit by this much, e.g. `+20W` or `-500mW`,
+    /// clamped to the supported range
+    #[arg(long, allow_hyphen_values = true, conflicts_with = "power_limit")]
+    pub power_delta: Option<PowerDelta>,
+    /// GPU min clock
+    #[arg(long, requires = "max_clock")]
+    pub min_clock: Option<u32>,
+    /// GPU max clock
+    #[arg(long, requires = "min_clock")]
+    pub max_clock: Option<u32>,
+    /// Lock the GPU clock to the supported clock nearest to this one, in MHz
+    #[arg(long, conflicts_with_all = ["min_clock", "min_pstate", "reset_clocks"])]
+    pub lock_clock: Option<u32>,
+    /// Highest-performance P-state to allow (0 for P0)
+    #[arg(long, requires = "max_pstate", conflicts_with_all = ["min_clock", "max_clock"])]
+    pub min_pstate: Option<u32>,
+    /// Lowest-performance P-state to allow (e.g. 8 for P8)
+    #[arg(long, requires = "min_pstate")]
+    pub max_pstate: Option<u32>,
+    /// GPU min memory clock
+    #[arg(long, requires = "max_mem_clock")]
+    pub min_mem_clock: Option<u32>,
+    /// GPU max memory clock
+    #[arg(long, requires = "min_mem_clock")]
+    pub max_mem_clock: Option<u32>,
+    /// Unlock the GPU and memory clocks
+    #[arg(long, conflicts_with_all = ["min_clock", "min_pstate", "min_mem_clock"])]
+    #[serde(default)]
+    pub reset_clocks: bool,
+    /// GPU application clock, the core clock compute workloads run at on
+    /// datacenter GPUs (a supported pair with --app-mem-clock)
+    #[arg(long, requires = "app_mem_clock")]
+    pub app_clock: Option<u32>,
+    /// GPU application memory clock
+    #[arg(long, requires = "app_clock")]
+    pub app_mem_clock: Option<u32>,
+    /// Return the application clocks to their defaults
+    #[arg(long, conflicts_with = "app_clock")]
+    #[serde(default)]
+    pub reset_app_clocks: bool,
+    /// GPU fan speed in percent (0-100)
+    #[arg(long, group = "fan_control", allow_hyphen_values = true, value_parser = clap::value_parser!(u32).range(0..=100))]
+    pub fan_speed: Option<u32>,
+    /// Restore automatic fan control
+    #[arg(long, group = "fan_control", conflicts_with = "fan_speed")]
+    #[serde(default)]
+    pub fan_auto: bool,
+    /// Switch the fans between driver control and manual control, keeping
+    /// their current speed
+    #[arg(long, value_enum, group = "fan_control", conflicts_with_all = ["fan_speed", "fan_auto"])]
+    pub fan_policy: Option<FanPolicy>,
+    /// Fan to apply the fan settings to (defaults to all fans)
+    #[arg(long, requires = "fan_control")]
+    pub fan_index: Option<u32>,
+    /// Fan speeds in percent keyed by fan index, to drive each fan of a card
+    /// with asymmetric cooling on its own (config only)
+    #[arg(skip)]
+    #[serde(default, deserialize_with = "fan_keys")]
+    pub fan_speeds: Option<HashMap<u32, u32>>,
+    /// GPU core voltage offset in millivolts. Not supported and always
+    /// rejected, see `VOLTAGE_OFFSET_UNSUPPORTED`
+    #[arg(long, allow_hyphen_values = true, hide = true)]
+    pub voltage_offset: Option<i32>,
+    /// Core clock offset in MHz of one P-state of the clock curve, as
+    /// PSTATE:OFFSET (e.g. 0:150), may be repeated
+    #[arg(long = "vf-point", value_name = "PSTATE:OFFSET")]
+    pub vf_points: Option<Vec<VfPoint>>,
+    /// Which processes may create compute contexts on the GPU
+    #[arg(long, value_enum)]
+    pub compute_mode: Option<ComputeMode>,
+    /// Keep the driver initialized while no process uses the GPU
+    #[arg(long, value_name = "BOOL")]
+    pub persistence_mode: Option<bool>,
+    /// Lowest target temperature the driver may be set to, in °C
+    #[arg(long)]
+    pub target_temp_min: Option<u32>,
+    /// Highest target temperature the driver may be set to, in °C
+    #[arg(long)]
+    pub target_temp_max: Option<u32>,
+    /// Clear the target temperature, raising it to the highest allowed one
+    #[arg(long, conflicts_with_all = ["target_temp_min", "target_temp_max"])]
+    #[serde(default)]
+    pub reset_target_temp: bool,
+    /// Temperature in °C above which the GPU slows its clocks down, on GPUs
+    /// that permit changing it
+    #[arg(long)]
+    pub max_temp_limit: Option<u32>,
+    /// Core clock offsets in MHz keyed by P-state number, e.g. to boost P0
+    /// while leaving idle clocks alone (config only)
+    #[arg(skip)]
+    #[serde(default, deserialize_with = "pstate_keys")]
+    pub freq_offset_pstate: Option<HashMap<u32, i32>>,
+    /// Memory clock offsets in MHz keyed by P-state number, e.g. to offset
+    /// the memory in P0 only (config only)
+    #[arg(skip)]
+    #[serde(default, deserialize_with = "pstate_keys")]
+    pub mem_offset_pstate: Option<HashMap<u32, i32>>,
+    /// Fan curve as (temperature °C, fan speed %) points, driven by `daemon`
+    #[arg(skip)]
+    pub fan_curve: Option<Vec<(u32, u32)>>,
+    /// Core offset and power limit that follow GPU utilization, driven by
+    /// `daemon`
+    #[arg(skip)]
+    pub auto_oc: Option<daemon::AutoOc>,
+    /// Largest accepted core/memory offset magnitude in MHz (config only,
+    /// `--max-offset` on the CLI)
+    #[arg(skip)]
+    pub max_offset: Option<u32>,
+    /// Lowest power limit in milliwatts to accept, on top of the hardware
+    /// minimum (config only)
+    #[arg(skip)]
+    pub min_power_limit: Option<u32>,
+    /// Only apply the entry to a GPU whose name contains this, ignoring case,
+    /// or whose UUID (`GPU-...`) is exactly this (config only)
+    #[arg(skip)]
+    pub require_gpu: Option<String>,
+    /// Apply offsets beyond `max_offset` (config only, `--force` on the CLI)
+    #[arg(skip)]
+    pub force: Option<bool>,
+}
+
+/// Deserializes a map keyed by P-state number, written as `"0"` or `"P0"`
+/// since JSON and TOML keys are always strings. YAML keys may also be plain
+/// numbers.
+fn pstate_keys<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HashMap<u32, i32>>, D::Error> {
+    #[derive(Deserialize, PartialEq, Eq, Hash)]
+    #[serde(untagged)]
+    enum Key {
+        Number(u32),
+        Name(String),
+    }
+
+    let Some(map) = Option::<HashMap<Key, i32>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    map.into_iter()
+        .map(|(key, value)| match key {
+            Key::Number(number) => Ok((number, value)),
+            Key::Name(name) => name
+                .strip_prefix(['P', 'p'])
+                .unwrap_or(&name)
+                .parse()
+                .map(|number| (number, value))
+                .map_err(|_| de::Error::custom(format!("invalid P-state `{}`", name))),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Deserializes a map keyed by fan index, written as `"0"` since JSON and
+/// TOML keys are always strings. YAML keys may also be plain numbers.
+fn fan_keys<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HashMap<u32, u32>>, D::Error> {
+    #[derive(Deserialize, PartialEq, Eq, Hash)]
+    #[serde(untagged)]
+    enum Key {
+        Number(u32),
+        Name(String),
+    }
+
+    let Some(map) = Option::<HashMap<Key, u32>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    map.into_iter()
+        .map(|(key, speed)| match key {
+            Key::Number(fan) => Ok((fan, speed)),
+            Key::Name(name) => name
+                .trim()
+                .parse()
+                .map(|fan| (fan, speed))
+                .map_err(|_| de::Error::custom(format!("invalid fan index `{}`", name))),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Core and memory offsets larger than this (in MHz, either direction) are
+/// rejected unless forced, as they are almost certainly typos.
+pub const DEFAULT_MAX_OFFSET: u32 = 2000;
+
+/// Core and memory offsets above these (in MHz) ask for confirmation on a
+/// terminal, as they can crash the GPU.
+const RISKY_FREQ_OFFSET: i32 = 200;
+const RISKY_MEM_OFFSET: i32 = 1500;
+
+/// Reads a value back right after setting it and warns if it differs from
+/// `requested`, as drivers sometimes clamp a value without an error. Offsets
+/// are shown with a sign.
+fn verify<T: Copy + Into<i64>>(
+    name: &str,
+    requested: T,
+    unit: &str,
+    offset: bool,
+    read: impl FnOnce() -> Result<T, NvmlError>,
+) {
+    let format = |value: i64| match offset {
+        true => format!("{:+} {}", value, unit),
+        false => format!("{} {}", value, unit),
+    };
+    match read() {
+        Ok(actual) if actual.into() != requested.into() => warn!(
+            "{} is {} after requesting {}, the driver adjusted it",
+            name,
+            format(actual.into()),
+            format(requested.into())
+        ),
+        Ok(_) => {}
+        Err(e) => debug!("Failed to read back {}: {:?}", name, e),
+    }
+}
+
+/// Power limits within this many percent above the hardware minimum are
+/// warned about, as some cards become unstable or unresponsive there.
+const NEAR_MIN_POWER_LIMIT_PERCENT: u32 = 10;
+
+/// Warns if `limit` (in mW) is close to the hardware minimum of `device`.
+fn warn_near_min_power_limit(device: &Device, limit: u32) {
+    let Ok(constraints) = device.power_management_limit_constraints() else {
+        return;
+    };
+    let near = constraints.min_limit as u64 * (100 + NEAR_MIN_POWER_LIMIT_PERCENT) as u64 / 100;
+    if (limit as u64) < near {
+        warn!(
+            "GPU power limit {} mW is close to the hardware minimum {} mW, the GPU may become unstable",
+            limit, constraints.min_limit
+        );
+    }
+}
+
+/// Core clock offsets that put the boost clock more than this many percent
+/// above the card's maximum are warned about, as few cards are stable there.
+const PLAUSIBLE_BOOST_PERCENT: u32 = 15;
+
+/// The boost clock in MHz a core clock offset lands on: the card's maximum
+/// core clock `max` plus the offset.
+pub fn projected_boost_clock(max: u32, offset: i32) -> u32 {
+    max.saturating_add_signed(offset)
+}
+
+/// Warns if a core clock `offset` would put the boost clock of `device`
+/// implausibly far above its maximum.
+fn warn_projected_boost_clock(device: &Device, offset: i32) {
+    let Ok(max) = retried("nvmlDeviceGetMaxClockInfo", || {
+        device.max_clock_info(Clock::Graphics)
+    }) else {
+        return;
+    };
+    let projected = projected_boost_clock(max, offset);
+    if projected as u64 * 100 > max as u64 * (100 + PLAUSIBLE_BOOST_PERCENT) as u64 {
+        warn!(
+            "GPU core clock offset {:+} MHz puts the boost clock at {} MHz, more than {}% above the maximum of {} MHz, the GPU may become unstable",
+            offset, projected, PLAUSIBLE_BOOST_PERCENT, max
+        );
+    }
+}
+
+/// How far in MHz a `lock_clock` may be from the nearest supported clock
+/// before `validate_clocks` reports it, as it then likely is a typo.
+const LOCK_CLOCK_TOLERANCE: u32 = 100;
+
+/// The supported GPU clock in MHz nearest to `mhz`, across every supported
+/// memory clock.
+fn nearest_graphics_clock(device: &Device, mhz: u32) -> Result<u32, NvmlError> {
+    let mut nearest: Option<u32> = None;
+    let memory_clocks = retried("nvmlDeviceGetSupportedMemoryClocks", || {
+        device.supported_memory_clocks()
+    })?;
+    for memory in memory_clocks {
+        let clocks = retried("nvmlDeviceGetSupportedGraphicsClocks", || {
+            device.supported_graphics_clocks(memory)
+        })?;
+        for clock in clocks {
+            if nearest.is_none_or(|nearest| clock.abs_diff(mhz) < nearest.abs_diff(mhz)) {
+                nearest = Some(clock);
+            }
+        }
+    }
+    nearest.ok_or(NvmlError::NotSupported)
+}
+
+/// Checks that `device` has application clocks and that `memory` and
+/// `graphics`, in MHz, are a pair it supports. Checks NVML cannot answer are
+/// skipped, leaving the pair to the driver.
+fn check_app_clocks(device: &Device, memory: u32, graphics: u32) -> Result<(), ApplyError> {
+    if let Err(NvmlError::NotSupported) = retried("nvmlDeviceGetApplicationsClock", || {
+        device.applications_clock(Clock::Graphics)
+    }) {
+        return Err(ApplyError::AppClocks(NvmlError::NotSupported));
+    }
+    let Ok(memory_clocks) = retried("nvmlDeviceGetSupportedMemoryClocks", || {
+        device.supported_memory_clocks()
+    }) else {
+        return Ok(());
+    };
+    if !memory_clocks.contains(&memory) {
+        let supported: Vec<String> = memory_clocks.iter().map(ToString::to_string).collect();
+        return Err(ApplyError::InvalidAppClocks(format!(
+            "memory clock {} MHz is not supported, the GPU supports {} MHz",
+            memory,
+            supported.join(", ")
+        )));
+    }
+    let Ok(graphics_clocks) = retried("nvmlDeviceGetSupportedGraphicsClocks", || {
+        device.supported_graphics_clocks(memory)
+    }) else {
+        return Ok(());
+    };
+    match graphics_clocks
+        .iter()
+        .min_by_key(|clock| clock.abs_diff(graphics))
+    {
+        Some(nearest) if *nearest != graphics => Err(ApplyError::InvalidAppClocks(format!(
+            "core clock {} MHz is not supported with {} MHz memory, the nearest is {} MHz \
+             (`get --supported-clocks` lists them all)",
+            graphics, memory, nearest
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Steps for moving the core clock offset gradually, as a borderline value
+/// applied in one jump can hang the GPU instantly.
+#[derive(Clone, Copy, Debug)]
+pub struct Ramp {
+    /// Offset change per step in MHz
+    pub step: u32,
+    /// Time between steps
+    pub delay: std::time::Duration,
+}
+
+impl Ramp {
+    /// Moves the core clock offset of `device` to `target` one step at a
+    /// time. If a step fails, the offset is set back to where it started.
+    pub fn freq_offset(&self, device: &mut Device, target: i32) -> Result<(), NvmlError> {
+        let start = retried("nvmlDeviceGetGpcClkVfOffset", || {
+            device.gpc_clock_vf_offset()
+        })?;
+        info!(
+            "Ramping GPU core clock offset from {:+} to {:+} MHz in steps of {} MHz...",
+            start, target, self.step
+        );
+
+        let mut offset = start;
+        while offset != target {
+            offset = if target > offset {
+                offset.saturating_add_unsigned(self.step).min(target)
+            } else {
+                offset.saturating_sub_unsigned(self.step).max(target)
+            };
+            if let Err(e) = retried("nvmlDeviceSetGpcClkVfOffset", || {
+                device.set_gpc_clock_vf_offset(offset)
+            }) {
+                warn!(
+                    "Failed to set GPU core clock offset {:+} MHz, backing off to {:+} MHz",
+                    offset, start
+                );
+                if let Err(e) = device.set_gpc_clock_vf_offset(start) {
+                    error!("Failed to restore GPU core clock offset: {:?}", e);
+                }
+                return Err(e);
+            }
+            if offset != target {
+                std::thread::sleep(self.delay);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A failure while applying one of the parameters in `Sets`. Variants
+/// holding an `NvmlError` are failed NVML calls; the others are values
+/// rejected by validation before anything was changed.
+#[derive(Debug)]
+pub enum ApplyError {
+    /// Setting the core clock offset failed
+    FreqOffset(NvmlError),
+    /// Setting the memory clock offset failed
+    MemOffset(NvmlError),
+    /// Reading or setting the power limit failed
+    PowerLimit(NvmlError),
+    /// Locking the core clocks set by the config key failed
+    Clocks(&'static str, NvmlError),
+    /// Locking the memory clocks failed
+    MemClocks(NvmlError),
+    /// Setting a fan speed failed
+    FanSpeed(NvmlError),
+    /// A fan speed above 100%
+    InvalidFanSpeed(u32),
+    /// Per-fan speeds for a fan the GPU lacks or mixed with other fan settings
+    InvalidFanSpeeds(String),
+    /// Restoring automatic fan control failed
+    FanAuto(NvmlError),
+    /// Setting a fan control policy failed
+    FanPolicy(NvmlError),
+    /// A fan curve that `daemon::validate_fan_curve` rejects
+    InvalidFanCurve(String),
+    /// Auto overclocking settings that `daemon::AutoOc::validate` rejects
+    InvalidAutoOc(String),
+    /// An offset beyond the safety limit without `force`
+    OffsetTooLarge {
+        parameter: &'static str,
+        /// The config key the offset was set with
+        key: &'static str,
+        /// Offset in MHz
+        offset: i32,
+        /// Largest accepted magnitude in MHz
+        limit: u32,
+    },
+    /// An offset outside the range the driver accepts for this GPU, all in MHz
+    OffsetOutOfRange {
+        parameter: &'static str,
+        /// The config key the offset was set with
+        key: &'static str,
+        offset: i32,
+        min: i32,
+        max: i32,
+    },
+    /// A power limit outside the range the GPU supports, all in mW
+    PowerLimitOutOfRange {
+        limit: u32,
+        min: u32,
+        max: u32,
+        /// The configured `min_power_limit`
+        floor: Option<u32>,
+    },
+    /// A power limit below the configured `min_power_limit`, all in mW
+    PowerLimitBelowFloor {
+        limit: u32,
+        floor: u32,
+        /// The hardware minimum, if it could be read
+        min: Option<u32>,
+    },
+    /// Locking the performance states failed
+    Pstates(NvmlError),
+    /// A performance state range the GPU does not support
+    InvalidPstates(String),
+    /// Reading a current value for `apply_atomic` to restore failed
+    Snapshot {
+        parameter: &'static str,
+        error: String,
+    },
+    /// Setting the core clock offset of a performance state failed
+    PstateFreqOffset(u32, NvmlError),
+    /// Setting the memory clock offset of a performance state failed
+    PstateMemOffset(u32, NvmlError),
+    /// A locked clock range, set by the config key, the GPU does not support
+    InvalidClocks(&'static str, String),
+    /// Setting the compute mode failed
+    ComputeMode(NvmlError),
+    /// Setting persistence mode failed
+    PersistenceMode(NvmlError),
+    /// Clock changes were requested on a GPU in MIG mode
+    MigEnabled,
+    /// Unlocking the GPU or memory clocks failed
+    ResetClocks(NvmlError),
+    /// Setting or resetting the application clocks failed
+    AppClocks(NvmlError),
+    /// Application clocks that are not a supported pair
+    InvalidAppClocks(String),
+    /// Setting the target temperature limit of the config key failed
+    TargetTemperature(&'static str, String),
+    /// A target temperature range whose minimum is above its maximum
+    InvalidTargetTemperature(String),
+    /// Setting the GPU max temperature failed
+    MaxTemperature(String),
+    /// A GPU max temperature above the slowdown temperature, both in °C
+    InvalidMaxTemperature { celsius: u32, slowdown: u32 },
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::FreqOffset(e) => write!(f, "Failed to set GPU frequency offset: {}", e),
+            ApplyError::MemOffset(e) => {
+                write!(f, "Failed to set GPU memory frequency offset: {}", e)
+            }
+            ApplyError::PowerLimit(e) => write!(f, "Failed to set GPU power limit: {}", e),
+            ApplyError::Clocks(_, e) => write!(f, "Failed to set GPU min and max clocks: {}", e),
+            ApplyError::MemClocks(e) => {
+                write!(f, "Failed to set GPU min and max memory clocks: {}", e)
+            }
+            ApplyError::ResetClocks(e) => write!(f, "Failed to reset GPU locked clocks: {}", e),
+            ApplyError::AppClocks(NvmlError::NotSupported) => write!(
+                f,
+                "GPU application clocks are not supported on this GPU, only on datacenter and \
+                 some workstation GPUs (lock the clocks with --lock-clock instead)"
+            ),
+            ApplyError::AppClocks(e) => write!(f, "Failed to set GPU application clocks: {}", e),
+            ApplyError::InvalidAppClocks(e) => write!(f, "Invalid application clocks: {}", e),
+            ApplyError::FanSpeed(e) => write!(f, "Failed to set GPU fan speed: {}", e),
+            ApplyError::InvalidFanSpeed(speed) => {
+                write!(f, "Invalid fan speed {}%: must be between 0 and 100", speed)
+            }
+            ApplyError::InvalidFanSpeeds(e) => write!(f, "Invalid fan speeds: {}", e),
+            ApplyError::FanAuto(e) => write!(f, "Failed to restore automatic fan control: {}", e),
+            ApplyError::FanPolicy(NvmlError::FailedToLoadSymbol(_)) => write!(
+                f,
+                "Failed to set GPU fan control policy: not supported by this driver"
+            ),
+            ApplyError::FanPolicy(e) => write!(f, "Failed to set GPU fan control policy: {}", e),
+            ApplyError::InvalidFanCurve(e) => write!(f, "Invalid fan curve: {}", e),
+            ApplyError::InvalidAutoOc(e) => write!(f, "Invalid auto overclocking settings: {}", e),
+            ApplyError::OffsetTooLarge {
+                parameter,
+                offset,
+                limit,
+                ..
+            } => write!(
+                f,
+                "{} {:+} MHz exceeds the safety limit of ±{} MHz (use --force to apply it anyway)",
+                parameter, offset, limit
+            ),
+            ApplyError::OffsetOutOfRange {
+                parameter,
+                offset,
+                min,
+                max,
+                ..
+            } if offset > max => write!(
+                f,
+                "{} {:+} MHz exceeds max {:+} MHz for this GPU",
+                parameter, offset, max
+            ),
+            ApplyError::OffsetOutOfRange {
+                parameter,
+                offset,
+                min,
+                ..
+            } => write!(
+                f,
+                "{} {:+} MHz is below min {:+} MHz for this GPU",
+                parameter, offset, min
+            ),
+            ApplyError::PowerLimitOutOfRange {
+                limit,
+                min,
+                max,
+                floor,
+            } => {
+                write!(
+                    f,
+                    "GPU power limit {} mW is outside the supported range {}-{} mW",
+                    limit, min, max
+                )?;
+                match floor {
+                    Some(floor) => write!(f, " (configured floor {} mW)", floor),
+                    None => Ok(()),
+                }
+            }
+            ApplyError::PowerLimitBelowFloor { limit, floor, min } => {
+                write!(
+                    f,
+                    "GPU power limit {} mW is below the configured floor {} mW",
+                    limit, floor
+                )?;
+                match min {
+                    Some(min) => write!(f, " (hardware minimum {} mW)", min),
+                    None => Ok(()),
+                }
+            }
+            ApplyError::Pstates(e) => write!(f, "Failed to lock GPU performance states: {}", e),
+            ApplyError::InvalidPstates(e) => write!(f, "Invalid performance state range: {}", e),
+            ApplyError::PstateFreqOffset(pstate, e) => write!(
+                f,
+                "Failed to set GPU frequency offset for P{}: {}",
+                pstate, e
+            ),
+            ApplyError::PstateMemOffset(pstate, e) => write!(
+                f,
+                "Failed to set GPU memory frequency offset for P{}: {}",
+                pstate, e
+            ),
+            ApplyError::InvalidClocks(_, e) => write!(f, "Invalid clock range: {}", e),
+            ApplyError::ComputeMode(e) => write!(f, "Failed to set GPU compute mode: {}", e),
+            ApplyError::PersistenceMode(e) => {
+                write!(f, "Failed to set GPU persistence mode: {}", e)
+            }
+            ApplyError::MigEnabled => write!(
+                f,
+                "MIG mode is enabled, which does not allow changing clock offsets or locking \
+                 clocks (disable it with `nvidia-smi -mig 0` first)"
+            ),
+            ApplyError::TargetTemperature(_, e) => {
+                write!(f, "Failed to set GPU target temperature range: {}", e)
+            }
+            ApplyError::InvalidTargetTemperature(e) => {
+                write!(f, "Invalid target temperature range: {}", e)
+            }
+            ApplyError::MaxTemperature(e) => write!(f, "Failed to set GPU max temperature: {}", e),
+            ApplyError::InvalidMaxTemperature { celsius, slowdown } => write!(
+                f,
+                "Invalid max temperature {} °C: above the GPU's slowdown temperature of {} °C",
+                celsius, slowdown
+            ),
+            ApplyError::Snapshot { parameter, error } => write!(
+                f,
+                "Failed to read the current {} to restore on failure: {}",
+                parameter, error
+            ),
+        }
+    }
+}
+
+impl ApplyError {
+    /// Whether NVML refused the change for lack of privileges.
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(
+            std::error::Error::source(self).and_then(|e| e.downcast_ref::<NvmlError>()),
+            Some(NvmlError::NoPermission)
+        )
+    }
+
+    /// A stable name for the kind of error, for scripts reading `--json`
+    /// output: `Permission`, `OutOfRange`, `Invalid`, `MigEnabled`,
+    /// `Snapshot` or `Nvml`.
+    pub fn code(&self) -> &'static str {
+        if self.is_permission_denied() {
+            return "Permission";
+        }
+        match self {
+            ApplyError::InvalidFanSpeed(_)
+            | ApplyError::OffsetTooLarge { .. }
+            | ApplyError::OffsetOutOfRange { .. }
+            | ApplyError::PowerLimitOutOfRange { .. }
+            | ApplyError::PowerLimitBelowFloor { .. }
+            | ApplyError::InvalidMaxTemperature { .. } => "OutOfRange",
+            ApplyError::InvalidFanSpeeds(_)
+            | ApplyError::InvalidFanCurve(_)
+            | ApplyError::InvalidAutoOc(_)
+            | ApplyError::InvalidPstates(_)
+            | ApplyError::InvalidClocks(..)
+            | ApplyError::InvalidAppClocks(_)
+            | ApplyError::InvalidTargetTemperature(_) => "Invalid",
+            ApplyError::MigEnabled => "MigEnabled",
+            ApplyError::Snapshot { .. } => "Snapshot",
+            ApplyError::FreqOffset(_)
+            | ApplyError::MemOffset(_)
+            | ApplyError::PowerLimit(_)
+            | ApplyError::Clocks(..)
+            | ApplyError::MemClocks(_)
+            | ApplyError::ResetClocks(_)
+            | ApplyError::AppClocks(_)
+            | ApplyError::FanSpeed(_)
+            | ApplyError::FanAuto(_)
+            | ApplyError::FanPolicy(_)
+            | ApplyError::Pstates(_)
+            | ApplyError::PstateFreqOffset(..)
+            | ApplyError::PstateMemOffset(..)
+            | ApplyError::ComputeMode(_)
+            | ApplyError::PersistenceMode(_)
+            | ApplyError::TargetTemperature(..)
+            | ApplyError::MaxTemperature(_) => "Nvml",
+        }
+    }
+
+    /// The config file key of the parameter the error is about, `None` for
+    /// errors about several parameters or none in particular.
+    pub fn parameter(&self) -> Option<&'static str> {
+        match self {
+            ApplyError::FreqOffset(_) => Some("freqOffset"),
+            ApplyError::MemOffset(_) => Some("memOffset"),
+            ApplyError::PowerLimit(_)
+            | ApplyError::PowerLimitOutOfRange { .. }
+            | ApplyError::PowerLimitBelowFloor { .. } => Some("powerLimit"),
+            ApplyError::Clocks(key, _) | ApplyError::InvalidClocks(key, _) => Some(key),
+            ApplyError::MemClocks(_) => Some("minMemClock"),
+            ApplyError::ResetClocks(_) => Some("resetClocks"),
+            ApplyError::AppClocks(_) | ApplyError::InvalidAppClocks(_) => Some("appClock"),
+            ApplyError::FanSpeed(_) | ApplyError::InvalidFanSpeed(_) => Some("fanSpeed"),
+            ApplyError::InvalidFanSpeeds(_) => Some("fanSpeeds"),
+            ApplyError::FanAuto(_) => Some("fanAuto"),
+            ApplyError::FanPolicy(_) => Some("fanPolicy"),
+            ApplyError::InvalidFanCurve(_) => Some("fanCurve"),
+            ApplyError::InvalidAutoOc(_) => Some("autoOc"),
+            ApplyError::OffsetTooLarge { key, .. } | ApplyError::OffsetOutOfRange { key, .. } => {
+                Some(key)
+            }
+            ApplyError::Pstates(_) | ApplyError::InvalidPstates(_) => Some("minPstate"),
+            ApplyError::PstateFreqOffset(..) => Some("freqOffsetPstate"),
+            ApplyError::PstateMemOffset(..) => Some("memOffsetPstate"),
+            ApplyError::ComputeMode(_) => Some("computeMode"),
+            ApplyError::PersistenceMode(_) => Some("persistenceMode"),
+            ApplyError::MaxTemperature(_) | ApplyError::InvalidMaxTemperature { .. } => {
+                Some("maxTempLimit")
+            }
+            ApplyError::TargetTemperature(key, _) => Some(key),
+            ApplyError::InvalidTargetTemperature(_) => Some("targetTempMin"),
+            ApplyError::MigEnabled | ApplyError::Snapshot { .. } => None,
+        }
+    }
+
+    /// Whether validation rejected the value before anything was changed,
+    /// rather than the driver failing to apply it.
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            ApplyError::InvalidFanSpeed(_)
+                | ApplyError::InvalidFanSpeeds(_)
+                | ApplyError::InvalidFanCurve(_)
+                | ApplyError::InvalidAutoOc(_)
+                | ApplyError::OffsetTooLarge { .. }
+                | ApplyError::OffsetOutOfRange { .. }
+                | ApplyError::PowerLimitOutOfRange { .. }
+                | ApplyError::PowerLimitBelowFloor { .. }
+                | ApplyError::InvalidPstates(_)
+                | ApplyError::InvalidClocks(..)
+                | ApplyError::InvalidAppClocks(_)
+                | ApplyError::MigEnabled
+                | ApplyError::InvalidTargetTemperature(_)
+                | ApplyError::InvalidMaxTemperature { .. }
+        )
+    }
+}
+
+impl std::error::Error for ApplyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApplyError::FreqOffset(e)
+            | ApplyError::MemOffset(e)
+            | ApplyError::PowerLimit(e)
+            | ApplyError::Clocks(_, e)
+            | ApplyError::MemClocks(e)
+            | ApplyError::ResetClocks(e)
+            | ApplyError::AppClocks(e)
+            | ApplyError::FanSpeed(e)
+            | ApplyError::FanAuto(e)
+            | ApplyError::FanPolicy(e)
+            | ApplyError::Pstates(e)
+            | ApplyError::PstateFreqOffset(_, e)
+            | ApplyError::PstateMemOffset(_, e)
+            | ApplyError::ComputeMode(e)
+            | ApplyError::PersistenceMode(e) => Some(e),
+            ApplyError::InvalidFanSpeed(_)
+            | ApplyError::InvalidFanSpeeds(_)
+            | ApplyError::InvalidFanCurve(_)
+            | ApplyError::InvalidAutoOc(_)
+            | ApplyError::OffsetTooLarge { .. }
+            | ApplyError::OffsetOutOfRange { .. }
+            | ApplyError::PowerLimitOutOfRange { .. }
+            | ApplyError::PowerLimitBelowFloor { .. }
+            | ApplyError::InvalidPstates(_)
+            | ApplyError::InvalidClocks(..)
+            | ApplyError::InvalidAppClocks(_)
+            | ApplyError::MigEnabled
+            | ApplyError::TargetTemperature(..)
+            | ApplyError::InvalidTargetTemperature(_)
+            | ApplyError::MaxTemperature(_)
+            | ApplyError::InvalidMaxTemperature { .. }
+            | ApplyError::Snapshot { .. } => None,
+        }
+    }
+}
+
+impl Sets {
+    /// Applies the settings to `device`. Nothing is changed if any value
+    /// fails validation; `force` allows offsets beyond the safety limit.
+    pub fn apply(&self, device: &mut Device, force: bool) -> Result<(), ApplyError> {
+        self.apply_with_ramp(device, force, None)
+    }
+
+    /// Like `apply`, but moves the core clock offset in steps when `ramp` is
+    /// given.
+    pub fn apply_with_ramp(
+        &self,
+        device: &mut Device,
+        force: bool,
+        ramp: Option<Ramp>,
+    ) -> Result<(), ApplyError> {
+        if let Some(problem) = self.validate(device, force).into_iter().next() {
+            return Err(problem);
+        }
+        self.apply_validated(device, ramp)
+    }
+
+    /// Applies the settings once `validate` has found nothing wrong with
+    /// them.
+    fn apply_validated(&self, device: &mut Device, ramp: Option<Ramp>) -> Result<(), ApplyError> {
+        // The offsets are applied before the locked clocks, so the locked
+        // range always has the last word on how fast the core may run.
+        let offsets_core = self.freq_offset.is_some() || !self.pstate_freq_offsets().is_empty();
+        let locks_core = (self.min_clock.is_some() && self.max_clock.is_some())
+            || (self.min_pstate.is_some() && self.max_pstate.is_some())
+            || self.lock_clock.is_some();
+        if offsets_core && locks_core {
+            warn!("Both a core clock offset and locked clocks are set. The offset shifts the voltage/frequency curve, but the locked clocks bound the resulting core clock, so the offset cannot raise it past the locked maximum.");
+        }
+        let mut locked_core = None;
+
+        if let Some(freq_offset) = self.freq_offset {
+            warn_projected_boost_clock(device, freq_offset);
+            match ramp {
+                Some(ramp) => ramp.freq_offset(device, freq_offset),
+                None => retried("nvmlDeviceSetGpcClkVfOffset", || {
+                    device.set_gpc_clock_vf_offset(freq_offset)
+                }),
+            }
+            .map_err(ApplyError::FreqOffset)?;
+            verify("GPU core clock offset", freq_offset, "MHz", true, || {
+                device.gpc_clock_vf_offset()
+            });
+        }
+
+        for (number, offset) in self.pstate_freq_offsets() {
+            let result = retried("nvmlDeviceSetClockOffsets", || {
+                pstate(number)
+                    .and_then(|state| device.set_clock_offset(Clock::Graphics, state, offset))
+            });
+            match result {
+                Err(NvmlError::FailedToLoadSymbol(_) | NvmlError::NotSupported) => {
+                    warn!("Per-P-state clock offsets are not supported by this driver, skipping");
+                    break;
+                }
+                result => result.map_err(|e| ApplyError::PstateFreqOffset(number, e))?,
+            }
+        }
+
+        if let Some(mem_offset) = self.mem_offset {
+            retried("nvmlDeviceSetMemClkVfOffset", || {
+                device.set_mem_clock_vf_offset(mem_offset)
+            })
+            .map_err(ApplyError::MemOffset)?;
+            verify("GPU memory clock offset", mem_offset, "MHz", true, || {
+                device.mem_clock_vf_offset()
+            });
+        }
+
+        for (number, offset) in self.pstate_mem_offsets() {
+            let result = retried("nvmlDeviceSetClockOffsets", || {
+                pstate(number)
+                    .and_then(|state| device.set_clock_offset(Clock::Memory, state, offset))
+            });
+            match result {
+                Err(NvmlError::FailedToLoadSymbol(_) | NvmlError::NotSupported)
+                    if self.mem_offset.is_none() =>
+                {
+                    // The offsets are ordered, so this is the one of the
+                    // highest-performance state.
+                    warn!(
+                        "Per-P-state clock offsets are not supported by this driver, applying the P{} memory offset {:+} MHz to every P-state",
+                        number, offset
+                    );
+                    retried("nvmlDeviceSetMemClkVfOffset", || {
+                        device.set_mem_clock_vf_offset(offset)
+                    })
+                    .map_err(ApplyError::MemOffset)?;
+                    break;
+                }
+                Err(NvmlError::FailedToLoadSymbol(_) | NvmlError::NotSupported) => {
+                    warn!("Per-P-state clock offsets are not supported by this driver, skipping");
+                    break;
+                }
+                result => result.map_err(|e| ApplyError::PstateMemOffset(number, e))?,
+            }
+        }
+
+        if let Some(limit) = self.power_limit {
+            let limit = limit.milliwatts(device).map_err(ApplyError::PowerLimit)?;
+            warn_near_min_power_limit(device, limit);
+            retried("nvmlDeviceSetPowerManagementLimit", || {
+                device.set_power_management_limit(limit)
+            })
+            .map_err(ApplyError::PowerLimit)?;
+            verify("GPU power limit", limit, "mW", false, || {
+                device.power_management_limit()
+            });
+        }
+
+        if let Some(delta) = self.power_delta {
+            let limit = delta.milliwatts(device).map_err(ApplyError::PowerLimit)?;
+            warn_near_min_power_limit(device, limit);
+            retried("nvmlDeviceSetPowerManagementLimit", || {
+                device.set_power_management_limit(limit)
+            })
+            .map_err(ApplyError::PowerLimit)?;
+            verify("GPU power limit", limit, "mW", false, || {
+                device.power_management_limit()
+            });
+            info!("Changed GPU power limit by {} to {} mW", delta, limit);
+        }
+
+        if self.reset_clocks {
+            retried("nvmlDeviceResetGpuLockedClocks", || {
+                device.reset_gpu_locked_clocks()
+            })
+            .map_err(ApplyError::ResetClocks)?;
+            retried("nvmlDeviceResetMemoryLockedClocks", || {
+                device.reset_mem_locked_clocks()
+            })
+            .map_err(ApplyError::ResetClocks)?;
+            info!("Unlocked GPU and memory clocks");
+        }
+
+        if let (Some(min_clock), Some(max_clock)) = (self.min_clock, self.max_clock) {
+            retried("nvmlDeviceSetGpuLockedClocks", || {
+                device.set_gpu_locked_clocks(
+                    nvml_wrapper::enums::device::GpuLockedClocksSetting::Numeric {
+                        min_clock_mhz: min_clock,
+                        max_clock_mhz: max_clock,
+                    },
+                )
+            })
+            .map_err(|e| ApplyError::Clocks("minClock", e))?;
+            locked_core = Some((min_clock, max_clock));
+        }
+
+        if let Some(requested) = self.lock_clock {
+            let clock = nearest_graphics_clock(device, requested)
+                .map_err(|e| ApplyError::Clocks("lockClock", e))?;
+            if clock != requested {
+                info!(
+                    "{} MHz is not a supported GPU clock, using the nearest one, {} MHz",
+                    requested, clock
+                );
+            }
+            retried("nvmlDeviceSetGpuLockedClocks", || {
+                device.set_gpu_locked_clocks(
+                    nvml_wrapper::enums::device::GpuLockedClocksSetting::Numeric {
+                        min_clock_mhz: clock,
+                        max_clock_mhz: clock,
+                    },
+                )
+            })
+            .map_err(|e| ApplyError::Clocks("lockClock", e))?;
+            info!("Locked GPU clock to {} MHz", clock);
+            locked_core = Some((clock, clock));
+        }
+
+        if let (Some(min_pstate), Some(max_pstate)) = (self.min_pstate, self.max_pstate) {
+            // Lock the clocks to the span from the slowest clock of the
+            // lowest-performance state to the fastest of the highest one.
+            let clocks_of = |number| {
+                retried("nvmlDeviceGetMinMaxClockOfPState", || {
+                    pstate(number)
+                        .and_then(|state| device.min_max_clock_of_pstate(Clock::Graphics, state))
+                })
+            };
+            let (min_clock, _) = clocks_of(max_pstate).map_err(ApplyError::Pstates)?;
+            let (_, max_clock) = clocks_of(min_pstate).map_err(ApplyError::Pstates)?;
+            retried("nvmlDeviceSetGpuLockedClocks", || {
+                device.set_gpu_locked_clocks(
+                    nvml_wrapper::enums::device::GpuLockedClocksSetting::Numeric {
+                        min_clock_mhz: min_clock,
+                        max_clock_mhz: max_clock,
+                    },
+                )
+            })
+            .map_err(ApplyError::Pstates)?;
+            locked_core = Some((min_clock, max_clock));
+        }
+
+        if let (true, Some((min_clock, max_clock))) = (offsets_core, locked_core) {
+            match retried("nvmlDeviceGetGpcClkVfOffset", || {
+                device.gpc_clock_vf_offset()
+            }) {
+                Ok(offset) => info!(
+                    "GPU core clock offset is {:+} MHz, core clock locked to {}-{} MHz",
+                    offset, min_clock, max_clock
+                ),
+                Err(_) => info!(
+                    "GPU core clock locked to {}-{} MHz on top of the offset",
+                    min_clock, max_clock
+                ),
+            }
+        }
+
+        if let (Some(min_mem_clock), Some(max_mem_clock)) = (self.min_mem_clock, self.max_mem_clock)
+        {
+            retried("nvmlDeviceSetMemoryLockedClocks", || {
+                device.set_mem_locked_clocks(min_mem_clock, max_mem_clock)
+            })
+            .map_err(ApplyError::MemClocks)?;
+        }
+
+        if self.reset_app_clocks {
+            retried("nvmlDeviceResetApplicationsClocks", || {
+                device.reset_applications_clocks()
+            })
+            .map_err(ApplyError::AppClocks)?;
+            info!("Reset GPU application clocks");
+        }
+
+        if let (Some(memory), Some(graphics)) = (self.app_mem_clock, self.app_clock) {
+            retried("nvmlDeviceSetApplicationsClocks", || {
+                device.set_applications_clocks(memory, graphics)
+            })
+            .map_err(ApplyError::AppClocks)?;
+            info!(
+                "Set GPU application clocks to {} MHz core, {} MHz memory",
+                graphics, memory
+            );
+        }
+
+        if let Some(speed) = self.fan_speed {
+            for fan in self.fans(device).map_err(ApplyError::FanSpeed)? {
+                retried("nvmlDeviceSetFanSpeed_v2", || {
+                    device.set_fan_speed(fan, speed)
+                })
+                .map_err(ApplyError::FanSpeed)?;
+            }
+        }
+
+        for (fan, speed) in self.sorted_fan_speeds() {
+            retried("nvmlDeviceSetFanSpeed_v2", || {
+                device.set_fan_speed(fan, speed)
+            })
+            .map_err(ApplyError::FanSpeed)?;
+        }
+
+        if self.fan_auto {
+            for fan in self.fans(device).map_err(ApplyError::FanAuto)? {
+                retried("nvmlDeviceSetDefaultFanSpeed_v2", || {
+                    device.set_default_fan_speed(fan)
+                })
+                .map_err(ApplyError::FanAuto)?;
+            }
+        }
+
+        if let Some(policy) = self.fan_policy {
+            for fan in self.fans(device).map_err(ApplyError::FanPolicy)? {
+                retried("nvmlDeviceSetFanControlPolicy", || {
+                    device.set_fan_control_policy(fan, policy.into())
+                })
+                .map_err(ApplyError::FanPolicy)?;
+            }
+        }
+
+        if let Some(mode) = self.compute_mode {
+            retried("nvmlDeviceSetComputeMode", || {
+                device.set_compute_mode(mode.into())
+            })
+            .map_err(ApplyError::ComputeMode)?;
+        }
+
+        if let Some(enabled) = self.persistence_mode {
+            retried("nvmlDeviceSetPersistenceMode", || {
+                device.set_persistent(enabled)
+            })
+            .map_err(ApplyError::PersistenceMode)?;
+        }
+
+        if self.target_temp_min.is_some() || self.target_temp_max.is_some() {
+            self.apply_target_temperature(device)?;
+        }
+
+        if self.reset_target_temp {
+            match reset_target_temperature(device)
+                .map_err(|e| ApplyError::TargetTemperature("resetTargetTemp", e))?
+            {
+                Some(celsius) => info!("Cleared GPU target temperature, now {} °C", celsius),
+                None => info!("GPU has no target temperature set"),
+            }
+        }
+
+        if let Some(celsius) = self.max_temp_limit {
+            nvml_raw::set_gpu_max_temperature(device, celsius)
+                .map_err(ApplyError::MaxTemperature)?;
+            verify("GPU max temperature", celsius, "°C", false, || {
+                device.temperature_threshold(TemperatureThreshold::GpuMax)
+            });
+            info!("Set GPU max temperature to {} °C", celsius);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the target temperature limits and reports the resulting range.
+    /// When the range moves up past the current maximum, the maximum is
+    /// raised first so the minimum never ends up above it.
+    fn apply_target_temperature(&self, device: &Device) -> Result<(), ApplyError> {
+        let current_max =
+            nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Max).ok();
+        let mut limits = [
+            (
+                TargetTemperatureLimit::Min,
+                "targetTempMin",
+                self.target_temp_min,
+            ),
+            (
+                TargetTemperatureLimit::Max,
+                "targetTempMax",
+                self.target_temp_max,
+            ),
+        ];
+        if self.target_temp_min > current_max {
+            limits.reverse();
+        }
+        for (limit, key, celsius) in limits {
+            if let Some(celsius) = celsius {
+                nvml_raw::set_target_temperature_limit(device, limit, celsius)
+                    .map_err(|e| ApplyError::TargetTemperature(key, e))?;
+            }
+        }
+
+        match (
+            nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Min),
+            nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Max),
+        ) {
+            (Ok(min), Ok(max)) => info!("GPU target temperature range is {}-{} °C", min, max),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Failed to read back GPU target temperature range: {}", e)
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `apply`, but if a parameter fails to apply the ones already
+    /// changed are restored to the values they had before. Fan settings are
+    /// not rolled back.
+    pub fn apply_atomic(
+        &self,
+        device: &mut Device,
+        force: bool,
+        ramp: Option<Ramp>,
+    ) -> Result<(), ApplyError> {
+        if let Some(problem) = self.validate(device, force).into_iter().next() {
+            return Err(problem);
+        }
+
+        let snapshot = self.snapshot(device)?;
+        self.apply_validated(device, ramp).inspect_err(|_| {
+            warn!("Restoring the previous GPU parameters...");
+            snapshot.restore(device);
+        })
+    }
+
+    /// Reads the current values of the parameters `apply` would change.
+    pub fn snapshot(&self, device: &Device) -> Result<Snapshot, ApplyError> {
+        fn read<T, E: fmt::Display>(
+            wanted: bool,
+            parameter: &'static str,
+            get: impl FnOnce() -> Result<T, E>,
+        ) -> Result<Option<T>, ApplyError> {
+            if !wanted {
+                return Ok(None);
+            }
+            get().map(Some).map_err(|e| ApplyError::Snapshot {
+                parameter,
+                error: e.to_string(),
+            })
+        }
+
+        let read_pstates = |clock: Clock, offsets: Vec<(u32, i32)>, parameter| {
+            let mut current = Vec::new();
+            for (number, _) in offsets {
+                match pstate(number).and_then(|state| device.clock_offset(clock, state)) {
+                    Ok(offset) => current.push((number, offset.clock_offset_mhz)),
+                    // `apply` skips these offsets as well.
+                    Err(NvmlError::FailedToLoadSymbol(_) | NvmlError::NotSupported) => break,
+                    Err(e) => {
+                        return Err(ApplyError::Snapshot {
+                            parameter,
+                            error: e.to_string(),
+                        })
+                    }
+                }
+            }
+            Ok(current)
+        };
+        let pstate_freq_offsets = read_pstates(
+            Clock::Graphics,
+            self.pstate_freq_offsets(),
+            "GPU P-state frequency offsets",
+        )?;
+        let pstate_mem_offsets = read_pstates(
+            Clock::Memory,
+            self.pstate_mem_offsets(),
+            "GPU P-state memory frequency offsets",
+        )?;
+
+        Ok(Snapshot {
+            freq_offset: read(self.freq_offset.is_some(), "GPU frequency offset", || {
+                device.gpc_clock_vf_offset()
+            })?,
+            pstate_freq_offsets,
+            pstate_mem_offsets,
+            // Also taken for P-state offsets, which may fall back to it.
+            mem_offset: read(
+                self.mem_offset.is_some() || self.mem_offset_pstate.is_some(),
+                "GPU memory frequency offset",
+                || device.mem_clock_vf_offset(),
+            )?,
+            power_limit: read(
+                self.power_limit.is_some() || self.power_delta.is_some(),
+                "GPU power limit",
+                || device.power_management_limit(),
+            )?,
+            clocks: self.min_clock.is_some()
+                || self.min_pstate.is_some()
+                || self.lock_clock.is_some(),
+            mem_clocks: self.min_mem_clock.is_some(),
+            app_clocks: read(
+                self.app_clock.is_some() || self.reset_app_clocks,
+                "GPU application clocks",
+                || {
+                    Ok::<_, NvmlError>((
+                        device.applications_clock(Clock::Memory)?,
+                        device.applications_clock(Clock::Graphics)?,
+                    ))
+                },
+            )?,
+            compute_mode: read(self.compute_mode.is_some(), "GPU compute mode", || {
+                device.compute_mode()
+            })?,
+            persistence_mode: read(
+                self.persistence_mode.is_some(),
+                "GPU persistence mode",
+                || device.is_in_persistent_mode(),
+            )?,
+            target_temp_min: read(
+                self.target_temp_min.is_some(),
+                "GPU minimum target temperature",
+                || nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Min),
+            )?,
+            target_temp_max: read(
+                self.target_temp_max.is_some(),
+                "GPU maximum target temperature",
+                || nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Max),
+            )?,
+            target_temp: read(self.reset_target_temp, "GPU target temperature", || {
+                nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Current)
+            })?,
+            max_temp_limit: read(self.max_temp_limit.is_some(), "GPU max temperature", || {
+                device.temperature_threshold(TemperatureThreshold::GpuMax)
+            })?,
+        })
+    }
+
+    /// Fills the parameters left unset from the config file's `defaults`.
+    /// Related parameters are inherited as a group, so an entry with a
+    /// `powerDelta` does not also get the default `powerLimit`, and one that
+    /// locks or unlocks any clocks gets none of the default clock locks.
+    fn inherit(&mut self, defaults: &Sets) {
+        fn fill<T: Clone>(value: &mut Option<T>, default: &Option<T>) {
+            if value.is_none() {
+                value.clone_from(default);
+            }
+        }
+
+        if self.power_limit.is_none() && self.power_delta.is_none() {
+            self.power_limit = defaults.power_limit;
+            self.power_delta = defaults.power_delta;
+        }
+        if self.min_clock.is_none()
+            && self.min_pstate.is_none()
+            && self.lock_clock.is_none()
+            && self.min_mem_clock.is_none()
+            && !self.reset_clocks
+        {
+            self.min_clock = defaults.min_clock;
+            self.max_clock = defaults.max_clock;
+            self.lock_clock = defaults.lock_clock;
+            self.min_pstate = defaults.min_pstate;
+            self.max_pstate = defaults.max_pstate;
+            self.min_mem_clock = defaults.min_mem_clock;
+            self.max_mem_clock = defaults.max_mem_clock;
+            self.reset_clocks = defaults.reset_clocks;
+        }
+        if self.app_clock.is_none() && self.app_mem_clock.is_none() && !self.reset_app_clocks {
+            self.app_clock = defaults.app_clock;
+            self.app_mem_clock = defaults.app_mem_clock;
+            self.reset_app_clocks = defaults.reset_app_clocks;
+        }
+        if self.fan_speed.is_none()
+            && !self.fan_auto
+            && self.fan_policy.is_none()
+            && self.fan_speeds.is_none()
+        {
+            self.fan_speed = defaults.fan_speed;
+            self.fan_auto = defaults.fan_auto;
+            self.fan_policy = defaults.fan_policy;
+            self.fan_index = defaults.fan_index;
+            self.fan_speeds.clone_from(&defaults.fan_speeds);
+        }
+        fill(&mut self.freq_offset, &defaults.freq_offset);
+        fill(&mut self.mem_offset, &defaults.mem_offset);
+        fill(&mut self.vf_points, &defaults.vf_points);
+        fill(&mut self.compute_mode, &defaults.compute_mode);
+        fill(&mut self.persistence_mode, &defaults.persistence_mode);
+        fill(&mut self.target_temp_min, &defaults.target_temp_min);
+        fill(&mut self.target_temp_max, &defaults.target_temp_max);
+        self.reset_target_temp |= defaults.reset_target_temp;
+        fill(&mut self.max_temp_limit, &defaults.max_temp_limit);
+        fill(&mut self.freq_offset_pstate, &defaults.freq_offset_pstate);
+        fill(&mut self.mem_offset_pstate, &defaults.mem_offset_pstate);
+        fill(&mut self.fan_curve, &defaults.fan_curve);
+        fill(&mut self.auto_oc, &defaults.auto_oc);
+        fill(&mut self.max_offset, &defaults.max_offset);
+        fill(&mut self.min_power_limit, &defaults.min_power_limit);
+        fill(&mut self.require_gpu, &defaults.require_gpu);
+        fill(&mut self.force, &defaults.force);
+    }
+
+    /// Checks that the parameters the command line only accepts together
+    /// are set together, as `apply` would silently ignore half a pair.
+    fn check_pairs(&self) -> Result<(), String> {
+        let fan_control = self.fan_speed.is_some() || self.fan_auto || self.fan_policy.is_some();
+        let pairs = [
+            ("minClock", self.min_clock, "maxClock", self.max_clock),
+            ("minPstate", self.min_pstate, "maxPstate", self.max_pstate),
+            (
+                "minMemClock",
+                self.min_mem_clock,
+                "maxMemClock",
+                self.max_mem_clock,
+            ),
+            (
+                "appClock",
+                self.app_clock,
+                "appMemClock",
+                self.app_mem_clock,
+            ),
+        ];
+        for (min_name, min, max_name, max) in pairs {
+            match (min, max) {
+                (Some(_), None) => return Err(format!("`{}` requires `{}`", min_name, max_name)),
+                (None, Some(_)) => return Err(format!("`{}` requires `{}`", max_name, min_name)),
+                _ => {}
+            }
+        }
+        if self.fan_index.is_some() && !fan_control {
+            return Err("`fanIndex` requires `fanSpeed`, `fanAuto` or `fanPolicy`".to_string());
+        }
+        Ok(())
+    }
+
+    /// The per-P-state core clock offsets, ordered by P-state. The points
+    /// given with `--vf-point` take precedence over `freqOffsetPstate`.
+    fn pstate_freq_offsets(&self) -> Vec<(u32, i32)> {
+        let mut offsets = self.freq_offset_pstate.clone().unwrap_or_default();
+        offsets.extend(
+            self.vf_points
+                .iter()
+                .flatten()
+                .map(|point| (point.pstate, point.offset)),
+        );
+        sorted_pstate_offsets(&Some(offsets))
+    }
+
+    /// The config file names of the parameters this entry sets, in the
+    /// order `apply` sets them.
+    pub fn parameters(&self) -> Vec<&'static str> {
+        [
+            ("freqOffset", self.freq_offset.is_some()),
+            ("freqOffsetPstate", self.freq_offset_pstate.is_some()),
+            ("memOffset", self.mem_offset.is_some()),
+            ("memOffsetPstate", self.mem_offset_pstate.is_some()),
+            ("vfPoints", self.vf_points.is_some()),
+            ("powerLimit", self.power_limit.is_some()),
+            ("powerDelta", self.power_delta.is_some()),
+            ("resetClocks", self.reset_clocks),
+            ("minClock", self.min_clock.is_some()),
+            ("maxClock", self.max_clock.is_some()),
+            ("lockClock", self.lock_clock.is_some()),
+            ("minPstate", self.min_pstate.is_some()),
+            ("maxPstate", self.max_pstate.is_some()),
+            ("minMemClock", self.min_mem_clock.is_some()),
+            ("maxMemClock", self.max_mem_clock.is_some()),
+            ("resetAppClocks", self.reset_app_clocks),
+            ("appClock", self.app_clock.is_some()),
+            ("appMemClock", self.app_mem_clock.is_some()),
+            ("fanSpeed", self.fan_speed.is_some()),
+            ("fanAuto", self.fan_auto),
+            ("fanPolicy", self.fan_policy.is_some()),
+            ("fanIndex", self.fan_index.is_some()),
+            ("fanSpeeds", self.fan_speeds.is_some()),
+            ("fanCurve", self.fan_curve.is_some()),
+            ("autoOc", self.auto_oc.is_some()),
+            ("computeMode", self.compute_mode.is_some()),
+            ("persistenceMode", self.persistence_mode.is_some()),
+            ("targetTempMin", self.target_temp_min.is_some()),
+            ("targetTempMax", self.target_temp_max.is_some()),
+            ("resetTargetTemp", self.reset_target_temp),
+            ("maxTempLimit", self.max_temp_limit.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect()
+    }
+
+    /// Checks `device` against `require_gpu`, describing the mismatch if it
+    /// is not the GPU the entry was written for.
+    pub fn check_gpu(&self, device: &Device) -> Result<(), String> {
+        let Some(required) = &self.require_gpu else {
+            return Ok(());
+        };
+        if required.len() > 4
+            && required
+                .get(..4)
+                .is_some_and(|p| p.eq_ignore_ascii_case("GPU-"))
+        {
+            let uuid = retried("nvmlDeviceGetUUID", || device.uuid())
+                .map_err(|e| format!("failed to get GPU UUID to match {}: {:?}", required, e))?;
+            if uuid != *required {
+                return Err(format!("GPU is {}, entry requires {}", uuid, required));
+            }
+        } else {
+            let name = retried("nvmlDeviceGetName", || device.name())
+                .map_err(|e| format!("failed to get GPU name to match {}: {:?}", required, e))?;
+            if !name.to_lowercase().contains(&required.to_lowercase()) {
+                return Err(format!("GPU is {}, entry requires {}", name, required));
+            }
+        }
+        Ok(())
+    }
+
+    /// The per-P-state memory clock offsets, ordered by P-state.
+    fn pstate_mem_offsets(&self) -> Vec<(u32, i32)> {
+        sorted_pstate_offsets(&self.mem_offset_pstate)
+    }
+
+    /// The per-fan speeds, ordered by fan index.
+    pub(crate) fn sorted_fan_speeds(&self) -> Vec<(u32, u32)> {
+        let mut speeds: Vec<(u32, u32)> = self
+            .fan_speeds
+            .iter()
+            .flatten()
+            .map(|(fan, speed)| (*fan, *speed))
+            .collect();
+        speeds.sort_unstable();
+        speeds
+    }
+
+    /// The fans targeted by the fan settings: the selected fan, or every fan
+    /// on the device.
+    fn fans(&self, device: &Device) -> Result<std::ops::Range<u32>, NvmlError> {
+        match self.fan_index {
+            Some(fan) => Ok(fan..fan + 1),
+            None => Ok(0..retried("nvmlDeviceGetNumFans", || device.num_fans())?),
+        }
+    }
+
+    /// Checks the values against the device's supported ranges and the
+    /// offset safety limit, returning every problem found.
+    pub fn validate(&self, device: &Device, force: bool) -> Vec<ApplyError> {
+        let mut problems = Vec::new();
+
+        let changes_clocks = self.freq_offset.is_some()
+            || self.freq_offset_pstate.is_some()
+            || self.mem_offset_pstate.is_some()
+            || self.mem_offset.is_some()
+            || self.vf_points.is_some()
+            || self.min_clock.is_some()
+            || self.min_pstate.is_some()
+            || self.lock_clock.is_some()
+            || self.min_mem_clock.is_some()
+            || self.auto_oc.is_some();
+        if changes_clocks && mig_enabled(device).unwrap_or(false) {
+            problems.push(ApplyError::MigEnabled);
+        }
+
+        if self.target_temp_min.is_some() || self.target_temp_max.is_some() {
+            let current = |limit| nvml_raw::get_target_temperature_limit(device, limit).ok();
+            let min = self
+                .target_temp_min
+                .or_else(|| current(TargetTemperatureLimit::Min));
+            let max = self
+                .target_temp_max
+                .or_else(|| current(TargetTemperatureLimit::Max));
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    problems.push(ApplyError::InvalidTargetTemperature(format!(
+                        "minimum {} °C is above maximum {} °C",
+                        min, max
+                    )));
+                }
+            }
+        }
+
+        if let Some(celsius) = self.max_temp_limit {
+            match retried("nvmlDeviceGetTemperatureThreshold", || {
+                device.temperature_threshold(TemperatureThreshold::Slowdown)
+            }) {
+                Ok(slowdown) if celsius > slowdown => {
+                    problems.push(ApplyError::InvalidMaxTemperature { celsius, slowdown })
+                }
+                Ok(_) => {}
+                Err(e) => debug!("Skipping GPU max temperature check: {:?}", e),
+            }
+        }
+
+        if let (Some(memory), Some(graphics)) = (self.app_mem_clock, self.app_clock) {
+            if let Err(problem) = check_app_clocks(device, memory, graphics) {
+                problems.push(problem);
+            }
+        }
+
+        let force = force || self.force.unwrap_or(false);
+        let limit = self.max_offset.unwrap_or(DEFAULT_MAX_OFFSET);
+        let pstate_offsets = self.pstate_freq_offsets();
+        let pstate_mem_offsets = self.pstate_mem_offsets();
+        for (parameter, key, offset) in [
+            ("GPU frequency offset", "freqOffset", self.freq_offset),
+            ("GPU memory frequency offset", "memOffset", self.mem_offset),
+        ]
+        .into_iter()
+        .chain(pstate_offsets.iter().map(|(_, offset)| {
+            (
+                "GPU P-state frequency offset",
+                "freqOffsetPstate",
+                Some(*offset),
+            )
+        }))
+        .chain(pstate_mem_offsets.iter().map(|(_, offset)| {
+            (
+                "GPU P-state memory frequency offset",
+                "memOffsetPstate",
+                Some(*offset),
+            )
+        }))
+        .chain(self.auto_oc.iter().flat_map(|auto_oc| {
+            [
+                (
+                    "GPU idle frequency offset",
+                    "autoOc",
+                    Some(auto_oc.idle_offset),
+                ),
+                (
+                    "GPU load frequency offset",
+                    "autoOc",
+                    Some(auto_oc.load_offset),
+                ),
+            ]
+        })) {
+            if let Some(offset) = offset.filter(|offset| !force && offset.unsigned_abs() > limit) {
+                problems.push(ApplyError::OffsetTooLarge {
+                    parameter,
+                    key,
+                    offset,
+                    limit,
+                });
+            }
+        }
+
+        // The driver silently ignores offsets outside its range, so reject
+        // them here. Older drivers cannot report the range.
+        for (parameter, key, offset, range) in [
+            (
+                "GPU frequency offset",
+                "freqOffset",
+                self.freq_offset,
+                nvml_raw::get_gpc_clock_vf_offset_range as fn(&Device) -> _,
+            ),
+            (
+                "GPU memory frequency offset",
+                "memOffset",
+                self.mem_offset,
+                nvml_raw::get_mem_clock_vf_offset_range,
+            ),
+        ] {
+            let Some(offset) = offset else {
+                continue;
+            };
+            match range(device) {
+                Ok((min, max)) if offset < min || offset > max => {
+                    problems.push(ApplyError::OffsetOutOfRange {
+                        parameter,
+                        key,
+                        offset,
+                        min,
+                        max,
+                    })
+                }
+                Ok(_) => {}
+                Err(e) => debug!("Skipping {} range check: {}", parameter, e),
+            }
+        }
+
+        let power_limit = match (self.power_limit, self.power_delta) {
+            (Some(limit), _) => limit.milliwatts(device).ok(),
+            (None, Some(delta)) => delta.milliwatts(device).ok(),
+            (None, None) => None,
+        };
+        if let Some(limit) = power_limit {
+            let constraints = device.power_management_limit_constraints().ok();
+            match constraints {
+                Some(constraints)
+                    if limit < constraints.min_limit || limit > constraints.max_limit =>
+                {
+                    problems.push(ApplyError::PowerLimitOutOfRange {
+                        limit,
+                        min: constraints.min_limit,
+                        max: constraints.max_limit,
+                        floor: self.min_power_limit,
+                    })
+                }
+                _ => {
+                    if let Some(floor) = self.min_power_limit.filter(|floor| limit < *floor) {
+                        problems.push(ApplyError::PowerLimitBelowFloor {
+                            limit,
+                            floor,
+                            min: constraints.map(|constraints| constraints.min_limit),
+                        });
+                    }
+                }
+            }
+        }
+
+        if let (Some(min_pstate), Some(max_pstate)) = (self.min_pstate, self.max_pstate) {
+            if min_pstate > max_pstate {
+                problems.push(ApplyError::InvalidPstates(format!(
+                    "P{} is a lower performance state than P{}",
+                    min_pstate, max_pstate
+                )));
+            }
+            if let Ok(supported) = device.supported_performance_states() {
+                for state in [min_pstate, max_pstate] {
+                    if !supported.iter().any(|s| s.as_c() == state) {
+                        problems.push(ApplyError::InvalidPstates(format!(
+                            "P{} is not supported by this GPU",
+                            state
+                        )));
+                    }
+                }
+            }
+        }
+
+        if !pstate_offsets.is_empty() || !pstate_mem_offsets.is_empty() {
+            let supported = device.supported_performance_states().ok();
+            let mut states: Vec<u32> = pstate_offsets
+                .iter()
+                .chain(&pstate_mem_offsets)
+                .map(|(state, _)| *state)
+                .collect();
+            states.sort_unstable();
+            states.dedup();
+            for state in &states {
+                let exists = pstate(*state).is_ok();
+                if !exists
+                    || supported
+                        .as_ref()
+                        .is_some_and(|supported| !supported.iter().any(|s| s.as_c() == *state))
+                {
+                    problems.push(ApplyError::InvalidPstates(format!(
+                        "P{} is not supported by this GPU",
+                        state
+                    )));
+                }
+            }
+        }
+
+        if let Some(speed) = self.fan_speed.filter(|speed| *speed > 100) {
+            problems.push(ApplyError::InvalidFanSpeed(speed));
+        }
+
+        if self.fan_speeds.is_some() {
+            if self.fan_speed.is_some()
+                || self.fan_auto
+                || self.fan_policy.is_some()
+                || self.fan_index.is_some()
+            {
+                problems.push(ApplyError::InvalidFanSpeeds(
+                    "cannot be combined with fanSpeed, fanAuto, fanPolicy or fanIndex".to_string(),
+                ));
+            }
+            let count = retried("nvmlDeviceGetNumFans", || device.num_fans()).ok();
+            for (fan, speed) in self.sorted_fan_speeds() {
+                if speed > 100 {
+                    problems.push(ApplyError::InvalidFanSpeed(speed));
+                }
+                if let Some(count) = count.filter(|count| fan >= *count) {
+                    problems.push(ApplyError::InvalidFanSpeeds(format!(
+                        "the GPU has no fan {}, only {} fans",
+                        fan, count
+                    )));
+                }
+            }
+        }
+
+        if let Some(curve) = &self.fan_curve {
+            if let Err(e) = daemon::validate_fan_curve(curve) {
+                problems.push(ApplyError::InvalidFanCurve(e));
+            }
+        }
+
+        if let Some(auto_oc) = &self.auto_oc {
+            if let Err(e) = auto_oc.validate() {
+                problems.push(ApplyError::InvalidAutoOc(e));
+            }
+        }
+
+        problems
+    }
+
+    /// Checks the locked clock ranges against the clocks the device supports.
+    /// Kept out of `validate` because NVML clamps out-of-range clocks itself,
+    /// so `apply` accepts them.
+    pub fn validate_clocks(&self, device: &Device) -> Vec<ApplyError> {
+        let mut problems = Vec::new();
+        for (name, key, clock, range) in [
+            (
+                "clock",
+                "minClock",
+                Clock::Graphics,
+                (self.min_clock, self.max_clock),
+            ),
+            (
+                "memory clock",
+                "minMemClock",
+                Clock::Memory,
+                (self.min_mem_clock, self.max_mem_clock),
+            ),
+        ] {
+            let (Some(min), Some(max)) = range else {
+                continue;
+            };
+            if min > max {
+                problems.push(ApplyError::InvalidClocks(
+                    key,
+                    format!("min {} {} MHz is above max {} {} MHz", name, min, name, max),
+                ));
+            }
+            if let Ok(supported) = device.max_clock_info(clock) {
+                if min > supported {
+                    problems.push(ApplyError::InvalidClocks(
+                        key,
+                        format!(
+                            "min {} {} MHz exceeds the GPU's maximum of {} MHz",
+                            name, min, supported
+                        ),
+                    ));
+                }
+            }
+        }
+        if let Some(requested) = self.lock_clock {
+            if let Ok(clock) = nearest_graphics_clock(device, requested) {
+                if clock.abs_diff(requested) > LOCK_CLOCK_TOLERANCE {
+                    problems.push(ApplyError::InvalidClocks(
+                        "lockClock",
+                        format!(
+                            "lock clock {} MHz is {} MHz away from the nearest supported clock, {} MHz",
+                            requested,
+                            clock.abs_diff(requested),
+                            clock
+                        ),
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Describes the requested values that may destabilize the GPU: large
+    /// positive offsets and a power limit at the maximum.
+    pub fn risks(&self, device: &Device) -> Vec<String> {
+        let mut risks = Vec::new();
+        for (name, offset, threshold) in [
+            ("GPU core clock offset", self.freq_offset, RISKY_FREQ_OFFSET),
+            ("GPU memory clock offset", self.mem_offset, RISKY_MEM_OFFSET),
+        ] {
+            if let Some(offset) = offset.filter(|offset| *offset > threshold) {
+                risks.push(format!(
+                    "{} of {:+} MHz is above {:+} MHz",
+                    name, offset, threshold
+                ));
+            }
+        }
+
+        let limit = match (self.power_limit, self.power_delta) {
+            (Some(limit), _) => limit.milliwatts(device).ok(),
+            (None, Some(delta)) => delta.milliwatts(device).ok(),
+            (None, None) => None,
+        };
+        if let (Some(limit), Ok(constraints)) = (limit, device.power_management_limit_constraints())
+        {
+            if limit >= constraints.max_limit {
+                risks.push(format!(
+                    "GPU power limit of {} mW is the maximum",
+                    constraints.max_limit
+                ));
+            }
+        }
+        risks
+    }
+
+    /// Prints what `apply` would change without touching the hardware,
+    /// warning about values that would be rejected.
+    pub fn preview(&self, device: &Device, force: bool) {
+        for problem in self.validate(device, force) {
+            warn!("{}", problem);
+        }
+
+        if let Some(freq_offset) = self.freq_offset {
+            warn_projected_boost_clock(device, freq_offset);
+            match device.max_clock_info(Clock::Graphics) {
+                Ok(max) => println!(
+                    "Would set GPU core clock offset to {:+} MHz, boosting up to {} MHz",
+                    freq_offset,
+                    projected_boost_clock(max, freq_offset)
+                ),
+                Err(_) => println!("Would set GPU core clock offset to {:+} MHz", freq_offset),
+            }
+        }
+
+        for (pstate, offset) in self.pstate_freq_offsets() {
+            println!(
+                "Would set GPU core clock offset for P{} to {:+} MHz",
+                pstate, offset
+            );
+        }
+
+        if let Some(mem_offset) = self.mem_offset {
+            println!("Would set GPU memory clock offset to {:+} MHz", mem_offset);
+        }
+
+        for (pstate, offset) in self.pstate_mem_offsets() {
+            println!(
+                "Would set GPU memory clock offset for P{} to {:+} MHz",
+                pstate, offset
+            );
+        }
+
+        if let Some(limit) = self.power_limit {
+            match (limit, limit.milliwatts(device)) {
+                (PowerLimit::Percent(_), Ok(milliwatts)) => {
+                    println!("Would set GPU power limit to {} ({} mW)", limit, milliwatts)
+                }
+                _ => println!("Would set GPU power limit to {}", limit),
+            }
+        }
+
+        if let Some(delta) = self.power_delta {
+            match delta.milliwatts(device) {
+                Ok(limit) => println!("Would change GPU power limit by {} to {} mW", delta, limit),
+                Err(_) => println!("Would change GPU power limit by {}", delta),
+            }
+        }
+
+        if let (Some(min_clock), Some(max_clock)) = (self.min_clock, self.max_clock) {
+            println!("Would lock GPU clocks to {}-{} MHz", min_clock, max_clock);
+        }
+
+        if let Some(requested) = self.lock_clock {
+            match nearest_graphics_clock(device, requested) {
+                Ok(clock) if clock != requested => println!(
+                    "Would lock GPU clock to {} MHz, the supported clock nearest to {} MHz",
+                    clock, requested
+                ),
+                _ => println!("Would lock GPU clock to {} MHz", requested),
+            }
+        }
+
+        if let (Some(min_pstate), Some(max_pstate)) = (self.min_pstate, self.max_pstate) {
+            println!(
+                "Would lock GPU to performance states P{}-P{}",
+                min_pstate, max_pstate
+            );
+        }
+
+        if let (Some(min_mem_clock), Some(max_mem_clock)) = (self.min_mem_clock, self.max_mem_clock)
+        {
+            println!(
+                "Would lock GPU memory clocks to {}-{} MHz",
+                min_mem_clock, max_mem_clock
+            );
+        }
+
+        if let Some(speed) = self.fan_speed {
+            match self.fans(device) {
+                Ok(fans) => {
+                    for fan in fans {
+                        println!("Would set GPU fan {} speed to {}%", fan, speed);
+                    }
+                }
+                Err(e) => error!("Failed to get GPU fan count: {:?}", e),
+            }
+        }
+
+        for (fan, speed) in self.sorted_fan_speeds() {
+            println!("Would set GPU fan {} speed to {}%", fan, speed);
+        }
+
+        if self.reset_clocks {
+            println!("Would unlock the GPU and memory clocks");
+        }
+
+        if self.reset_app_clocks {
+            println!("Would reset the GPU application clocks");
+        }
+
+        if let (Some(memory), Some(graphics)) = (self.app_mem_clock, self.app_clock) {
+            match check_app_clocks(device, memory, graphics) {
+                Ok(()) => println!(
+                    "Would set GPU application clocks to {} MHz core, {} MHz memory",
+                    graphics, memory
+                ),
+                Err(e) => println!("Would fail: {}", e),
+            }
+        }
+
+        if self.fan_auto {
+            match self.fans(device) {
+                Ok(fans) => {
+                    for fan in fans {
+                        println!("Would restore automatic control for GPU fan {}", fan);
+                    }
+                }
+                Err(e) => error!("Failed to get GPU fan count: {:?}", e),
+            }
+        }
+
+        if let Some(policy) = self.fan_policy {
+            match self.fans(device) {
+                Ok(fans) => {
+                    for fan in fans {
+                        println!("Would set GPU fan {} control policy to {}", fan, policy);
+                    }
+                }
+                Err(e) => error!("Failed to get GPU fan count: {:?}", e),
+            }
+        }
+
+        if let Some(mode) = self.compute_mode {
+            println!("Would set GPU compute mode to {}", mode);
+        }
+
+        if let Some(enabled) = self.persistence_mode {
+            println!(
+                "Would {} GPU persistence mode",
+                if enabled { "enable" } else { "disable" }
+            );
+        }
+
+        if let Some(celsius) = self.target_temp_min {
+            println!("Would set GPU minimum target temperature to {} °C", celsius);
+        }
+
+        if let Some(celsius) = self.target_temp_max {
+            println!("Would set GPU maximum target temperature to {} °C", celsius);
+        }
+
+        if self.reset_target_temp {
+            println!("Would clear the GPU target temperature");
+        }
+
+        if let Some(celsius) = self.max_temp_limit {
+            println!("Would set GPU max temperature to {} °C", celsius);
+        }
+    }
+}
+
+/// Reads a config file into a JSON value, choosing the format from the
+/// extension of `path` like `Config::parse`, JSON when there is no path.
+/// YAML goes through `serde_yaml::Value` so that numeric GPU keys become
+/// strings.
+pub(crate) fn config_value(
+    path: Option<&str>,
+    contents: &str,
+) -> Result<serde_json::Value, String> {
+    let extension = path
+        .and_then(|path| std::path::Path::new(path).extension())
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("toml") => toml::from_str(contents).map_err(|e| e.to_string()),
+        Some("yaml" | "yml") => serde_yaml::from_str::<serde_yaml::Value>(contents)
+            .map_err(|e| e.to_string())
+            .and_then(|yaml| serde_json::to_value(yaml).map_err(|e| e.to_string())),
+        _ => serde_json::from_str(contents).map_err(|e| e.to_string()),
+    }
+}
+
+/// Merges `overrides` into `base`, descending `depth` levels into maps
+/// present in both and replacing everything else.
+fn merge_value(base: &mut serde_json::Value, overrides: serde_json::Value, depth: usize) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) if depth > 0 => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value, depth - 1),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// The contents of a config file. Unknown keys, such as a misspelled or
+/// snake_case parameter, are rejected rather than silently ignored.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Settings every GPU entry inherits unless it sets them itself. They are
+    /// merged into `sets` and `profiles` when the file is parsed.
+    #[serde(default)]
+    pub defaults: Option<Sets>,
+    #[serde(default)]
+    pub sets: HashMap<GpuSelector, Sets>,
+    /// Named alternatives to `sets`, selected with `--profile`
+    #[serde(default)]
+    pub profiles: HashMap<String, HashMap<GpuSelector, Sets>>,
+}
+
+impl Config {
+    /// Parses a config file, choosing the format from the file extension.
+    /// Files ending in `.toml` are read as TOML, `.yaml` or `.yml` as YAML
+    /// and everything else as JSON.
+    pub fn parse(path: &str, contents: &str) -> Result<Config, String> {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        let config: Config = match extension.as_deref() {
+            Some("toml") => toml::from_str(contents).map_err(|e| e.to_string()),
+            Some("yaml" | "yml") => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+            _ => serde_json::from_str(contents).map_err(|e| e.to_string()),
+        }?;
+        config.finish()
+    }
+
+    /// Parses several config files, given as `(path, contents)`, as one.
+    /// Later files override earlier ones: `defaults`, `sets`, `profiles` and
+    /// their GPU entries are merged key by key, so an override only needs the
+    /// parameters it changes, while a parameter such as a fan curve is
+    /// replaced as a whole. GPUs must be keyed the same way in every file to
+    /// be merged. The entries inherit the merged `defaults`, and the result
+    /// is checked like a single file.
+    pub fn parse_all(files: &[(&str, String)]) -> Result<Config, String> {
+        if let [(path, contents)] = files {
+            return Config::parse(path, contents);
+        }
+        let mut merged = serde_json::Map::new();
+        for (path, contents) in files {
+            let value =
+                config_value(Some(path), contents).map_err(|e| format!("{}: {}", path, e))?;
+            let serde_json::Value::Object(value) = value else {
+                return Err(format!(
+                    "{}: the config file is not a map of settings",
+                    path
+                ));
+            };
+            for (key, value) in value {
+                // How many levels of maps below the key are merged.
+                let depth = match key.as_str() {
+                    "defaults" => 1,
+                    "sets" => 2,
+                    "profiles" => 3,
+                    _ => 0,
+                };
+                match merged.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value, depth),
+                    None => {
+                        merged.insert(key, value);
+                    }
+                }
+            }
+        }
+        let config: Config = serde_json::from_value(serde_json::Value::Object(merged))
+            .map_err(|e| format!("merged config: {}", e))?;
+        config.finish()
+    }
+
+    /// Checks the entries of a freshly read config and lets them inherit the
+    /// `defaults`.
+    fn finish(mut self) -> Result<Config, String> {
+        // Checked before inheriting, which would hide an incomplete pair
+        // behind the defaults.
+        let mut problems: Vec<String> = self
+            .defaults
+            .iter()
+            .map(|sets| ("defaults".to_string(), sets))
+            .chain(
+                self.sets
+                    .iter()
+                    .map(|(index, sets)| (format!("GPU {}", index), sets)),
+            )
+            .chain(self.profiles.iter().flat_map(|(name, gpus)| {
+                gpus.iter()
+                    .map(move |(index, sets)| (format!("Profile `{}`, GPU {}", name, index), sets))
+            }))
+            .filter_map(|(entry, sets)| {
+                sets.check_pairs()
+                    .and_then(|()| match sets.voltage_offset {
+                        Some(_) => Err(format!("`voltageOffset`: {}", VOLTAGE_OFFSET_UNSUPPORTED)),
+                        None => Ok(()),
+                    })
+                    .err()
+                    .map(|e| format!("{}: {}", entry, e))
+            })
+            .collect();
+        if !problems.is_empty() {
+            problems.sort_unstable();
+            return Err(problems.join("; "));
+        }
+
+        if let Some(defaults) = &self.defaults {
+            for sets in self
+                .sets
+                .values_mut()
+                .chain(self.profiles.values_mut().flat_map(HashMap::values_mut))
+            {
+                sets.inherit(defaults);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Returns the per-GPU settings of the named profile, or the top-level
+    /// `sets` when no profile is given.
+    pub fn profile(&self, name: Option<&str>) -> Result<&HashMap<GpuSelector, Sets>, String> {
+        let Some(name) = name else {
+            return Ok(&self.sets);
+        };
+        self.profiles.get(name).ok_or_else(|| {
+            let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            if names.is_empty() {
+                format!(
+                    "Profile `{}` not found, the config file defines no profiles",
+                    name
+                )
+            } else {
+                format!(
+                    "Profile `{}` not found, available profiles: {}",
+                    name,
+                    names.join(", ")
+                )
+            }
+        })
+    }
+
+    /// Checks the settings of every GPU in `sets` and in each profile against
+    /// the device, reporting every problem. Returns `true` if there were none.
+    pub fn validate(&self, nvml: &Nvml) -> bool {
+        let mut profiles: Vec<(Option<&str>, &HashMap<GpuSelector, Sets>)> =
+            vec![(None, &self.sets)];
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort_unstable();
+        profiles.extend(
+            names
+                .into_iter()
+                .map(|name| (Some(name.as_str()), &self.profiles[name])),
+        );
+
+        let mut ok = true;
+        for (profile, gpus) in profiles {
+            let context = |index: &GpuSelector| match profile {
+                Some(name) => format!("Profile `{}`, GPU {}", name, index),
+                None => format!("GPU {}", index),
+            };
+            for (index, sets) in gpus {
+                let problems = match index.device(nvml) {
+                    Ok(device) => match sets.check_gpu(&device) {
+                        Ok(()) => sets
+                            .validate(&device, false)
+                            .iter()
+                            .chain(&sets.validate_clocks(&device))
+                            .map(ToString::to_string)
+                            .collect(),
+                        Err(e) => {
+                            warn!("{}: {}, the entry would be skipped", context(index), e);
+                            continue;
+                        }
+                    },
+                    Err(e) => vec![format!("No such GPU ({})", e)],
+                };
+                for problem in &problems {
+                    error!("{}: {}", context(index), problem);
+                }
+                ok &= problems.is_empty();
+            }
+        }
+        ok
+    }
+}
+
+/// Why a voltage offset is rejected. NVML offers no call to read or set the
+/// core voltage; the offsets it has only shift clocks along the V/F curve.
+pub const VOLTAGE_OFFSET_UNSUPPORTED: &str =
+    "voltage offsets are not supported: NVML has no over-voltage API, use a clock offset instead";
+
+/// Explains an `ApplyError` for which `is_permission_denied` holds.
+pub const PERMISSION_DENIED: &str =
+    "EPERM: changing GPU settings requires root or the CAP_SYS_ADMIN capability";
+
+/// Whether failures are also printed as JSON objects, see `set_json_errors`.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Set once a JSON error object has been printed, so that the failure it led
+/// to is not printed again as a generic one.
+static JSON_ERROR_PRINTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes `apply_all` also print each GPU's failure as a JSON object on
+/// standard output, for scripts. Off by default.
+pub fn set_json_errors(enabled: bool) {
+    JSON_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether a JSON error object has been printed with `print_json_error`.
+pub fn json_error_printed() -> bool {
+    JSON_ERROR_PRINTED.load(Ordering::Relaxed)
+}
+
+/// Prints an error as a single-line JSON object on standard output.
+pub fn print_json_error(
+    error: &str,
+    code: &str,
+    parameter: Option<&str>,
+    gpu: Option<&GpuSelector>,
+) {
+    let mut object = serde_json::json!({ "error": error, "code": code });
+    if let Some(parameter) = parameter {
+        object["parameter"] = parameter.into();
+    }
+    if let Some(gpu) = gpu {
+        object["gpu"] = gpu.to_string().into();
+    }
+    println!("{}", object);
+    JSON_ERROR_PRINTED.store(true, Ordering::Relaxed);
+}
+
+/// Applies the settings for every GPU in `gpus`, reporting failures per GPU,
+/// also as JSON objects if enabled with `set_json_errors`. Returns `true` if
+/// every GPU was configured successfully.
+pub fn apply_all(gpus: &HashMap<GpuSelector, Sets>, nvml: &Nvml) -> bool {
+    let gpus: Vec<_> = gpus.iter().collect();
+    let results = in_parallel(&gpus, |(index, sets)| {
+        let result = match index.device(nvml) {
+            Ok(mut device) => {
+                if let Err(e) = sets.check_gpu(&device) {
+                    warn!("GPU {}: {}, skipping", index, e);
+                    return true;
+                }
+                sets.apply(&mut device, false).map_err(|e| {
+                    let message = e.to_string();
+                    let message = if e.is_permission_denied() {
+                        format!("{} ({})", message, PERMISSION_DENIED)
+                    } else {
+                        message
+                    };
+                    (message, e.code(), e.parameter())
+                })
+            }
+            Err(e) => Err((format!("Failed to get GPU: {}", e), "DeviceNotFound", None)),
+        };
+        if let Err((message, code, parameter)) = &result {
+            error!("GPU {}: {}", index, message);
+            if JSON_ERRORS.load(Ordering::Relaxed) {
+                print_json_error(message, code, *parameter, Some(index));
+            }
+        }
+        result.is_ok()
+    });
+    results.into_iter().all(|ok| ok)
+}
+
+/// How many GPUs `in_parallel` works on at once, see `set_jobs`.
+static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// Limits how many GPUs are configured at once, each on its own thread. 0,
+/// the default, configures all of them at once; 1 configures them one after
+/// another on the calling thread, which keeps the log in order for debugging.
+pub fn set_jobs(jobs: usize) {
+    JOBS.store(jobs, Ordering::Relaxed);
+}
+
+/// Runs `f` for every item on up to `set_jobs` threads and returns the results
+/// in the order of `items`. `f` should get its own `Device` from `Nvml` rather
+/// than share a handle with the other threads.
+pub fn in_parallel<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let jobs = match JOBS.load(Ordering::Relaxed) {
+        0 => items.len(),
+        jobs => jobs.min(items.len()),
+    };
+    if jobs <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let (next, f) = (&next, &f);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break done;
+                        };
+                        done.push((i, f(item)));
+                    }
+                })
+            })
+            .collect();
+        let mut results: Vec<(usize, R)> = workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect();
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    })
+}
+
+/// Logs the outcome of an NVML call at debug level.
+pub(crate) trait Traced {
+    fn traced(self, call: &str) -> Self;
+}
+
+impl<T> Traced for Result<T, NvmlError> {
+    fn traced(self, call: &str) -> Self {
+        match &self {
+            Ok(_) => debug!("{}: success", call),
+            Err(e) => debug!("{}: {:?}", call, e),
+        }
+        self
+    }
+}
+
+/// How many times a transiently failing NVML call is retried, see
+/// `set_retries`.
+static RETRIES: AtomicU32 = AtomicU32::new(0);
+
+/// The pause before the first retry, growing with each further one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Retries NVML calls that fail with a transient error up to `retries` times,
+/// which helps boot-time services that race the driver initialization. Off
+/// by default.
+pub fn set_retries(retries: u32) {
+    RETRIES.store(retries, Ordering::Relaxed);
+}
+
+/// Runs the NVML call `f`, tracing it and retrying it with a growing pause as
+/// configured with `set_retries`. Only `Unknown` and `Timeout` errors are
+/// retried; errors such as `NotSupported` or `InvalidArg` will not go away.
+pub fn retried<T>(call: &str, mut f: impl FnMut() -> Result<T, NvmlError>) -> Result<T, NvmlError> {
+    let retries = RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    loop {
+        match f().traced(call) {
+            Err(e @ (NvmlError::Unknown | NvmlError::Timeout)) if attempt < retries => {
+                attempt += 1;
+                let delay = RETRY_BACKOFF * attempt;
+                warn!(
+                    "{} failed ({:?}), retrying in {} ms ({}/{})",
+                    call,
+                    e,
+                    delay.as_millis(),
+                    attempt,
+                    retries
+                );
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// When the driver has to be ready by, see `set_driver_wait`.
+static DRIVER_DEADLINE: OnceLock<Instant> = OnceLock::new();
+
+/// The pause between checks whether the driver is ready.
+const DRIVER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Makes `wait_for_driver` keep retrying until `timeout` from now, for boot
+/// services that may start before the NVIDIA driver has been loaded or has
+/// found the GPUs. Off by default.
+pub fn set_driver_wait(timeout: Duration) {
+    let _ = DRIVER_DEADLINE.set(Instant::now() + timeout);
+}
+
+/// Runs `f` again while it fails because the driver or the GPU is not ready
+/// yet, until the deadline set with `set_driver_wait` has passed.
+pub fn wait_for_driver<T>(
+    call: &str,
+    mut f: impl FnMut() -> Result<T, NvmlError>,
+) -> Result<T, NvmlError> {
+    loop {
+        let result = f();
+        let Some(deadline) = DRIVER_DEADLINE.get() else {
+            return result;
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match &result {
+            Err(
+                e @ (NvmlError::LibloadingError(_)
+                | NvmlError::DriverNotLoaded
+                | NvmlError::Uninitialized
+                | NvmlError::NotFound
+                | NvmlError::InvalidArg
+                | NvmlError::Unknown),
+            ) if !remaining.is_zero() => {
+                debug!(
+                    "{} failed ({:?}), waiting for the driver for up to {:.1} more seconds",
+                    call,
+                    e,
+                    remaining.as_secs_f64()
+                );
+                std::thread::sleep(DRIVER_POLL_INTERVAL.min(remaining));
+            }
+            _ => return result,
+        }
+    }
+}
+
+/// Whether `device` is partitioned into MIG instances. GPUs without MIG
+/// support report `false`.
+pub fn mig_enabled(device: &Device) -> Result<bool, NvmlError> {
+    match retried("nvmlDeviceGetMigMode", || device.mig_mode()) {
+        Ok(mode) => Ok(mode.current == nvml_wrapper_sys::bindings::NVML_DEVICE_MIG_ENABLE),
+        Err(NvmlError::NotSupported) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The offsets of a per-P-state offset map, ordered by P-state.
+fn sorted_pstate_offsets(offsets: &Option<HashMap<u32, i32>>) -> Vec<(u32, i32)> {
+    let mut offsets: Vec<(u32, i32)> = offsets
+        .iter()
+        .flatten()
+        .map(|(pstate, offset)| (*pstate, *offset))
+        .collect();
+    offsets.sort_unstable();
+    offsets
+}
+
+/// Converts a P-state number (0 for P0) into NVML's performance state.
+fn pstate(number: u32) -> Result<PerformanceState, NvmlError> {
+    if number > 15 {
+        return Err(NvmlError::InvalidArg);
+    }
+    PerformanceState::try_from(number)
+}
+
+/// One-line summary of a GPU: name, UUID, PCI bus ID and VRAM size. Parts
+/// that cannot be queried are shown as `unknown`.
+pub fn describe_device(device: &Device) -> String {
+    let unknown = || "unknown".to_string();
+    format!(
+        "{} (UUID: {}, PCI: {}, VRAM: {})",
+        device.name().unwrap_or_else(|_| unknown()),
+        device.uuid().unwrap_or_else(|_| unknown()),
+        device
+            .pci_info()
+            .map(|pci| pci.bus_id)
+            .unwrap_or_else(|_| unknown()),
+        device
+            .memory_info()
+            .map(|memory| format!("{} MiB", memory.total / (1024 * 1024)))
+            .unwrap_or_else(|_| unknown()),
+    )
+}
+
+/// The values of the parameters a `Sets` is about to change, taken so an
+/// `--atomic` apply can undo a partial change and `undo` can go back to them
+/// later. NVML cannot report locked clocks, so those are unlocked rather than
+/// restored.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    freq_offset: Option<i32>,
+    pstate_freq_offsets: Vec<(u32, i32)>,
+    pstate_mem_offsets: Vec<(u32, i32)>,
+    mem_offset: Option<i32>,
+    power_limit: Option<u32>,
+    clocks: bool,
+    mem_clocks: bool,
+    /// Memory and core application clocks in MHz
+    #[serde(default)]
+    app_clocks: Option<(u32, u32)>,
+    #[serde(with = "nvml_compute_mode")]
+    compute_mode: Option<nvml_wrapper::enum_wrappers::device::ComputeMode>,
+    persistence_mode: Option<bool>,
+    target_temp_min: Option<u32>,
+    target_temp_max: Option<u32>,
+    #[serde(default)]
+    target_temp: Option<u32>,
+    #[serde(default)]
+    max_temp_limit: Option<u32>,
+}
+
+/// (De)serializes an NVML compute mode as its C enum value, since
+/// `nvml-wrapper` is built without serde support.
+mod nvml_compute_mode {
+    use nvml_wrapper::enum_wrappers::device::ComputeMode;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        mode: &Option<ComputeMode>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        mode.map(|mode| mode.as_c()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<ComputeMode>, D::Error> {
+        Option::<u32>::deserialize(deserializer)?
+            .map(|mode| ComputeMode::try_from(mode).map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+impl Snapshot {
+    /// Writes the saved values back, attempting every parameter even if an
+    /// earlier one fails. Returns `true` if all of them were restored.
+    pub fn restore(&self, device: &mut Device) -> bool {
+        let mut ok = true;
+        let mut report = |name: &str, result: Result<(), String>| match result {
+            Ok(()) => info!("Restored {}", name),
+            Err(e) => {
+                error!("Failed to restore {}: {}", name, e);
+                ok = false;
+            }
+        };
+
+        if let Some(offset) = self.freq_offset {
+            report(
+                "GPU core clock offset",
+                device
+                    .set_gpc_clock_vf_offset(offset)
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        for (number, offset) in &self.pstate_freq_offsets {
+            report(
+                &format!("GPU core clock offset for P{}", number),
+                pstate(*number)
+                    .and_then(|state| device.set_clock_offset(Clock::Graphics, state, *offset))
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        for (number, offset) in &self.pstate_mem_offsets {
+            report(
+                &format!("GPU memory clock offset for P{}", number),
+                pstate(*number)
+                    .and_then(|state| device.set_clock_offset(Clock::Memory, state, *offset))
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        if let Some(offset) = self.mem_offset {
+            report(
+                "GPU memory clock offset",
+                device
+                    .set_mem_clock_vf_offset(offset)
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        if let Some(limit) = self.power_limit {
+            report(
+                "GPU power limit",
+                device
+                    .set_power_management_limit(limit)
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        if self.clocks {
+            report(
+                "GPU locked clocks",
+                device.reset_gpu_locked_clocks().map_err(|e| e.to_string()),
+            );
+        }
+        if self.mem_clocks {
+            report(
+                "GPU locked memory clocks",
+                device.reset_mem_locked_clocks().map_err(|e| e.to_string()),
+            );
+        }
+        if let Some((memory, graphics)) = self.app_clocks {
+            report(
+                "GPU application clocks",
+                device
+                    .set_applications_clocks(memory, graphics)
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        if let Some(mode) = self.compute_mode {
+            report(
+                "GPU compute mode",
+                device.set_compute_mode(mode).map_err(|e| e.to_string()),
+            );
+        }
+        if let Some(enabled) = self.persistence_mode {
+            report(
+                "GPU persistence mode",
+                device.set_persistent(enabled).map_err(|e| e.to_string()),
+            );
+        }
+        for (name, limit, celsius) in [
+            (
+                "GPU minimum target temperature",
+                TargetTemperatureLimit::Min,
+                self.target_temp_min,
+            ),
+            (
+                "GPU maximum target temperature",
+                TargetTemperatureLimit::Max,
+                self.target_temp_max,
+            ),
+            (
+                "GPU target temperature",
+                TargetTemperatureLimit::Current,
+                self.target_temp,
+            ),
+        ] {
+            if let Some(celsius) = celsius {
+                report(
+                    name,
+                    nvml_raw::set_target_temperature_limit(device, limit, celsius),
+                );
+            }
+        }
+        if let Some(celsius) = self.max_temp_limit {
+            report(
+                "GPU max temperature",
+                nvml_raw::set_gpu_max_temperature(device, celsius),
+            );
+        }
+
+        ok
+    }
+}
+
+/// Clears a user-set target temperature by raising it to the highest one the
+/// GPU allows. Returns the new target, or `None` if none was set.
+pub fn reset_target_temperature(device: &Device) -> Result<Option<u32>, String> {
+    let max = nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Max)?;
+    let current = nvml_raw::get_target_temperature_limit(device, TargetTemperatureLimit::Current)?;
+    if current >= max {
+        return Ok(None);
+    }
+    nvml_raw::set_target_temperature_limit(device, TargetTemperatureLimit::Current, max)?;
+    Ok(Some(max))
+}
+
+/// Restores offsets, power limit, locked and application clocks, fan control
+/// and the target temperature to their defaults. Every parameter is attempted even if an
+/// earlier one fails; returns `true` if all of them were reset. GPUs without
+/// a target temperature only get a warning for it.
+pub fn reset(device: &mut Device) -> bool {
+    let mut ok = true;
+    let mut report = |name: &str, result: Result<(), nvml_wrapper::error::NvmlError>| match result {
+        Ok(()) => info!("Reset {}", name),
+        Err(e) => {
+            error!("Failed to reset {}: {:?}", name, e);
+            ok = false;
+        }
+    };
+
+    report("GPU core clock offset", device.set_gpc_clock_vf_offset(0));
+    report("GPU memory clock offset", device.set_mem_clock_vf_offset(0));
+
+    let default_limit = device.power_management_limit_default().or_else(|_| {
+        device
+            .power_management_limit_constraints()
+            .map(|constraints| constraints.max_limit)
+    });
+    report(
+        "GPU power limit",
+        default_limit.and_then(|limit| device.set_power_management_limit(limit)),
+    );
+
+    report("GPU locked clocks", device.reset_gpu_locked_clocks());
+    report("GPU locked memory clocks", device.reset_mem_locked_clocks());
+    // Most GeForce GPUs have no application clocks to reset.
+    match device.reset_applications_clocks() {
+        Err(NvmlError::NotSupported) => {}
+        result => report("GPU application clocks", result),
+    }
+
+    match device.num_fans() {
+        Ok(fans) => {
+            for fan in 0..fans {
+                report(
+                    &format!("GPU fan {} control", fan),
+                    device.set_default_fan_speed(fan),
+                );
+            }
+        }
+        Err(e) => report("GPU fan control", Err(e)),
+    }
+
+    match reset_target_temperature(device) {
+        Ok(Some(celsius)) => info!("Reset GPU target temperature to {} °C", celsius),
+        Ok(None) => info!("GPU has no target temperature set"),
+        Err(e) => warn!("Failed to reset GPU target temperature: {}", e),
+    }
+
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The entry of GPU 0 in the top-level `sets`.
+    fn gpu0(config: &Config) -> &Sets {
+        &config.sets[&GpuSelector::Index(0)]
+    }
+
+    #[test]
+    fn gpu_selectors_expand_ranges_and_drop_duplicates() {
+        let uuid = "GPU-12345678-1234-1234-1234-123456789abc";
+        let parse = |s: &str| s.parse::<GpuSelectors>().map(|selectors| selectors.0);
+        assert_eq!(
+            parse("0-3").unwrap(),
+            (0..=3).map(GpuSelector::Index).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            parse(" 2 , 0-1, 1 ").unwrap(),
+            [2, 0, 1].map(GpuSelector::Index)
+        );
+        assert_eq!(parse("3-3").unwrap(), [GpuSelector::Index(3)]);
+        assert_eq!(
+            parse(&format!("{},1,{}", uuid, uuid)).unwrap(),
+            [GpuSelector::Uuid(uuid.to_string()), GpuSelector::Index(1)]
+        );
+        assert_eq!(
+            parse("00000000:01:00.0").unwrap(),
+            [GpuSelector::PciBusId("00000000:01:00.0".to_string())]
+        );
+        assert!(parse("3-1").unwrap_err().contains("ends before it starts"));
+        assert!(parse("0,gpu").is_err());
+        assert!(parse("0,").is_err());
+    }
+
+    #[test]
+    fn power_limits_parse_as_milliwatts_or_percent() {
+        assert!(matches!(
+            "250000".parse(),
+            Ok(PowerLimit::Milliwatts(250000))
+        ));
+        assert!(matches!(" 80 % ".parse(), Ok(PowerLimit::Percent(80))));
+        assert!(matches!("100%".parse(), Ok(PowerLimit::Percent(100))));
+        for (limit, expected) in [
+            ("0", "above 0 mW"),
+            ("0%", "between 1% and 100%"),
+            ("101%", "between 1% and 100%"),
+            ("-5", "must not be negative"),
+            ("80W", "not a power limit"),
+            ("%", "invalid power limit percentage"),
+        ] {
+            let error = limit.parse::<PowerLimit>().unwrap_err();
+            assert!(error.contains(expected), "{}: {}", limit, error);
+        }
+
+        let config = Config::parse(
+            "zelos.json",
+            r#"{ "sets": { "0": { "powerLimit": 200000 }, "1": { "powerLimit": "75%" } } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            gpu0(&config).power_limit,
+            Some(PowerLimit::Milliwatts(200000))
+        ));
+        assert!(matches!(
+            config.sets[&GpuSelector::Index(1)].power_limit,
+            Some(PowerLimit::Percent(75))
+        ));
+        for limit in ["0", "-1", r#""150%""#] {
+            let contents = format!(r#"{{ "sets": {{ "0": {{ "powerLimit": {} }} }} }}"#, limit);
+            assert!(Config::parse("zelos.json", &contents).is_err(), "{}", limit);
+        }
+    }
+
+    #[test]
+    fn power_deltas_parse_in_watts_or_milliwatts() {
+        for (delta, expected) in [
+            ("+20W", 20000),
+            ("-20 W", -20000),
+            ("-500mW", -500),
+            ("750", 750),
+            (" +0W ", 0),
+        ] {
+            assert_eq!(
+                delta.parse::<PowerDelta>().unwrap().0,
+                expected,
+                "{}",
+                delta
+            );
+        }
+        for delta in ["", "20kW", "+W", "1.5W", "9223372036854775807W"] {
+            assert!(delta.parse::<PowerDelta>().is_err(), "{}", delta);
+        }
+
+        let config = Config::parse(
+            "zelos.json",
+            r#"{ "sets": { "0": { "powerDelta": -15000 }, "1": { "powerDelta": "+20W" } } }"#,
+        )
+        .unwrap();
+        assert_eq!(gpu0(&config).power_delta.map(|delta| delta.0), Some(-15000));
+        assert_eq!(
+            config.sets[&GpuSelector::Index(1)]
+                .power_delta
+                .map(|delta| delta.0),
+            Some(20000)
+        );
+    }
+
+    #[test]
+    fn fan_speed_follows_the_curve() {
+        let curve = [(40, 30), (60, 50), (80, 100)];
+        for (temperature, expected) in [
+            (20, 30),
+            (40, 30),
+            (50, 40),
+            (60, 50),
+            (65, 63),
+            (70, 75),
+            (80, 100),
+            (95, 100),
+        ] {
+            assert_eq!(
+                daemon::fan_speed_for(&curve, temperature),
+                expected,
+                "{} °C",
+                temperature
+            );
+        }
+        assert_eq!(daemon::fan_speed_for(&[(50, 60)], 30), 60);
+        assert_eq!(daemon::fan_speed_for(&[(50, 60)], 90), 60);
+        assert_eq!(daemon::fan_speed_for(&[(40, 80), (70, 40)], 55), 60);
+    }
+
+    #[test]
+    fn merge_value_descends_only_as_deep_as_asked() {
+        let mut base = serde_json::json!({ "a": { "b": { "c": 1, "d": 2 } }, "e": 3 });
+        merge_value(
+            &mut base,
+            serde_json::json!({ "a": { "b": { "c": 10 } }, "f": 4 }),
+            2,
+        );
+        assert_eq!(
+            base,
+            serde_json::json!({ "a": { "b": { "c": 10 } }, "e": 3, "f": 4 })
+        );
+
+        let mut base = serde_json::json!({ "a": { "b": 1, "c": 2 } });
+        merge_value(&mut base, serde_json::json!({ "a": { "b": 10 } }), 2);
+        assert_eq!(base, serde_json::json!({ "a": { "b": 10, "c": 2 } }));
+
+        let mut base = serde_json::json!({ "a": 1 });
+        merge_value(&mut base, serde_json::json!({ "b": 2 }), 0);
+        assert_eq!(base, serde_json::json!({ "b": 2 }));
+    }
+
+    #[test]
+    fn later_config_files_override_earlier_ones_key_by_key() {
+        let base = r#"{
+            "defaults": { "powerLimit": 200000, "fanSpeed": 50 },
+            "sets": { "0": { "freqOffset": 100, "fanCurve": [[40, 30], [80, 100]] } },
+            "profiles": { "quiet": { "0": { "freqOffset": 0, "memOffset": 0 } } }
+        }"#;
+        let overrides = r#"
+            [defaults]
+            fanSpeed = 60
+
+            [sets.0]
+            memOffset = 500
+            fanCurve = [[50, 40], [90, 100]]
+
+            [sets.1]
+            minClock = 210
+
+            [profiles.quiet.0]
+            memOffset = -200
+        "#;
+        let files = |overrides: &str| {
+            [
+                ("base.json", base.to_string()),
+                ("overrides.toml", overrides.to_string()),
+            ]
+        };
+
+        let error = Config::parse_all(&files(overrides)).err().unwrap();
+        assert!(
+            error.contains("`minClock` requires `maxClock`"),
+            "{}",
+            error
+        );
+
+        let overrides = overrides.replace("minClock = 210", "minClock = 210\nmaxClock = 1800");
+        let config = Config::parse_all(&files(&overrides)).unwrap();
+        let sets = gpu0(&config);
+        assert_eq!(sets.freq_offset, Some(100));
+        assert_eq!(sets.mem_offset, Some(500));
+        assert_eq!(sets.fan_curve, Some(vec![(50, 40), (90, 100)]));
+        assert_eq!(sets.fan_speed, Some(60));
+        assert!(matches!(
+            sets.power_limit,
+            Some(PowerLimit::Milliwatts(200000))
+        ));
+        let gpu1 = &config.sets[&GpuSelector::Index(1)];
+        assert_eq!((gpu1.min_clock, gpu1.max_clock), (Some(210), Some(1800)));
+        let quiet = &config.profile(Some("quiet")).unwrap()[&GpuSelector::Index(0)];
+        assert_eq!(quiet.freq_offset, Some(0));
+        assert_eq!(quiet.mem_offset, Some(-200));
+    }
+
+    #[test]
+    fn negative_offsets_parse_in_every_format() {
+        let files = [
+            (
+                "zelos.json",
+                r#"{ "sets": { "0": { "freqOffset": -500, "memOffset": -1000 } } }"#,
+            ),
+            (
+                "zelos.toml",
+                "[sets.0]\nfreqOffset = -500\nmemOffset = -1000\n",
+            ),
+            (
+                "zelos.yaml",
+                "sets:\n  0:\n    freqOffset: -500\n    memOffset: -1000\n",
+            ),
+        ];
+        for (path, contents) in files {
+            let config = Config::parse(path, contents).unwrap();
+            let sets = gpu0(&config);
+            assert_eq!(sets.freq_offset, Some(-500), "{}", path);
+            assert_eq!(sets.mem_offset, Some(-1000), "{}", path);
+            assert_eq!(sets.parameters(), ["freqOffset", "memOffset"], "{}", path);
+        }
+    }
+
+    #[test]
+    fn keys_are_camel_case() {
+        let config = Config::parse(
+            "zelos.json",
+            r#"{ "sets": { "0": { "minClock": 210, "maxClock": 1800, "targetTempMax": 83 } } }"#,
+        )
+        .unwrap();
+        let sets = gpu0(&config);
+        assert_eq!(sets.min_clock, Some(210));
+        assert_eq!(sets.max_clock, Some(1800));
+        assert_eq!(sets.target_temp_max, Some(83));
+
+        let error = Config::parse(
+            "zelos.json",
+            r#"{ "sets": { "0": { "freq_offset": 100 } } }"#,
+        )
+        .err()
+        .unwrap();
+        assert!(error.contains("unknown field `freq_offset`"), "{}", error);
+    }
+
+    #[test]
+    fn save_replaces_the_entry_of_a_gpu_under_its_existing_key() {
+        let uuid = "GPU-12345678-1234-1234-1234-123456789abc";
+        let existing = format!(
+            r#"{{ "sets": {{ "{}": {{ "freqOffset": 100 }}, "1": {{ "memOffset": 500 }} }} }}"#,
+            uuid
+        );
+        let mut entry = serde_json::Map::new();
+        entry.insert("freqOffset".to_string(), 150.into());
+        let contents = capture::merge(
+            Some("zelos.json"),
+            Some(&existing),
+            vec![(GpuSelector::Index(0), entry)],
+            None,
+            |key, index| {
+                key == index
+                    || (*key == GpuSelector::Uuid(uuid.to_string())
+                        && *index == GpuSelector::Index(0))
+            },
+        )
+        .unwrap();
+        let config = Config::parse("zelos.json", &contents).unwrap();
+        assert_eq!(config.sets.len(), 2);
+        assert_eq!(
+            config.sets[&GpuSelector::Uuid(uuid.to_string())].freq_offset,
+            Some(150)
+        );
+        assert_eq!(config.sets[&GpuSelector::Index(1)].mem_offset, Some(500));
+    }
+
+    #[test]
+    fn half_pairs_are_rejected() {
+        let entries = [
+            (r#"{ "minClock": 210 }"#, "`minClock` requires `maxClock`"),
+            (r#"{ "maxClock": 1800 }"#, "`maxClock` requires `minClock`"),
+            (r#"{ "minPstate": 0 }"#, "`minPstate` requires `maxPstate`"),
+            (r#"{ "maxPstate": 8 }"#, "`maxPstate` requires `minPstate`"),
+            (
+                r#"{ "minMemClock": 405 }"#,
+                "`minMemClock` requires `maxMemClock`",
+            ),
+            (
+                r#"{ "maxMemClock": 9501 }"#,
+                "`maxMemClock` requires `minMemClock`",
+            ),
+            (r#"{ "fanIndex": 1 }"#, "`fanIndex` requires `fanSpeed`"),
+        ];
+        for (entry, expected) in entries {
+            for contents in [
+                format!(r#"{{ "sets": {{ "0": {} }} }}"#, entry),
+                format!(r#"{{ "defaults": {} }}"#, entry),
+                format!(r#"{{ "profiles": {{ "quiet": {{ "0": {} }} }} }}"#, entry),
+            ] {
+                let error = Config::parse("zelos.json", &contents).err().unwrap();
+                assert!(error.contains(expected), "{}: {}", contents, error);
+            }
+        }
+    }
+
+    #[test]
+    fn voltage_offsets_are_rejected() {
+        let error = Config::parse("zelos.json", r#"{ "defaults": { "voltageOffset": 50 } }"#)
+            .err()
+            .unwrap();
+        assert!(error.contains(VOLTAGE_OFFSET_UNSUPPORTED), "{}", error);
+    }
+
+    #[test]
+    fn pairs_completed_from_defaults_are_accepted() {
+        let config = Config::parse(
+            "zelos.json",
+            r#"{
+                "defaults": { "minClock": 210, "maxClock": 1800, "fanSpeed": 60, "fanIndex": 1 },
+                "sets": { "0": { "freqOffset": 100 }, "1": { "minPstate": 0, "maxPstate": 8 } }
+            }"#,
+        )
+        .unwrap();
+        let sets = gpu0(&config);
+        assert_eq!(sets.min_clock, Some(210));
+        assert_eq!(sets.max_clock, Some(1800));
+        assert_eq!(sets.fan_speed, Some(60));
+        assert_eq!(sets.fan_index, Some(1));
+
+        let sets = &config.sets[&GpuSelector::Index(1)];
+        assert_eq!((sets.min_clock, sets.max_clock), (None, None));
+        assert_eq!((sets.min_pstate, sets.max_pstate), (Some(0), Some(8)));
+        assert_eq!(sets.fan_index, Some(1));
+    }
+}
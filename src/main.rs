@@ -1,122 +1,480 @@
-use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use clap_complete::{generate, Generator, Shell};
-use nvml_wrapper::{Device, Nvml};
-use serde::Deserialize;
-use std::{collections::HashMap, io};
+use log::{error, info, warn};
+use nvml_wrapper::{error::NvmlError, Device, Nvml};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Mutex, PoisonError};
+use zelos::status::{self, GpuStatus};
+use zelos::{
+    apply_all, benchmark, capture, compare, daemon, describe_device, diff, exporter, monitor,
+    power_supply, print_json_error, reset, stress, undo, watchdog, ApplyError, Config, GpuSelector,
+    GpuSelectors, Ramp, Sets, DEFAULT_MAX_OFFSET,
+};
 #[cfg(feature = "gui")]
 mod gui_gtk;
 
+/// The exit status for each `Failure`, shown by `--help`.
+const EXIT_STATUS: &str = "Exit status:
+  0  Success
+  1  Any other failure, e.g. an unstable GPU or a config that differs from the GPU
+  2  Invalid arguments, or a config file that cannot be read or parsed or lacks a
+     profile or GPU
+  3  No such GPU, or the NVIDIA driver is not available
+  4  Root privileges could not be obtained, or NVML denied permission
+  5  NVML failed to apply or reset a setting
+  6  A value outside the accepted range, rejected before changing anything";
+
+/// Why the program failed, as a stable exit status that scripts and systemd
+/// units can act on. See `EXIT_STATUS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Failure {
+    Other = 1,
+    Config = 2,
+    DeviceNotFound = 3,
+    Permission = 4,
+    Nvml = 5,
+    OutOfRange = 6,
+    /// Arguments that parsed but were rejected afterwards
+    Usage,
+}
+
+/// The message of the last failure logged with `Failure::report`, which the
+/// `--json` error object of the failure carries.
+static REPORTED: Mutex<Option<String>> = Mutex::new(None);
+
+impl Failure {
+    /// Logs `error` along with what failed, for use with `map_err`.
+    fn report(self, context: &str, error: impl fmt::Display) -> Failure {
+        let message = format!("{}: {}", context, error);
+        error!("{}", message);
+        *REPORTED.lock().unwrap_or_else(PoisonError::into_inner) = Some(message);
+        self
+    }
+
+    /// The name of the failure, used as the `code` of `--json` errors.
+    fn code(self) -> &'static str {
+        match self {
+            Failure::Other => "Other",
+            Failure::Config => "Config",
+            Failure::DeviceNotFound => "DeviceNotFound",
+            Failure::Permission => "Permission",
+            Failure::Nvml => "Nvml",
+            Failure::OutOfRange => "OutOfRange",
+            Failure::Usage => "Usage",
+        }
+    }
+
+    /// A summary of the failure for `--json` errors when no message was
+    /// reported with `report`, the details having been logged to standard
+    /// error.
+    fn message(self) -> &'static str {
+        match self {
+            Failure::Other => "The command failed",
+            Failure::Config => "Invalid arguments or configuration",
+            Failure::DeviceNotFound => "No such GPU, or the NVIDIA driver is not available",
+            Failure::Permission => "Permission denied",
+            Failure::Nvml => "NVML failed to apply or reset a setting",
+            Failure::OutOfRange => "A value is outside the accepted range",
+            Failure::Usage => "Invalid arguments",
+        }
+    }
+}
+
+impl From<Failure> for ExitCode {
+    fn from(failure: Failure) -> ExitCode {
+        match failure {
+            // The status clap exits with for arguments it rejects itself
+            Failure::Usage => ExitCode::from(2),
+            failure => ExitCode::from(failure as u8),
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, after_help = EXIT_STATUS)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
-    /// Path to the config file
-    #[arg(short, long, default_value = "/etc/zelos.json")]
-    file: String,
+    /// Path to the config file, may be repeated to merge several files with
+    /// later ones overriding earlier ones
+    #[arg(
+        short,
+        long,
+        env = "NVIDIA_OC_CONFIG",
+        default_value = "/etc/zelos.json"
+    )]
+    file: Vec<String>,
+    /// Read the config as JSON from standard input instead of --file
+    #[arg(long)]
+    config_stdin: bool,
     /// Launch the GTK4 GUI
     #[arg(long, default_value_t = false)]
     gui: bool,
+    /// Apply a named profile from the config file instead of its top-level sets
+    #[arg(long)]
+    profile: Option<String>,
+    /// Print more details, repeat for even more (-v logs every NVML call)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Never ask for root privileges, e.g. when already running as a
+    /// privileged service or container
+    #[arg(long, global = true, default_value_t = false)]
+    no_escalate: bool,
+    /// Print failures as JSON objects on standard output for scripts, and
+    /// the results of `get` and `list` as JSON
+    #[arg(long, global = true, default_value_t = false)]
+    json: bool,
+    /// Retry NVML calls that fail transiently up to this many times
+    #[arg(long, global = true, default_value_t = 0)]
+    retry: u32,
+    /// Configure at most this many GPUs at once, 1 to configure them one
+    /// after another (defaults to all of them)
+    #[arg(long, global = true, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    jobs: Option<usize>,
+    /// Keep retrying for up to this many seconds while the NVIDIA driver or
+    /// the GPU is not ready yet, e.g. in a service started early at boot
+    #[arg(long, global = true, value_name = "SECONDS")]
+    wait_for_driver: Option<u64>,
+    /// Give up on the sudo, doas or pkexec password prompt after this many
+    /// seconds, 0 to wait forever
+    #[arg(long, global = true, default_value_t = 30, value_name = "SECONDS")]
+    escalation_timeout: u64,
+    /// Set on the escalated copy of the program, which removes this file
+    /// from the private directory of the unprivileged one to tell it that
+    /// authentication succeeded
+    #[arg(long, global = true, hide = true)]
+    escalated: Option<std::path::PathBuf>,
+    /// Also append the log messages and the standard output, with
+    /// timestamps, to this file, e.g. to keep a dedicated log of a service
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<std::path::PathBuf>,
+    /// Move the log file to PATH.1 once it reaches this size and start a new one
+    #[arg(long, global = true, default_value_t = 10, value_name = "MB", value_parser = clap::value_parser!(u64).range(1..))]
+    log_file_max_mb: u64,
+}
+
+/// How `get` and `list` print their results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// One line per value
+    Plain,
+    /// Aligned columns with one row per GPU
+    Table,
+    Json,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Sets GPU parameters like frequency offset and power limit
     Set {
-        /// GPU index
-        #[arg(short, long)]
-        index: u32,
+        /// GPU index, UUID or PCI bus ID, or several as a list such as `0,2,3`
+        /// or `0-3` (optional on single-GPU systems)
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: Option<GpuSelectors>,
+
+        /// Print the changes that would be made without applying them
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+        /// Apply core and memory offsets beyond the safety limit
+        #[arg(long, default_value_t = false)]
+        force: bool,
+        /// Safety limit for the magnitude of core and memory offsets in MHz
+        /// [default: 2000]
+        #[arg(long, value_name = "MHZ", conflicts_with = "profile")]
+        max_offset: Option<u32>,
+        /// Apply risky values without asking for confirmation
+        #[arg(short, long, default_value_t = false)]
+        yes: bool,
+        /// Restore the previous values if any parameter fails to apply
+        #[arg(long, default_value_t = false)]
+        atomic: bool,
+        /// Raise the core clock offset gradually instead of in one jump
+        #[arg(long, default_value_t = false)]
+        ramp: bool,
+        /// Core clock offset change per ramp step in MHz
+        #[arg(long, default_value_t = 50, requires = "ramp", value_parser = clap::value_parser!(u32).range(1..))]
+        ramp_step: u32,
+        /// Delay between ramp steps in milliseconds
+        #[arg(long, default_value_t = 500, requires = "ramp")]
+        ramp_delay_ms: u64,
+        /// Watch the GPU after applying and reset it if it hangs or overheats
+        #[arg(long, default_value_t = false)]
+        watch_stability: bool,
+        /// How long to watch the GPU for, in seconds
+        #[arg(long, default_value_t = 300, requires = "watch_stability")]
+        watch_duration: u64,
+        /// How long queries may keep failing before the GPU is considered hung, in seconds
+        #[arg(long, default_value_t = 10, requires = "watch_stability")]
+        watch_timeout: u64,
+        /// Temperature in °C above which the settings are reverted
+        #[arg(long, default_value_t = 90, requires = "watch_stability")]
+        watch_max_temp: u32,
+        /// Wait for the GPU to become idle before applying
+        #[arg(long, default_value_t = false)]
+        wait_idle: bool,
+        /// GPU utilization in percent below which the GPU counts as idle
+        #[arg(long, default_value_t = 10, requires = "wait_idle")]
+        idle_threshold: u32,
+        /// How long to wait for the GPU to become idle, in seconds
+        #[arg(long, default_value_t = 600, requires = "wait_idle")]
+        idle_timeout: u64,
+        /// Apply this GPU's settings from a named profile in the config file
+        #[arg(long, group = "Sets", conflicts_with_all = [
+            "freq_offset", "mem_offset", "power_limit", "power_delta", "min_clock", "min_pstate",
+            "min_mem_clock", "fan_control", "voltage_offset", "vf_points", "compute_mode",
+            "persistence_mode", "target_temp_min", "target_temp_max", "reset_target_temp",
+            "max_temp_limit", "reset_clocks", "lock_clock", "app_clock", "app_mem_clock",
+            "reset_app_clocks",
+        ])]
+        profile: Option<String>,
 
         #[command(flatten)]
         sets: Sets,
     },
     /// Gets GPU parameters
     Get {
-        /// GPU index
+        /// GPU index, UUID or PCI bus ID (optional on single-GPU systems)
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: Option<GpuSelector>,
+        /// Get the parameters of every GPU, ignoring NVIDIA_OC_INDEX
+        #[arg(long, default_value_t = false)]
+        all: bool,
+        /// Also print the clock/voltage (V/F) curve points, the clock range
+        /// and offset of each P-state
+        #[arg(long, default_value_t = false)]
+        vf_curve: bool,
+        /// Also print the ECC memory error counters
+        #[arg(long, default_value_t = false)]
+        ecc: bool,
+        /// Also print the memory and core clocks the GPU can be locked to
+        #[arg(long, default_value_t = false)]
+        supported_clocks: bool,
+        /// How to print the status (`--json` is the same as `json`)
+        #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+        output_format: OutputFormat,
+    },
+    /// Lists all GPUs with their indices
+    List {
+        /// How to print the GPUs (`--json` is the same as `json`)
+        #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+        output_format: OutputFormat,
+    },
+    /// Prints the driver, NVML and VBIOS versions for bug reports
+    Info {
+        /// GPU index, UUID or PCI bus ID (defaults to all GPUs)
         #[arg(short, long)]
-        index: u32,
+        index: Option<GpuSelector>,
     },
+    /// Resets GPU parameters to their defaults
+    Reset {
+        /// GPU index, UUID or PCI bus ID
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: GpuSelector,
+    },
+    /// Restores the values the last `set` replaced
+    Undo,
+    /// Steps the core clock offset up to find the highest stable value
+    Benchmark {
+        /// GPU index, UUID or PCI bus ID
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: GpuSelector,
+        /// First core clock offset to test, in MHz
+        #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+        start: i32,
+        /// Last core clock offset to test, in MHz
+        #[arg(long, allow_hyphen_values = true)]
+        end: i32,
+        /// Increment between offsets, in MHz
+        #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u32).range(1..))]
+        step: u32,
+        /// How long to hold each offset, in seconds
+        #[arg(long, default_value_t = 30)]
+        dwell: u64,
+        /// Test offsets beyond the safety limit
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Applies two profiles in turn and compares the GPU's telemetry under each
+    BenchCompare {
+        /// GPU index, UUID or PCI bus ID (optional on single-GPU systems)
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: Option<GpuSelector>,
+        /// Profile to apply first
+        first: String,
+        /// Profile to apply second
+        second: String,
+        /// How long to record each profile, in seconds
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        duration: u64,
+        /// How long to wait after applying a profile before recording, in seconds
+        #[arg(long, default_value_t = 10)]
+        settle: u64,
+        /// Interval between readings in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Apply core and memory offsets beyond the safety limit
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Continuously prints GPU telemetry until interrupted
+    Monitor {
+        /// GPU index, UUID or PCI bus ID
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: GpuSelector,
+        /// Interval between readings in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Reset the GPU to its defaults when it reports an XID error
+        #[arg(long, default_value_t = false)]
+        revert_on_xid: bool,
+        /// Also append each reading to this CSV file
+        #[arg(long, value_name = "PATH")]
+        csv: Option<std::path::PathBuf>,
+        /// Start a new CSV file once it reaches this size, keeping the previous one as PATH.1
+        #[arg(long, value_name = "MB", requires = "csv", value_parser = clap::value_parser!(u64).range(1..))]
+        csv_max_mb: Option<u64>,
+        /// How many of the latest readings the power, temperature and clock
+        /// statistics printed on exit cover
+        #[arg(long, default_value_t = monitor::DEFAULT_STATS_WINDOW, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        stats_window: usize,
+        /// Every this many readings under load, print the core clock per watt
+        /// and how it changed since the previous window
+        #[arg(long, value_name = "READINGS", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        efficiency_window: Option<usize>,
+    },
+    /// Watches the GPU under an external load and reports how it held up
+    Stress {
+        /// GPU index, UUID or PCI bus ID
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: GpuSelector,
+        /// How long to watch the GPU, in seconds
+        #[arg(long, default_value_t = 300)]
+        duration: u64,
+        /// Interval between readings in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+    },
+    /// Serves GPU metrics for Prometheus over HTTP
+    Export {
+        /// GPU index, UUID or PCI bus ID
+        #[arg(short, long, env = "NVIDIA_OC_INDEX", required_unless_present = "all")]
+        index: Option<GpuSelector>,
+        /// Export every GPU, labelled with its index, ignoring NVIDIA_OC_INDEX
+        #[arg(long, default_value_t = false)]
+        all: bool,
+        /// Address to listen on; 0.0.0.0 for every interface
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: std::net::IpAddr,
+        /// Port to listen on
+        #[arg(long, default_value_t = 9835)]
+        port: u16,
+    },
+    /// Draws the V/F curve and the configured fan curve of a GPU as a PNG
+    /// image
+    Plot {
+        /// GPU index, UUID or PCI bus ID (optional on single-GPU systems)
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: Option<GpuSelector>,
+        /// File to write the PNG image to
+        #[arg(short, long, default_value = "curves.png")]
+        output: String,
+    },
+    /// Shows a terminal dashboard with live graphs and power/offset controls
+    Tui {
+        /// GPU index, UUID or PCI bus ID
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: GpuSelector,
+        /// Interval between readings in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+    },
+    /// Applies the config file and keeps driving the configured fan curves and
+    /// auto overclocking
+    Daemon {
+        /// Interval between fan curve and auto overclocking updates in milliseconds
+        #[arg(long, default_value_t = 2000)]
+        interval_ms: u64,
+        /// Reset a GPU to its defaults when it reports an XID error
+        #[arg(long, default_value_t = false)]
+        revert_on_xid: bool,
+        /// Profile to apply while on AC power (defaults to the top-level settings)
+        #[arg(long)]
+        profile_on_ac: Option<String>,
+        /// Profile to apply while on battery (defaults to the top-level settings)
+        #[arg(long)]
+        profile_on_battery: Option<String>,
+        /// Directory listing the power supplies
+        #[arg(long, default_value = power_supply::DEFAULT_PATH)]
+        power_supply_path: std::path::PathBuf,
+        /// Apply the settings again when a GPU's clock offsets revert to zero,
+        /// as after suspend/resume or a driver reload
+        #[arg(long, default_value_t = false)]
+        watch_config: bool,
+        /// Leave the fans and clock offsets as they are when stopped, instead
+        /// of restoring automatic fan control and zero offsets
+        #[arg(long, default_value_t = false)]
+        keep_on_exit: bool,
+    },
+    /// Compares the live GPU settings with the config file
+    Diff {
+        /// GPU index, UUID or PCI bus ID (defaults to every GPU in the config)
+        #[arg(short, long)]
+        index: Option<GpuSelector>,
+        /// Config file to compare with, defaults to the one given with --file
+        file: Option<String>,
+    },
+    /// Writes the settings the GPU is running with as a config file entry,
+    /// into the profile given with --profile if any
+    Save {
+        /// GPU index, UUID or PCI bus ID (optional on single-GPU systems)
+        #[arg(short, long, env = "NVIDIA_OC_INDEX")]
+        index: Option<GpuSelector>,
+        /// Save every GPU, ignoring NVIDIA_OC_INDEX
+        #[arg(long, default_value_t = false)]
+        all: bool,
+        /// Config file to add the entries to, replacing those of the same
+        /// GPUs (prints a new JSON config file if omitted)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Lists the profiles in the config file and what each of them sets
+    Profiles {
+        /// Config file to read, defaults to the one given with --file
+        file: Option<String>,
+    },
+    /// Checks the config file against the GPUs without applying anything
+    Validate {
+        /// Config file to check, defaults to the one given with --file
+        file: Option<String>,
+    },
+    /// Installs a systemd service that applies the config file at boot
+    InstallService {
+        /// Where to write the unit file
+        #[arg(long, default_value = "/etc/systemd/system/zelos.service")]
+        unit_path: String,
+    },
+    /// Opens the GTK4 GUI (same as --gui)
+    Gui,
     /// Generate shell completion script
     Completion {
-        /// The shell to generate the script for
-        #[arg(value_enum)]
-        shell: Shell,
+        /// The shell to generate the script for (detected from $SHELL with --install)
+        #[arg(value_enum, required_unless_present = "install")]
+        shell: Option<Shell>,
+        /// Write the script to where the shell loads completions from
+        #[arg(long, default_value_t = false)]
+        install: bool,
     },
 }
 
-#[derive(Args, Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-#[group(required = true, multiple = true)]
-struct Sets {
-    /// GPU frequency offset
-    #[arg(short, long, allow_hyphen_values = true)]
-    freq_offset: Option<i32>,
-    /// GPU memory frequency offset
-    #[arg(long, allow_hyphen_values = true)]
-    mem_offset: Option<i32>,
-    /// GPU power limit in milliwatts
-    #[arg(short, long)]
-    power_limit: Option<u32>,
-    /// GPU min clock
-    #[arg(long, requires = "max_clock")]
-    min_clock: Option<u32>,
-    /// GPU max clock
-    #[arg(long, requires = "min_clock")]
-    max_clock: Option<u32>,
-    /// GPU min memory clock
-    #[arg(long, requires = "max_mem_clock")]
-    min_mem_clock: Option<u32>,
-    /// GPU max memory clock
-    #[arg(long, requires = "min_mem_clock")]
-    max_mem_clock: Option<u32>,
-}
-
-impl Sets {
-    fn apply(&self, device: &mut Device) {
-        if let Some(freq_offset) = self.freq_offset {
-            device
-                .set_gpc_clock_vf_offset(freq_offset)
-                .expect("Failed to set GPU frequency offset");
-        }
-
-        if let Some(mem_offset) = self.mem_offset {
-            device
-                .set_mem_clock_vf_offset(mem_offset)
-                .expect("Failed to set GPU memory frequency offset");
-        }
-
-        if let Some(limit) = self.power_limit {
-            device
-                .set_power_management_limit(limit)
-                .expect("Failed to set GPU power limit");
-        }
-
-        if let (Some(min_clock), Some(max_clock)) = (self.min_clock, self.max_clock) {
-            device
-                .set_gpu_locked_clocks(
-                    nvml_wrapper::enums::device::GpuLockedClocksSetting::Numeric {
-                        min_clock_mhz: min_clock,
-                        max_clock_mhz: max_clock,
-                    },
-                )
-                .expect("Failed to set GPU min and max clocks");
-        }
-
-        if let (Some(min_mem_clock), Some(max_mem_clock)) = (self.min_mem_clock, self.max_mem_clock)
-        {
-            device
-                .set_mem_locked_clocks(min_mem_clock, max_mem_clock)
-                .expect("Failed to set GPU min and max memory clocks");
-        }
-    }
-}
-
-#[derive(Deserialize)]
-struct Config {
-    sets: HashMap<u32, Sets>,
-}
-
-fn main() {
+fn main() -> ExitCode {
     // Allow launching the GUI via --gui even if clap parsing fails in some cases.
     // Check raw args first and run the GUI immediately if requested.
     let raw_args: Vec<String> = std::env::args().collect();
@@ -128,7 +486,7 @@ fn main() {
         let mut cmd = Cli::command();
         let _ = cmd.print_help();
         println!();
-        return;
+        return ExitCode::SUCCESS;
     }
     // GUI feature marker (used for conditional compilation checks)
     #[cfg(feature = "gui")]
@@ -140,7 +498,8 @@ fn main() {
         #[cfg(feature = "gui")]
         {
             // find file arg if present
-            let mut file_arg = "/etc/zelos.json".to_string();
+            let mut file_arg =
+                std::env::var("NVIDIA_OC_CONFIG").unwrap_or_else(|_| "/etc/zelos.json".to_string());
             let mut it = raw_args.iter();
             while let Some(s) = it.next() {
                 if s == "--file" || s == "-f" {
@@ -152,12 +511,12 @@ fn main() {
                 }
             }
             gui_gtk::run(&file_arg);
-            return;
+            return ExitCode::SUCCESS;
         }
         #[cfg(not(feature = "gui"))]
         {
-            eprintln!("GUI feature not enabled in this build. Rebuild with `--features gui`.");
-            std::process::exit(1);
+            eprintln!("{}", gui_unavailable());
+            return Failure::Other.into();
         }
     }
 
@@ -178,93 +537,1647 @@ fn main() {
             }
         }
         let status = cmd.status().expect("Failed to spawn GUI child");
-        std::process::exit(status.code().unwrap_or(0));
+        return ExitCode::from(status.code().unwrap_or(0) as u8);
     }
 
     let cli = Cli::parse();
+    init_logger(&cli);
+    if let Some(marker) = &cli.escalated {
+        match escalation_marker(marker) {
+            Ok(()) => {
+                let _ = std::fs::remove_file(marker);
+            }
+            Err(e) => warn!("Not removing {}: {}", marker.display(), e),
+        }
+    }
+    zelos::set_retries(cli.retry);
+    zelos::set_json_errors(cli.json);
+    zelos::set_jobs(cli.jobs.unwrap_or(0));
+    if let Some(seconds) = cli.wait_for_driver {
+        zelos::set_driver_wait(std::time::Duration::from_secs(seconds));
+    }
 
+    let status = match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if cli.json && !zelos::json_error_printed() {
+                let message = REPORTED
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take()
+                    .unwrap_or_else(|| failure.message().to_string());
+                print_json_error(&message, failure.code(), None, None);
+            }
+            failure.into()
+        }
+    };
+    close_log_file();
+    status
+}
+
+/// Runs the command given on the command line. Failures are logged where
+/// they happen and returned for the exit status.
+fn run(cli: &Cli) -> Result<(), Failure> {
     match &cli.command {
-        Some(Commands::Set { index, sets }) => {
-            escalate_permissions().expect("Failed to escalate permissions");
+        Some(Commands::Set {
+            index,
+            dry_run,
+            force,
+            max_offset,
+            yes,
+            atomic,
+            ramp,
+            ramp_step,
+            ramp_delay_ms,
+            watch_stability,
+            watch_duration,
+            watch_timeout,
+            watch_max_temp,
+            wait_idle,
+            idle_threshold,
+            idle_timeout,
+            profile,
+            sets,
+        }) => {
+            let sets = &Sets {
+                max_offset: max_offset.or(sets.max_offset),
+                ..sets.clone()
+            };
+            if let Some(offset) = sets.voltage_offset {
+                return Err(Failure::Usage.report(
+                    &format!("Invalid value '{}' for '--voltage-offset'", offset),
+                    zelos::VOLTAGE_OFFSET_UNSUPPORTED,
+                ));
+            }
+            // A dry run reports the offsets it would reject instead.
+            if !*dry_run {
+                check_offsets(sets, *force)?;
+            }
+            let indices = match index {
+                Some(selectors) => selectors.0.clone(),
+                None => vec![single_gpu(&init_nvml()?)?],
+            };
+            if *watch_stability && indices.len() > 1 {
+                error!("--watch-stability can only watch a single GPU");
+                return Err(Failure::Other);
+            }
+            let config;
+            let gpus: Vec<(&GpuSelector, &Sets)> = match profile {
+                Some(name) => {
+                    // Escalating runs the program again, which has to happen
+                    // before the piped config is consumed.
+                    if cli.config_stdin && !*dry_run {
+                        escalate_permissions(cli)?;
+                    }
+                    config = load_config(&config_paths(cli))?;
+                    let gpus = config
+                        .profile(Some(name))
+                        .map_err(|e| Failure::Config.report("Invalid profile", e))?;
+                    indices
+                        .iter()
+                        .map(|index| match gpus.get(index) {
+                            Some(sets) => Ok((index, sets)),
+                            None => {
+                                error!("Profile `{}` has no settings for GPU {}", name, index);
+                                Err(Failure::Config)
+                            }
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                None => indices.iter().map(|index| (index, sets)).collect(),
+            };
+            let several = gpus.len() > 1;
 
-            sudo2::escalate_if_needed()
-                .or_else(|_| sudo2::doas())
-                .or_else(|_| sudo2::pkexec())
-                .expect("Failed to escalate privileges");
+            if *dry_run {
+                let nvml = init_nvml()?;
+                for (i, (index, sets)) in gpus.iter().enumerate() {
+                    let device = device(&nvml, index)?;
+                    if several {
+                        if i > 0 {
+                            println!();
+                        }
+                        println!("GPU {}:", index);
+                    }
+                    sets.preview(&device, *force);
+                }
+                return Ok(());
+            }
 
-            let nvml = Nvml::init().expect("Failed to initialize NVML");
+            escalate_permissions(cli)?;
 
-            let mut device = nvml.device_by_index(*index).expect("Failed to get GPU");
+            let nvml = init_nvml()?;
 
-            sets.apply(&mut device);
-            println!("Successfully set GPU parameters.");
-        }
-        Some(Commands::Get { index }) => {
-            let nvml = Nvml::init().expect("Failed to initialize NVML");
-            let device = nvml.device_by_index(*index).expect("Failed to get GPU");
+            // GPUs an entry does not match are left out before their values
+            // are saved, so `undo` does not touch GPUs `set` never changed.
+            let mut devices = Vec::new();
+            for (index, sets) in &gpus {
+                let device = device(&nvml, index)?;
+                if let Err(e) = sets.check_gpu(&device) {
+                    warn!("GPU {}: {}, skipping", index, e);
+                    continue;
+                }
+                devices.push((*index, *sets, device));
+            }
+
+            let risks: Vec<String> = devices
+                .iter()
+                .flat_map(|(index, sets, device)| {
+                    sets.risks(device)
+                        .into_iter()
+                        .map(move |risk| match several {
+                            true => format!("GPU {}: {}", index, risk),
+                            false => risk,
+                        })
+                })
+                .collect();
+            if !*yes && !confirm(&risks) {
+                info!("Aborted.");
+                return Err(Failure::Other);
+            }
 
-            let freq_offset = device.gpc_clock_vf_offset();
-            match freq_offset {
-                Ok(freq_offset) => println!("GPU core clock offset: {} MHz", freq_offset),
-                Err(e) => eprintln!("Failed to get GPU core clock offset: {:?}", e),
+            let ramp = ramp.then_some(Ramp {
+                step: *ramp_step,
+                delay: std::time::Duration::from_millis(*ramp_delay_ms),
+            });
+            if *wait_idle {
+                let wait = watchdog::IdleWait {
+                    threshold: *idle_threshold,
+                    timeout: std::time::Duration::from_secs(*idle_timeout),
+                };
+                for (index, _, device) in &devices {
+                    if !wait.run(device) {
+                        warn!("GPU {} did not become idle, applying anyway.", index);
+                    }
+                }
             }
 
-            let mem_offset = device.mem_clock_vf_offset();
-            match mem_offset {
-                Ok(mem_offset) => println!("GPU memory clock offset: {} MHz", mem_offset),
-                Err(e) => eprintln!("Failed to get GPU memory clock offset: {:?}", e),
+            let mut snapshots = HashMap::new();
+            for (index, sets, device) in &devices {
+                match sets.snapshot(device) {
+                    Ok(snapshot) => {
+                        snapshots.insert((*index).clone(), snapshot);
+                    }
+                    Err(ApplyError::Snapshot { parameter, error }) => warn!(
+                        "GPU {}: Failed to read the current {}, `undo` will not restore this GPU: {}",
+                        index, parameter, error
+                    ),
+                    Err(e) => warn!("GPU {}: {}", index, e),
+                }
+            }
+            // With nothing to change, the values of the last `set` are kept.
+            let saved = match devices.is_empty() {
+                true => Ok(()),
+                false => undo::save(Path::new(undo::STATE_FILE), &snapshots),
+            };
+            if let Err(e) = saved {
+                warn!(
+                    "Failed to save the previous GPU parameters to {}: {}",
+                    undo::STATE_FILE,
+                    e
+                );
             }
 
-            let power_limit = device.enforced_power_limit();
-            match power_limit {
-                Ok(power_limit) => println!("GPU power limit: {} W", power_limit / 1000),
-                Err(e) => eprintln!("Failed to get GPU power limit: {:?}", e),
+            // Each GPU is configured on its own thread with its own handle
+            let jobs: Vec<_> = devices
+                .iter()
+                .map(|(index, sets, _)| (*index, *sets))
+                .collect();
+            let failures = zelos::in_parallel(&jobs, |(index, sets)| {
+                let mut device = match device(&nvml, index) {
+                    Ok(device) => device,
+                    Err(failure) => return Some(failure),
+                };
+                let result = if *atomic {
+                    sets.apply_atomic(&mut device, *force, ramp)
+                } else {
+                    sets.apply_with_ramp(&mut device, *force, ramp)
+                };
+                let e = result.err()?;
+                error!("GPU {}: {}", index, e);
+                if cli.json {
+                    print_json_error(&e.to_string(), e.code(), e.parameter(), Some(index));
+                }
+                Some(apply_failure(&e))
+            });
+            if let Some(failure) = failures.into_iter().flatten().last() {
+                return Err(failure);
+            }
+            println!("Successfully set GPU parameters.");
+
+            if let Some((_, _, device)) = devices.first_mut().filter(|_| *watch_stability) {
+                let watch = watchdog::StabilityWatch {
+                    duration: std::time::Duration::from_secs(*watch_duration),
+                    timeout: std::time::Duration::from_secs(*watch_timeout),
+                    max_temp: *watch_max_temp,
+                };
+                match watch
+                    .run(device)
+                    .map_err(|e| Failure::Other.report("Failed to install signal handlers", e))?
+                {
+                    watchdog::Verdict::Stable => info!("No instability detected."),
+                    watchdog::Verdict::Interrupted => {
+                        info!("Stopped watching, keeping the GPU parameters.")
+                    }
+                    watchdog::Verdict::Reverted => return Err(Failure::Other),
+                }
+            }
+        }
+        Some(Commands::Get {
+            index,
+            all,
+            vf_curve,
+            ecc,
+            supported_clocks,
+            output_format,
+        }) => {
+            let format = if cli.json {
+                OutputFormat::Json
+            } else {
+                *output_format
+            };
+            let nvml = init_nvml()?;
+            let details = status::Details {
+                vf_curve: *vf_curve,
+                ecc: *ecc,
+                supported_clocks: *supported_clocks,
+            };
+
+            let index = match index {
+                _ if *all => None,
+                Some(index) => Some(index.clone()),
+                None => Some(single_gpu(&nvml)?),
+            };
+            let Some(index) = index else {
+                let count = device_count(&nvml)?;
+                let mut statuses = Vec::new();
+                for index in 0..count {
+                    match nvml.device_by_index(index) {
+                        Ok(device) => statuses.push(status::IndexedStatus {
+                            index,
+                            status: GpuStatus::read(&device, &details),
+                        }),
+                        Err(e) => error!("GPU {}: failed to initialize ({:?})", index, e),
+                    }
+                }
+
+                match format {
+                    OutputFormat::Json => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&statuses)
+                            .expect("Failed to serialize GPU status")
+                    ),
+                    OutputFormat::Table => print!("{}", status::IndexedStatus::table(&statuses)),
+                    OutputFormat::Plain => {
+                        for (i, indexed) in statuses.iter().enumerate() {
+                            if i > 0 {
+                                println!();
+                            }
+                            println!("GPU {}:", indexed.index);
+                            indexed.status.print();
+                        }
+                    }
+                }
+                return Ok(());
+            };
+
+            let device = device(&nvml, &index)?;
+            let status = GpuStatus::read(&device, &details);
+            match format {
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&status).expect("Failed to serialize GPU status")
+                ),
+                OutputFormat::Table => {
+                    let status = status::IndexedStatus {
+                        index: device.index().map_err(|e| {
+                            Failure::DeviceNotFound.report("Failed to get GPU index", e)
+                        })?,
+                        status,
+                    };
+                    print!("{}", status::IndexedStatus::table(&[status]));
+                }
+                OutputFormat::Plain => status.print(),
             }
         }
         None => {
-                let Ok(config_file) = std::fs::read_to_string(cli.file) else {
-                panic!("Configuration file not found and no valid arguments were provided. Run `zelos --help` for more information.");
+            if cli.config_stdin {
+                escalate_permissions(cli)?;
+            }
+            let Ok(files) = read_configs(&config_paths(cli)) else {
+                error!("Configuration file not found and no valid arguments were provided. Run `zelos --help` for more information.");
+                return Err(Failure::Config);
             };
 
-            escalate_permissions().expect("Failed to escalate permissions");
+            escalate_permissions(cli)?;
 
-            let config: Config =
-                serde_json::from_str(&config_file).expect("Invalid configuration file");
+            let config = Config::parse_all(&files)
+                .map_err(|e| Failure::Config.report("Invalid configuration file", e))?;
+            let gpus = config
+                .profile(cli.profile.as_deref())
+                .map_err(|e| Failure::Config.report("Invalid profile", e))?;
 
-            let nvml = Nvml::init().expect("Failed to initialize NVML");
+            let nvml = init_nvml()?;
 
-            for (index, sets) in config.sets {
-                let mut device = nvml.device_by_index(index).expect("Failed to get GPU");
-                sets.apply(&mut device);
+            if !apply_all(gpus, &nvml) {
+                error!("Some GPU parameters could not be set.");
+                return Err(Failure::Nvml);
             }
             println!("Successfully set GPU parameters.");
         }
-        Some(Commands::Completion { shell }) => {
-            generate_completion_script(*shell);
+        Some(Commands::List { output_format }) => {
+            let output_format = match cli.json {
+                true => &OutputFormat::Json,
+                false => output_format,
+            };
+            let nvml = init_nvml()?;
+            let count = device_count(&nvml)?;
+
+            if *output_format == OutputFormat::Plain {
+                for index in 0..count {
+                    match nvml.device_by_index(index) {
+                        Ok(device) => println!("GPU {}: {}", index, describe_device(&device)),
+                        Err(e) => println!("GPU {}: failed to initialize ({:?})", index, e),
+                    }
+                }
+                return Ok(());
+            }
+
+            let mut devices = Vec::new();
+            for index in 0..count {
+                match nvml.device_by_index(index) {
+                    Ok(device) => devices.push(status::DeviceInfo::read(index, &device)),
+                    Err(e) => error!("GPU {}: failed to initialize ({:?})", index, e),
+                }
+            }
+            if *output_format == OutputFormat::Json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&devices).expect("Failed to serialize GPU list")
+                );
+            } else {
+                print!("{}", status::DeviceInfo::table(&devices));
+            }
+        }
+        Some(Commands::Info { index }) => {
+            let nvml = init_nvml()?;
+            let version = |result: Result<String, NvmlError>| {
+                result.unwrap_or_else(|e| format!("unknown ({:?})", e))
+            };
+            println!("Driver version: {}", version(nvml.sys_driver_version()));
+            println!("NVML version: {}", version(nvml.sys_nvml_version()));
+            println!(
+                "CUDA driver version: {}",
+                version(nvml.sys_cuda_driver_version().map(|version| format!(
+                    "{}.{}",
+                    nvml_wrapper::cuda_driver_version_major(version),
+                    nvml_wrapper::cuda_driver_version_minor(version)
+                )))
+            );
+
+            let devices = match index {
+                Some(index) => vec![index.device(&nvml)],
+                None => {
+                    let count = device_count(&nvml)?;
+                    (0..count)
+                        .map(|index| nvml.device_by_index(index))
+                        .collect()
+                }
+            };
+            for device in devices {
+                match device.and_then(|device| Ok((device.index()?, device))) {
+                    Ok((index, device)) => {
+                        println!("GPU {}: {}", index, describe_device(&device));
+                        println!("  VBIOS version: {}", version(device.vbios_version()));
+                        if let Some(link) = status::PcieLink::read(&device).describe() {
+                            println!("  PCIe link: {}", link);
+                        }
+                    }
+                    Err(e) => error!("Failed to get GPU: {:?}", e),
+                }
+            }
+        }
+        Some(Commands::Reset { index }) => {
+            escalate_permissions(cli)?;
+
+            let nvml = init_nvml()?;
+            let mut device = device(&nvml, index)?;
+
+            if reset(&mut device) {
+                println!("Successfully reset GPU parameters.");
+            } else {
+                error!("Some GPU parameters could not be reset.");
+                return Err(Failure::Nvml);
+            }
+        }
+        Some(Commands::Undo) => {
+            escalate_permissions(cli)?;
+
+            let snapshots = undo::load(Path::new(undo::STATE_FILE)).map_err(|e| {
+                error!(
+                    "Failed to read the previous GPU parameters from {}: {}",
+                    undo::STATE_FILE,
+                    e
+                );
+                Failure::Other
+            })?;
+            let nvml = init_nvml()?;
+            let mut ok = true;
+            for (index, snapshot) in &snapshots {
+                match index.device(&nvml) {
+                    Ok(mut device) => {
+                        info!("GPU {}:", index);
+                        ok &= snapshot.restore(&mut device);
+                    }
+                    Err(e) => {
+                        error!("GPU {}: Failed to get GPU: {:?}", index, e);
+                        ok = false;
+                    }
+                }
+            }
+            if ok {
+                info!("Successfully restored the previous GPU parameters.");
+            } else {
+                error!("Some GPU parameters could not be restored.");
+                return Err(Failure::Nvml);
+            }
+        }
+        Some(Commands::Benchmark {
+            index,
+            start,
+            end,
+            step,
+            dwell,
+            force,
+        }) => {
+            if start > end {
+                error!("The start offset must not be larger than the end offset.");
+                return Err(Failure::OutOfRange);
+            }
+            let limit = DEFAULT_MAX_OFFSET;
+            if let Some(offset) = [*start, *end]
+                .into_iter()
+                .find(|offset| !force && offset.unsigned_abs() > limit)
+            {
+                let problem = ApplyError::OffsetTooLarge {
+                    parameter: "GPU frequency offset",
+                    key: "freqOffset",
+                    offset,
+                    limit,
+                };
+                error!("{}", problem);
+                return Err(Failure::OutOfRange);
+            }
+
+            escalate_permissions(cli)?;
+
+            let nvml = init_nvml()?;
+            let mut device = device(&nvml, index)?;
+
+            let benchmark = benchmark::Benchmark {
+                start: *start,
+                end: *end,
+                step: *step,
+                dwell: std::time::Duration::from_secs(*dwell),
+            };
+            let results = benchmark
+                .run(&nvml, &mut device)
+                .map_err(|e| Failure::Other.report("Failed to install signal handlers", e))?;
+            benchmark::print_summary(&results);
+        }
+        Some(Commands::BenchCompare {
+            index,
+            first,
+            second,
+            duration,
+            settle,
+            interval_ms,
+            force,
+        }) => {
+            let index = match index {
+                Some(index) => index.clone(),
+                None => single_gpu(&init_nvml()?)?,
+            };
+            if cli.config_stdin {
+                escalate_permissions(cli)?;
+            }
+            let config = load_config(&config_paths(cli))?;
+            let mut profiles = Vec::new();
+            for name in [first, second] {
+                let gpus = config
+                    .profile(Some(name))
+                    .map_err(|e| Failure::Config.report("Invalid profile", e))?;
+                match gpus.get(&index) {
+                    Some(sets) => profiles.push((name.as_str(), sets)),
+                    None => {
+                        error!("Profile `{}` has no settings for GPU {}", name, index);
+                        return Err(Failure::Config);
+                    }
+                }
+            }
+
+            escalate_permissions(cli)?;
+
+            let nvml = init_nvml()?;
+            let mut device = device(&nvml, &index)?;
+
+            let comparison = compare::Comparison {
+                settle: std::time::Duration::from_secs(*settle),
+                duration: std::time::Duration::from_secs(*duration),
+                interval: std::time::Duration::from_millis(*interval_ms),
+                force: *force,
+            };
+            match comparison
+                .run(&mut device, [profiles[0], profiles[1]])
+                .map_err(|e| Failure::Other.report("Failed to install signal handlers", e))?
+            {
+                compare::Outcome::Recorded(recorded) => {
+                    println!();
+                    compare::print([first, second], &recorded);
+                }
+                compare::Outcome::Failed { profile, error } => {
+                    error!("Profile `{}`: {}", profile, error);
+                    return Err(apply_failure(&error));
+                }
+                compare::Outcome::Interrupted => {
+                    info!("Comparison interrupted.");
+                    return Err(Failure::Other);
+                }
+            }
+        }
+        Some(Commands::Monitor {
+            index,
+            interval_ms,
+            revert_on_xid,
+            csv,
+            csv_max_mb,
+            stats_window,
+            efficiency_window,
+        }) => {
+            if *revert_on_xid {
+                escalate_permissions(cli)?;
+            }
+
+            let nvml = init_nvml()?;
+            let device = device(&nvml, index)?;
+
+            let csv = match csv {
+                Some(path) => {
+                    let fans = device.num_fans().unwrap_or(0) as usize;
+                    let max_size = csv_max_mb.map(|mb| mb * 1024 * 1024);
+                    let log = monitor::CsvLog::open(path, fans, max_size).map_err(|e| {
+                        Failure::Other.report(&format!("Failed to open {}", path.display()), e)
+                    })?;
+                    Some(log)
+                }
+                None => None,
+            };
+            monitor::run(
+                &nvml,
+                &device,
+                std::time::Duration::from_millis(*interval_ms),
+                *revert_on_xid,
+                csv,
+                *stats_window,
+                *efficiency_window,
+            )
+            .map_err(|e| Failure::Other.report("Failed to install signal handlers", e))?;
+        }
+        Some(Commands::Stress {
+            index,
+            duration,
+            interval_ms,
+        }) => {
+            let nvml = init_nvml()?;
+            let device = device(&nvml, index)?;
+
+            let report = stress::run(
+                &nvml,
+                &device,
+                std::time::Duration::from_secs(*duration),
+                std::time::Duration::from_millis(*interval_ms),
+            )
+            .map_err(|e| Failure::Other.report("Failed to install signal handlers", e))?;
+            report.print();
+            if !report.stable() {
+                return Err(Failure::Other);
+            }
+        }
+        Some(Commands::Export {
+            index,
+            all,
+            bind,
+            port,
+        }) => {
+            let nvml = init_nvml()?;
+            let index = index.as_ref().filter(|_| !*all);
+            if let Err(e) = exporter::serve(&nvml, index, *bind, *port) {
+                error!("Failed to serve metrics on {}:{}: {}", bind, port, e);
+                return Err(Failure::Other);
+            }
+        }
+        Some(Commands::Plot { index, output }) => {
+            #[cfg(feature = "plot")]
+            {
+                use zelos::plot::{self, Chart};
+
+                let nvml = init_nvml()?;
+                let index = match index {
+                    Some(index) => index.clone(),
+                    None => single_gpu(&nvml)?,
+                };
+                let device = device(&nvml, &index)?;
+
+                let mut charts = Vec::new();
+                match zelos::status::VfPoint::read_curve(&device) {
+                    Ok(points) if !points.is_empty() => charts.push(Chart {
+                        title: format!("GPU {} V/F curve", index),
+                        x_label: "P-state".to_string(),
+                        y_label: "Max core clock with offset (MHz)".to_string(),
+                        points: points
+                            .iter()
+                            .map(|point| {
+                                let clock = i64::from(point.max_clock) + i64::from(point.offset);
+                                (point.pstate.into(), clock as f64)
+                            })
+                            .collect(),
+                        y_range: None,
+                    }),
+                    Ok(_) => info!("GPU {} reports an empty V/F curve", index),
+                    Err(e) => warn!("Leaving out the V/F curve: {}", e),
+                }
+
+                let paths = config_paths(cli).join(" + ");
+                match read_configs(&config_paths(cli)) {
+                    Ok(files) => {
+                        let config = Config::parse_all(&files)
+                            .map_err(|e| Failure::Config.report("Invalid configuration file", e))?;
+                        let gpus = config
+                            .profile(cli.profile.as_deref())
+                            .map_err(|e| Failure::Config.report("Invalid profile", e))?;
+                        match gpus.get(&index).and_then(|sets| sets.fan_curve.as_ref()) {
+                            Some(curve) => charts.push(Chart {
+                                title: format!("GPU {} fan curve", index),
+                                x_label: "Temperature (°C)".to_string(),
+                                y_label: "Fan speed (%)".to_string(),
+                                points: curve
+                                    .iter()
+                                    .map(|(celsius, speed)| {
+                                        (f64::from(*celsius), f64::from(*speed))
+                                    })
+                                    .collect(),
+                                y_range: Some((0.0, 100.0)),
+                            }),
+                            None => info!("{} has no fan curve for GPU {}", paths, index),
+                        }
+                    }
+                    Err(e) => info!("Leaving out the fan curve, failed to read {}: {}", paths, e),
+                }
+
+                if charts.is_empty() {
+                    error!(
+                        "Nothing to draw: no V/F curve and no fan curve for GPU {}",
+                        index
+                    );
+                    return Err(Failure::Other);
+                }
+                plot::render(output.as_ref(), &charts).map_err(|e| {
+                    Failure::Other.report(&format!("Failed to write {}", output), e)
+                })?;
+                info!("Saved the curves of GPU {} to {}.", index, output);
+            }
+            #[cfg(not(feature = "plot"))]
+            {
+                let _ = (index, output);
+                error!("Plot feature not enabled in this build. Rebuild with `--features plot`.");
+                return Err(Failure::Other);
+            }
+        }
+        Some(Commands::Tui { index, interval_ms }) => {
+            #[cfg(feature = "tui")]
+            {
+                escalate_permissions(cli)?;
+
+                let nvml = init_nvml()?;
+                let mut device = device(&nvml, index)?;
+
+                zelos::tui::run(&mut device, std::time::Duration::from_millis(*interval_ms))
+                    .map_err(|e| {
+                        Failure::Other.report("Failed to run the terminal dashboard", e)
+                    })?;
+            }
+            #[cfg(not(feature = "tui"))]
+            {
+                let _ = (index, interval_ms);
+                error!("TUI feature not enabled in this build. Rebuild with `--features tui`.");
+                return Err(Failure::Other);
+            }
+        }
+        Some(Commands::Daemon {
+            interval_ms,
+            revert_on_xid,
+            profile_on_ac,
+            profile_on_battery,
+            power_supply_path,
+            watch_config,
+            keep_on_exit,
+        }) => {
+            if cli.config_stdin {
+                error!("The daemon reloads the config file when it changes and cannot read it from standard input");
+                return Err(Failure::Config);
+            }
+            let [path] = cli.file.as_slice() else {
+                error!("The daemon reloads a single config file and cannot merge several");
+                return Err(Failure::Config);
+            };
+            let config_file = std::fs::read_to_string(path)
+                .map_err(|e| Failure::Config.report("Failed to read configuration file", e))?;
+
+            escalate_permissions(cli)?;
+
+            let config = Config::parse(path, &config_file)
+                .map_err(|e| Failure::Config.report("Invalid configuration file", e))?;
+
+            for name in [profile_on_ac, profile_on_battery].into_iter().flatten() {
+                config
+                    .profile(Some(name))
+                    .map_err(|e| Failure::Config.report("Invalid profile", e))?;
+            }
+            let power = (profile_on_ac.is_some() || profile_on_battery.is_some()).then(|| {
+                daemon::PowerProfiles {
+                    ac: profile_on_ac.clone(),
+                    battery: profile_on_battery.clone(),
+                    supply_path: power_supply_path.clone(),
+                }
+            });
+
+            let nvml = init_nvml()?;
+
+            daemon::run(
+                &nvml,
+                path,
+                config,
+                std::time::Duration::from_millis(*interval_ms),
+                *revert_on_xid,
+                power.as_ref(),
+                *watch_config,
+                *keep_on_exit,
+            )
+            .map_err(|e| Failure::Other.report("Failed to install signal handlers", e))?;
+        }
+        Some(Commands::Diff { index, file }) => {
+            let paths = config_paths_or(cli, file);
+            let config = load_config(&paths)?;
+            let gpus = config
+                .profile(cli.profile.as_deref())
+                .map_err(|e| Failure::Config.report("Invalid profile", e))?;
+            let gpus: Vec<_> = match index {
+                Some(index) => match gpus.get(index) {
+                    Some(sets) => vec![(index, sets)],
+                    None => {
+                        error!("{} has no settings for GPU {}", paths.join(" + "), index);
+                        return Err(Failure::Config);
+                    }
+                },
+                None => gpus.iter().collect(),
+            };
+
+            let nvml = init_nvml()?;
+            let mut in_sync = true;
+            for (index, sets) in gpus {
+                let device = match index.device(&nvml) {
+                    Ok(device) => device,
+                    Err(e) => {
+                        error!("GPU {}: Failed to get GPU: {:?}", index, e);
+                        in_sync = false;
+                        continue;
+                    }
+                };
+                if let Err(e) = sets.check_gpu(&device) {
+                    println!("GPU {}: skipped, {}", index, e);
+                    continue;
+                }
+                let differences = diff::diff(sets, &device);
+                if differences.is_empty() {
+                    println!("GPU {}: matches the config", index);
+                }
+                for difference in &differences {
+                    println!("GPU {}: {}", index, difference);
+                }
+                in_sync &= differences.is_empty();
+            }
+            if !in_sync {
+                return Err(Failure::Other);
+            }
+        }
+        Some(Commands::Save { index, all, output }) => {
+            let nvml = init_nvml()?;
+            let indices = match index {
+                _ if *all => (0..device_count(&nvml)?).map(GpuSelector::Index).collect(),
+                Some(index) => vec![index.clone()],
+                None => vec![single_gpu(&nvml)?],
+            };
+            let mut entries = Vec::new();
+            for index in indices {
+                let device = device(&nvml, &index)?;
+                entries.push((index, capture::capture(&device)));
+            }
+
+            let existing = match output {
+                Some(path) => match std::fs::read_to_string(path) {
+                    Ok(contents) => Some(contents),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => {
+                        return Err(Failure::Config.report(&format!("Failed to read {}", path), e))
+                    }
+                },
+                None => None,
+            };
+            let gpus: Vec<String> = entries.iter().map(|(index, _)| index.to_string()).collect();
+            let uuid = |selector: &GpuSelector| {
+                selector.device(&nvml).and_then(|device| device.uuid()).ok()
+            };
+            let contents = capture::merge(
+                output.as_deref(),
+                existing.as_deref(),
+                entries,
+                cli.profile.as_deref(),
+                |key, index| key == index || uuid(key).is_some_and(|key| Some(key) == uuid(index)),
+            )
+            .map_err(|e| Failure::Config.report("Failed to write the config file", e))?;
+            let Some(path) = output else {
+                print!("{}", contents);
+                return Ok(());
+            };
+            std::fs::write(path, contents)
+                .map_err(|e| Failure::Other.report(&format!("Failed to write {}", path), e))?;
+            info!("Saved the settings of GPU {} to {}.", gpus.join(", "), path);
+        }
+        Some(Commands::Profiles { file }) => {
+            let paths = config_paths_or(cli, file);
+            let config = load_config(&paths)?;
+            if config.profiles.is_empty() {
+                info!("{} defines no profiles.", paths.join(" + "));
+                return Ok(());
+            }
+
+            let mut names: Vec<&String> = config.profiles.keys().collect();
+            names.sort_unstable();
+            for name in names {
+                let mut gpus: Vec<String> = config.profiles[name]
+                    .iter()
+                    .map(|(index, sets)| match sets.parameters().as_slice() {
+                        [] => format!("GPU {} (nothing)", index),
+                        parameters => format!("GPU {} ({})", index, parameters.join(", ")),
+                    })
+                    .collect();
+                gpus.sort_unstable();
+                match gpus.is_empty() {
+                    true => println!("{}: no GPUs", name),
+                    false => println!("{}: {}", name, gpus.join("; ")),
+                }
+            }
+        }
+        Some(Commands::Validate { file }) => {
+            let paths = config_paths_or(cli, file);
+            let config = load_config(&paths)?;
+
+            let nvml = init_nvml()?;
+            if !config.validate(&nvml) {
+                return Err(Failure::OutOfRange);
+            }
+            info!("{} is valid.", paths.join(" + "));
+        }
+        Some(Commands::InstallService { unit_path }) => {
+            if cli.config_stdin {
+                error!("The service applies a config file and cannot read it from standard input");
+                return Err(Failure::Config);
+            }
+            let config_paths = cli
+                .file
+                .iter()
+                .map(|path| {
+                    std::fs::canonicalize(path).map_err(|e| {
+                        Failure::Config.report(&format!("Configuration file {} not found", path), e)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let exe = std::env::current_exe()
+                .map_err(|e| Failure::Other.report("Failed to get the executable path", e))?;
+
+            escalate_permissions(cli)?;
+
+            std::fs::write(unit_path, service_unit(&exe, &config_paths))
+                .map_err(|e| Failure::Other.report("Failed to write the service unit", e))?;
+            let name = std::path::Path::new(unit_path)
+                .file_stem()
+                .map_or("zelos".into(), |stem| stem.to_string_lossy());
+            info!("Wrote {}. Enable it with:", unit_path);
+            info!("  sudo systemctl daemon-reload");
+            info!("  sudo systemctl enable --now {}", name);
+        }
+        Some(Commands::Gui) => {
+            #[cfg(feature = "gui")]
+            {
+                // GTK parses the command line itself, so the GUI runs in a
+                // child without arguments, as with --gui.
+                let exe = std::env::current_exe()
+                    .map_err(|e| Failure::Other.report("Failed to get the executable path", e))?;
+                let status = std::process::Command::new(exe)
+                    .env("ZELOS_GUI_RUN", "1")
+                    .env(
+                        "NVIDIA_OC_CONFIG",
+                        cli.file.last().map_or("", String::as_str),
+                    )
+                    .status()
+                    .map_err(|e| Failure::Other.report("Failed to start the GUI", e))?;
+                if !status.success() {
+                    return Err(Failure::Other);
+                }
+            }
+            #[cfg(not(feature = "gui"))]
+            {
+                error!("{}", gui_unavailable());
+                return Err(Failure::Other);
+            }
+        }
+        Some(Commands::Completion { shell, install }) => {
+            let Some(shell) = shell.or_else(Shell::from_env) else {
+                error!("Could not detect your shell from $SHELL, please name it");
+                return Err(Failure::Other);
+            };
+            if !*install {
+                generate_completion_script(shell, &mut io::stdout());
+                return Ok(());
+            }
+            match install_completion_script(shell) {
+                Ok((path, hint)) => {
+                    info!("Installed {} completions to {}", shell, path.display());
+                    if let Some(hint) = hint {
+                        info!("{}", hint);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to install {} completions: {}", shell, e);
+                    return Err(Failure::Other);
+                }
+            }
         }
     }
+    Ok(())
 }
 
-fn escalate_permissions() -> Result<(), Box<dyn std::error::Error>> {
-    if sudo2::running_as_root() {
-        return Ok(());
+/// Initializes NVML, exiting with an explanation instead of a panic when the
+/// NVIDIA driver is missing or not loaded, e.g. on a hybrid laptop with the
+/// discrete GPU powered off.
+fn init_nvml() -> Result<Nvml, Failure> {
+    match zelos::wait_for_driver("nvmlInit_v2", || zelos::retried("nvmlInit_v2", Nvml::init)) {
+        Ok(nvml) => Ok(nvml),
+        Err(NvmlError::LibloadingError(e)) => {
+            let failure = Failure::DeviceNotFound.report("Failed to load the NVML library", e);
+            error!("Make sure the proprietary NVIDIA driver is installed.");
+            Err(failure)
+        }
+        Err(NvmlError::DriverNotLoaded) => {
+            let failure = Failure::DeviceNotFound.report(
+                "Failed to initialize NVML",
+                "the NVIDIA driver is not loaded",
+            );
+            error!("Make sure the nvidia kernel module is loaded and the GPU is powered on.");
+            Err(failure)
+        }
+        Err(e) => Err(Failure::DeviceNotFound.report("Failed to initialize NVML", e)),
     }
+}
 
-    if which::which("sudo").is_ok() {
-        sudo2::escalate_if_needed()?;
-    } else if which::which("doas").is_ok() {
-        sudo2::doas()?;
-    } else if which::which("pkexec").is_ok() {
-        sudo2::pkexec()?;
+/// Gets the GPU `index`, logging why if there is no such GPU.
+fn device<'nvml>(nvml: &'nvml Nvml, index: &GpuSelector) -> Result<Device<'nvml>, Failure> {
+    index
+        .device(nvml)
+        .map_err(|e| Failure::DeviceNotFound.report(&format!("Failed to get GPU {}", index), e))
+}
+
+/// The number of GPUs in the system.
+fn device_count(nvml: &Nvml) -> Result<u32, Failure> {
+    nvml.device_count()
+        .map_err(|e| Failure::DeviceNotFound.report("Failed to get GPU count", e))
+}
+
+/// Loads the config file at `path`, logging why if it cannot be read or
+/// parsed.
+fn load_config(paths: &[&str]) -> Result<Config, Failure> {
+    let files = paths
+        .iter()
+        .map(|path| {
+            read_config(path)
+                .map(|contents| (*path, contents))
+                .map_err(|e| Failure::Config.report(&format!("Failed to read {}", path), e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Config::parse_all(&files).map_err(|e| Failure::Config.report("Invalid configuration file", e))
+}
+
+/// The exit status for a `Sets` that failed to apply, logging how to get
+/// the permissions if those were missing.
+fn apply_failure(e: &ApplyError) -> Failure {
+    if e.is_permission_denied() {
+        error!("{}", zelos::PERMISSION_DENIED);
+        Failure::Permission
+    } else if e.is_rejected() {
+        Failure::OutOfRange
     } else {
+        Failure::Nvml
+    }
+}
+
+/// Rejects offsets beyond `--max-offset` unless `force` as invalid arguments,
+/// like a malformed argument, before any GPU is looked at.
+fn check_offsets(sets: &Sets, force: bool) -> Result<(), Failure> {
+    let limit = sets.max_offset.unwrap_or(DEFAULT_MAX_OFFSET);
+    for (arg, offset) in [
+        ("--freq-offset", sets.freq_offset),
+        ("--mem-offset", sets.mem_offset),
+    ] {
+        if let Some(offset) = offset.filter(|offset| !force && offset.unsigned_abs() > limit) {
+            return Err(Failure::Usage.report(
+                &format!("Invalid value '{}' for '{}'", offset, arg),
+                format!(
+                    "offsets are limited to ±{} MHz, raise the limit with --max-offset or pass --force",
+                    limit
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The GPU to use when `--index` is omitted: the only one in the system. With
+/// several GPUs, lists them and fails rather than guess.
+fn single_gpu(nvml: &Nvml) -> Result<GpuSelector, Failure> {
+    let count = device_count(nvml)?;
+    match count {
+        0 => {
+            error!("No NVIDIA GPU found.");
+            Err(Failure::DeviceNotFound)
+        }
+        1 => Ok(GpuSelector::Index(0)),
+        _ => {
+            error!("This system has {} GPUs, choose one with --index:", count);
+            for index in 0..count {
+                match nvml.device_by_index(index) {
+                    Ok(device) => error!("  GPU {}: {}", index, describe_device(&device)),
+                    Err(e) => error!("  GPU {}: failed to initialize ({:?})", index, e),
+                }
+            }
+            Err(Failure::Other)
+        }
+    }
+}
+
+/// Explains that this build has no GUI and what rebuilding with it takes,
+/// checking with pkg-config whether the GTK4 and libadwaita development files
+/// it needs are installed.
+#[cfg(not(feature = "gui"))]
+fn gui_unavailable() -> String {
+    let message = "The GUI is not included in this build. Rebuild with `cargo build --release --features gui` to enable it";
+    let installed = std::process::Command::new("pkg-config")
+        .args(["--exists", "gtk4", "libadwaita-1"])
+        .status()
+        .map(|status| status.success());
+    match installed {
+        Ok(true) => format!("{}.", message),
+        Ok(false) => format!(
+            "{}, after installing the GTK4 and libadwaita development packages \
+             (e.g. libgtk-4-dev and libadwaita-1-dev), which pkg-config cannot find.",
+            message
+        ),
+        Err(_) => format!(
+            "{}; this needs the GTK4 and libadwaita development packages and pkg-config.",
+            message
+        ),
+    }
+}
+
+/// Sends log messages to stderr, and with `--log-file` to that file too along
+/// with everything printed on standard output. Info, warning and error
+/// messages are printed as plain lines like the rest of the output;
+/// `RUST_LOG` overrides the level chosen with `--verbose` and `--quiet`.
+fn init_logger(cli: &Cli) {
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    let console = env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            log::Level::Error | log::Level::Info => writeln!(buf, "{}", record.args()),
+            log::Level::Warn => writeln!(buf, "Warning: {}", record.args()),
+            level => writeln!(buf, "[{}] {}", level, record.args()),
+        })
+        .build();
+    let filter = console.filter();
+
+    let logger: Box<dyn log::Log> = match &cli.log_file {
+        Some(path) => {
+            *LOG_FILE.lock().unwrap_or_else(PoisonError::into_inner) =
+                Some(LogFile::new(path, cli.log_file_max_mb * 1024 * 1024));
+            Box::new(TeeLogger { console })
+        }
+        None => Box::new(console),
+    };
+    if log::set_boxed_logger(logger).is_ok() {
+        log::set_max_level(filter);
+    }
+
+    // The dashboard draws on the terminal, which a pipe is not.
+    #[cfg(feature = "tui")]
+    if matches!(cli.command, Some(Commands::Tui { .. })) {
+        return;
+    }
+    if cli.log_file.is_some() {
+        match StdoutTee::start() {
+            Ok(tee) => *STDOUT_TEE.lock().unwrap_or_else(PoisonError::into_inner) = Some(tee),
+            Err(e) => warn!("Failed to copy standard output to the log file: {}", e),
+        }
+    }
+}
+
+/// The `--log-file`, shared by the logger and the copy of standard output.
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// Copies standard output to `LOG_FILE` while set, see `StdoutTee`.
+static STDOUT_TEE: Mutex<Option<StdoutTee>> = Mutex::new(None);
+
+/// Stops writing to the log file, leaving it to the escalated copy of the
+/// program, or at exit. Standard output is restored and whatever was printed
+/// so far is copied to the file first.
+fn close_log_file() {
+    let tee = STDOUT_TEE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    if let Some(tee) = tee {
+        tee.stop();
+    }
+    LOG_FILE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+}
+
+/// Appends log messages to a file with a timestamp and level, moving the file
+/// to `<path>.1` once it reaches `max_size` bytes. The file is opened when
+/// the first message is written, so a process that only runs the program
+/// again as root never touches it.
+struct LogFile {
+    path: std::path::PathBuf,
+    file: Option<std::fs::File>,
+    max_size: u64,
+    /// Set once the file failed to open, to stop trying
+    failed: bool,
+}
+
+impl LogFile {
+    fn new(path: &Path, max_size: u64) -> LogFile {
+        LogFile {
+            path: path.to_path_buf(),
+            file: None,
+            max_size,
+            failed: false,
+        }
+    }
+
+    fn open(path: &Path) -> io::Result<std::fs::File> {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+    }
+
+    /// Writes `message` with `level`, warning on the console the first time
+    /// the file cannot be opened. The logger cannot be used for that while
+    /// it is writing to the file itself.
+    fn write(&mut self, level: &str, message: impl fmt::Display) -> io::Result<()> {
+        if self.failed {
+            return Ok(());
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => match LogFile::open(&self.path) {
+                Ok(file) => self.file.insert(file),
+                Err(e) => {
+                    self.failed = true;
+                    eprintln!(
+                        "Warning: Failed to open log file {}: {}, logging to the console only",
+                        self.path.display(),
+                        e
+                    );
+                    return Err(e);
+                }
+            },
+        };
+        if file.metadata()?.len() >= self.max_size {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, &rotated)?;
+            *file = LogFile::open(&self.path)?;
+        }
+        writeln!(
+            file,
+            "{} {:<5} {}",
+            humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
+            level,
+            message
+        )
+    }
+}
+
+/// Writes `message` to the log file, if there is one.
+fn write_log_file(level: &str, message: impl fmt::Display) {
+    // A log file that cannot be written must not stop the program, and the
+    // console already has the message.
+    if let Some(file) = LOG_FILE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_mut()
+    {
+        let _ = file.write(level, message);
+    }
+}
+
+/// Passes log messages on to the console logger and also writes them to the
+/// log file.
+struct TeeLogger {
+    console: env_logger::Logger,
+}
+
+impl log::Log for TeeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.console.matches(record) {
+            return;
+        }
+        self.console.log(record);
+        write_log_file(record.level().as_str(), record.args());
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        if let Some(file) = LOG_FILE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .and_then(|file| file.file.as_mut())
+        {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Copies everything printed on standard output to the log file as `OUT`
+/// lines, by pointing standard output at a pipe that a thread drains into the
+/// original standard output and the file.
+struct StdoutTee {
+    /// The original standard output
+    console: std::os::fd::OwnedFd,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl StdoutTee {
+    fn start() -> io::Result<StdoutTee> {
+        use std::os::fd::{AsFd, AsRawFd};
+
+        let (mut reader, writer) = io::pipe()?;
+        let console = io::stdout().as_fd().try_clone_to_owned()?;
+        let mut output = std::fs::File::from(console.try_clone()?);
+        io::stdout().flush()?;
+        // SAFETY: both descriptors are open for the duration of the call.
+        if unsafe { libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        drop(writer);
+
+        let thread = std::thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            let mut line = Vec::new();
+            loop {
+                let read = match io::Read::read(&mut reader, &mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => read,
+                };
+                // Passed on right away, so prompts without a newline show.
+                let _ = output.write_all(&buffer[..read]);
+                line.extend_from_slice(&buffer[..read]);
+                while let Some(end) = line.iter().position(|byte| *byte == b'\n') {
+                    write_log_file("OUT", String::from_utf8_lossy(&line[..end]));
+                    line.drain(..=end);
+                }
+            }
+            if !line.is_empty() {
+                write_log_file("OUT", String::from_utf8_lossy(&line));
+            }
+        });
+        Ok(StdoutTee { console, thread })
+    }
+
+    /// Points standard output back at the original one and waits until the
+    /// thread has copied everything printed before.
+    fn stop(self) {
+        use std::os::fd::AsRawFd;
+
+        let _ = io::stdout().flush();
+        // SAFETY: both descriptors are open for the duration of the call.
+        // Replacing the pipe closes its last write end, ending the thread.
+        unsafe { libc::dup2(self.console.as_raw_fd(), libc::STDOUT_FILENO) };
+        let _ = self.thread.join();
+    }
+}
+
+/// Stands in for the config file path when the config is read from standard
+/// input. Having no extension, it is parsed as JSON.
+const STDIN_CONFIG: &str = "<stdin>";
+
+/// The config files to merge, just `STDIN_CONFIG` with --config-stdin.
+fn config_paths(cli: &Cli) -> Vec<&str> {
+    if cli.config_stdin {
+        vec![STDIN_CONFIG]
+    } else {
+        cli.file.iter().map(String::as_str).collect()
+    }
+}
+
+/// The config file given to a subcommand, or the ones given with --file.
+fn config_paths_or<'a>(cli: &'a Cli, file: &'a Option<String>) -> Vec<&'a str> {
+    match file {
+        Some(file) => vec![file.as_str()],
+        None => config_paths(cli),
+    }
+}
+
+/// Reads the config files at `paths` as `(path, contents)` pairs.
+fn read_configs<'a>(paths: &[&'a str]) -> io::Result<Vec<(&'a str, String)>> {
+    paths
+        .iter()
+        .map(|path| read_config(path).map(|contents| (*path, contents)))
+        .collect()
+}
+
+/// Reads the config file at `path`, or standard input for `STDIN_CONFIG`.
+fn read_config(path: &str) -> io::Result<String> {
+    if path == STDIN_CONFIG {
+        io::read_to_string(io::stdin())
+    } else {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Asks on the terminal whether to go ahead despite `risks`. Always agrees
+/// when there are none or stdin is not a terminal, so scripts are unaffected.
+fn confirm(risks: &[String]) -> bool {
+    if risks.is_empty() || !io::stdin().is_terminal() {
+        return true;
+    }
+    for risk in risks {
+        warn!("{}", risk);
+    }
+    print!("This may destabilize your GPU — continue? [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// A oneshot systemd unit that applies `config` with `exe` once the NVIDIA
+/// driver has been loaded, waiting for it a while in case it is late.
+fn service_unit(exe: &std::path::Path, configs: &[std::path::PathBuf]) -> String {
+    let files: Vec<String> = configs
+        .iter()
+        .map(|config| format!("--file \"{}\"", config.display()))
+        .collect();
+    format!(
+        "[Unit]
+Description=Zelos Overclocking Service
+After=systemd-modules-load.service nvidia-persistenced.service
+
+[Service]
+Type=oneshot
+RemainAfterExit=yes
+ExecStart=\"{}\" --wait-for-driver 60 {}
+User=root
+
+[Install]
+WantedBy=multi-user.target
+",
+        exe.display(),
+        files.join(" ")
+    )
+}
+
+/// Runs the program again as root through sudo, doas or pkexec and exits
+/// with its status, unless already running as root or `--no-escalate` is
+/// set. Fails if nobody authenticates within `--escalation-timeout`, so
+/// scripts and services without a user at hand do not hang on the prompt.
+fn escalate_permissions(cli: &Cli) -> Result<(), Failure> {
+    run_as_root(cli).map_err(|e| Failure::Permission.report("Failed to escalate permissions", e))
+}
+
+/// Does the work of `escalate_permissions`.
+fn run_as_root(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    if cli.no_escalate || sudo2::running_as_root() {
+        return Ok(());
+    }
+    if sudo2::running_as_suid() {
+        sudo2::escalate_if_needed()?;
+        return Ok(());
+    }
+    if cli.escalated.is_some() {
+        return Err("Still not running as root after escalating privileges".into());
+    }
+
+    let Some(wrapper) = ["sudo", "doas", "pkexec"]
+        .into_iter()
+        .find(|wrapper| which::which(wrapper).is_ok())
+    else {
         return Err("Please install sudo, doas or pkexec and try again. Alternatively, run the program as root.".into());
+    };
+
+    // The escalated copy removes the marker as soon as it starts, which is
+    // how this one learns that authentication is over. The marker lives in a
+    // fresh directory only this process can write to, and the escalated copy
+    // refuses to remove anything else, so `--escalated` cannot be abused to
+    // delete files as root.
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+    let dir = std::env::temp_dir().join(format!(
+        "{}{}-{}",
+        ESCALATION_DIR_PREFIX,
+        std::process::id(),
+        nanos
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let marker = dir.join(ESCALATION_MARKER);
+    if let Err(e) = std::fs::File::create(&marker) {
+        let _ = std::fs::remove_dir(&dir);
+        return Err(e.into());
+    }
+    // Only the escalated copy, which does the work, writes the log file.
+    close_log_file();
+    // sudo's env_reset, doas and pkexec drop the environment, so the
+    // variables standing in for `--file` and `--index` are set again through
+    // env(1).
+    let forwarded: Vec<_> = FORWARDED_ENV
+        .into_iter()
+        .filter_map(|name| {
+            let mut assignment = std::ffi::OsString::from(format!("{}=", name));
+            assignment.push(std::env::var_os(name)?);
+            Some(assignment)
+        })
+        .collect();
+    let mut command = std::process::Command::new(wrapper);
+    if !forwarded.is_empty() {
+        command
+            .arg(which::which("env").unwrap_or_else(|_| "/usr/bin/env".into()))
+            .args(forwarded);
     }
+    let mut child = command
+        .arg(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .arg("--escalated")
+        .arg(&marker)
+        .spawn()?;
+
+    let timeout = std::time::Duration::from_secs(cli.escalation_timeout);
+    let start = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if !marker.exists() {
+            break child.wait()?;
+        }
+        if !timeout.is_zero() && start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = std::fs::remove_file(&marker);
+            let _ = std::fs::remove_dir(&dir);
+            return Err(format!(
+                "Privilege escalation timed out after {} s waiting for {} to authenticate",
+                cli.escalation_timeout, wrapper
+            )
+            .into());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    };
+    let _ = std::fs::remove_file(&marker);
+    let _ = std::fs::remove_dir(&dir);
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Environment variables the escalated copy needs to act on the same config
+/// file and GPU.
+const FORWARDED_ENV: [&str; 2] = ["NVIDIA_OC_CONFIG", "NVIDIA_OC_INDEX"];
 
+/// Prefix of the private directory holding the escalation marker.
+const ESCALATION_DIR_PREFIX: &str = "zelos-escalate-";
+/// Name of the escalation marker inside that directory.
+const ESCALATION_MARKER: &str = "authenticating";
+
+/// Checks that `marker` is an escalation marker created by `run_as_root`: a
+/// regular file with the expected name in a directory that is not a
+/// symlink, is named like the ones `run_as_root` creates, is accessible only
+/// to its owner and is not owned by root.
+fn escalation_marker(marker: &Path) -> Result<(), &'static str> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    if marker.file_name() != Some(std::ffi::OsStr::new(ESCALATION_MARKER)) {
+        return Err("not an escalation marker");
+    }
+    let dir = marker.parent().ok_or("not an escalation marker")?;
+    if !dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(ESCALATION_DIR_PREFIX))
+    {
+        return Err("not in an escalation directory");
+    }
+    let dir_metadata = std::fs::symlink_metadata(dir).map_err(|_| "no escalation directory")?;
+    if !dir_metadata.is_dir()
+        || dir_metadata.permissions().mode() & 0o777 != 0o700
+        || dir_metadata.uid() == 0
+    {
+        return Err("not in a private escalation directory");
+    }
+    let metadata = std::fs::symlink_metadata(marker).map_err(|_| "no escalation marker")?;
+    if !metadata.is_file() {
+        return Err("not a regular file");
+    }
     Ok(())
 }
 
-fn generate_completion_script<G: Generator>(gen: G) {
+fn generate_completion_script<G: Generator>(gen: G, out: &mut dyn Write) {
     let mut cmd = Cli::command();
     let name = cmd.get_name().to_string();
-    generate(gen, &mut cmd, name, &mut io::stdout());
+    generate(gen, &mut cmd, name, out);
+}
+
+/// Writes the completion script for `shell` to the per-user directory the
+/// shell loads completions from. Returns the path written, and what else the
+/// user has to do for the shell to pick it up, if anything.
+fn install_completion_script(
+    shell: Shell,
+) -> io::Result<(std::path::PathBuf, Option<&'static str>)> {
+    let name = Cli::command().get_name().to_string();
+    let home = std::env::var_os("HOME")
+        .map(std::path::PathBuf::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "$HOME is not set"))?;
+    let xdg = |var: &str, fallback: &str| {
+        std::env::var_os(var)
+            .filter(|dir| !dir.is_empty())
+            .map_or_else(|| home.join(fallback), std::path::PathBuf::from)
+    };
+    let (path, hint) = match shell {
+        Shell::Bash => (
+            xdg("XDG_DATA_HOME", ".local/share")
+                .join("bash-completion/completions")
+                .join(&name),
+            None,
+        ),
+        Shell::Zsh => (
+            home.join(".zfunc").join(format!("_{}", name)),
+            Some("Add `fpath+=~/.zfunc` before `compinit` in your ~/.zshrc if it is not there yet"),
+        ),
+        Shell::Fish => (
+            xdg("XDG_CONFIG_HOME", ".config")
+                .join("fish/completions")
+                .join(format!("{}.fish", name)),
+            None,
+        ),
+        Shell::Elvish => (
+            xdg("XDG_CONFIG_HOME", ".config")
+                .join("elvish/lib")
+                .join(format!("{}.elv", name)),
+            Some("Add `use zelos` to your ~/.config/elvish/rc.elv"),
+        ),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no conventional location, add the output of `zelos completion <shell>` to your profile instead",
+            ))
+        }
+    };
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::File::create(&path)?;
+    generate_completion_script(shell, &mut file);
+    Ok((path, hint))
 }
@@ -0,0 +1,486 @@
+use crate::status::{describe_throttle_reasons, throttle_reasons, violation_time};
+use crate::xid::XidWatch;
+use log::{info, warn};
+use nvml_wrapper::enum_wrappers::device::{
+    Clock, PcieUtilCounter, PerformancePolicy, TemperatureSensor,
+};
+use nvml_wrapper::{Device, Nvml};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A single telemetry reading. Fields are `None` when the query failed or is
+/// not supported by the device.
+pub struct Sample {
+    pub temperature: Option<u32>,
+    /// Memory junction temperature, only reported by some GPUs
+    pub memory_temperature: Option<u32>,
+    pub graphics_clock: Option<u32>,
+    pub memory_clock: Option<u32>,
+    pub gpu_utilization: Option<u32>,
+    /// Memory controller utilization in percent
+    pub memory_utilization: Option<u32>,
+    /// NVENC utilization in percent
+    pub encoder_utilization: Option<u32>,
+    /// NVDEC utilization in percent
+    pub decoder_utilization: Option<u32>,
+    /// Power draw in milliwatts
+    pub power_usage: Option<u32>,
+    /// PCIe traffic from the GPU in KB/s
+    pub pcie_tx: Option<u32>,
+    /// PCIe traffic to the GPU in KB/s
+    pub pcie_rx: Option<u32>,
+    pub fan_speeds: Vec<Option<u32>>,
+    pub throttle_reasons: Option<Vec<&'static str>>,
+    /// Time in ns the power limit held the clocks down since the driver was
+    /// loaded
+    pub power_violation_time: Option<u64>,
+    /// Time in ns the temperature held the clocks down since the driver was
+    /// loaded
+    pub thermal_violation_time: Option<u64>,
+}
+
+impl Sample {
+    pub fn read(device: &Device) -> Sample {
+        let fans = device.num_fans().unwrap_or(0);
+        let utilization = device.utilization_rates().ok();
+        Sample {
+            temperature: device.temperature(TemperatureSensor::Gpu).ok(),
+            memory_temperature: crate::nvml_raw::get_memory_temperature(device)
+                .ok()
+                .flatten(),
+            graphics_clock: device.clock_info(Clock::Graphics).ok(),
+            memory_clock: device.clock_info(Clock::Memory).ok(),
+            gpu_utilization: utilization.as_ref().map(|u| u.gpu),
+            memory_utilization: utilization.as_ref().map(|u| u.memory),
+            encoder_utilization: device.encoder_utilization().ok().map(|u| u.utilization),
+            decoder_utilization: device.decoder_utilization().ok().map(|u| u.utilization),
+            power_usage: device.power_usage().ok(),
+            pcie_tx: device.pcie_throughput(PcieUtilCounter::Send).ok(),
+            pcie_rx: device.pcie_throughput(PcieUtilCounter::Receive).ok(),
+            fan_speeds: (0..fans).map(|fan| device.fan_speed(fan).ok()).collect(),
+            throttle_reasons: throttle_reasons(device).ok(),
+            power_violation_time: violation_time(device, PerformancePolicy::Power)
+                .ok()
+                .flatten(),
+            thermal_violation_time: violation_time(device, PerformancePolicy::Thermal)
+                .ok()
+                .flatten(),
+        }
+    }
+}
+
+/// Formats an optional reading, showing `N/A` when it is missing.
+pub fn or_na<T: fmt::Display>(value: Option<T>, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{}{}", value, unit),
+        None => "N/A".to_string(),
+    }
+}
+
+/// Formats a time counted in nanoseconds as a duration such as `1h 2m 5s`,
+/// with milliseconds only below a second.
+pub fn format_nanoseconds(nanoseconds: u64) -> String {
+    let duration = if nanoseconds < 1_000_000_000 {
+        Duration::from_millis(nanoseconds / 1_000_000)
+    } else {
+        Duration::from_secs(nanoseconds / 1_000_000_000)
+    };
+    humantime::format_duration(duration).to_string()
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fans: Vec<String> = self
+            .fan_speeds
+            .iter()
+            .map(|speed| or_na(*speed, "%"))
+            .collect();
+        write!(
+            f,
+            "Temp: {} | Mem temp: {} | Core: {} | Mem: {} | Util: {} | Mem util: {} | Enc: {} | Dec: {} | Power: {} | PCIe tx: {} | PCIe rx: {} | Fans: {} | Throttle: {} | Power capped: {} | Thermal capped: {}",
+            or_na(self.temperature, " °C"),
+            or_na(self.memory_temperature, " °C"),
+            or_na(self.graphics_clock, " MHz"),
+            or_na(self.memory_clock, " MHz"),
+            or_na(self.gpu_utilization, "%"),
+            or_na(self.memory_utilization, "%"),
+            or_na(self.encoder_utilization, "%"),
+            or_na(self.decoder_utilization, "%"),
+            or_na(self.power_usage.map(|mw| format!("{:.1}", mw as f64 / 1000.0)), " W"),
+            or_na(
+                self.pcie_tx
+                    .map(|kbs| format!("{:.1}", kbs as f64 / 1000.0)),
+                " MB/s"
+            ),
+            or_na(
+                self.pcie_rx
+                    .map(|kbs| format!("{:.1}", kbs as f64 / 1000.0)),
+                " MB/s"
+            ),
+            if fans.is_empty() {
+                "N/A".to_string()
+            } else {
+                fans.join(" ")
+            },
+            or_na(
+                self.throttle_reasons
+                    .as_deref()
+                    .map(describe_throttle_reasons),
+                ""
+            ),
+            or_na(self.power_violation_time.map(format_nanoseconds), ""),
+            or_na(self.thermal_violation_time.map(format_nanoseconds), ""),
+        )
+    }
+}
+
+/// Appends samples as timestamped rows to a CSV file, so telemetry can be
+/// lined up with events afterwards.
+pub struct CsvLog {
+    path: PathBuf,
+    file: File,
+    fans: usize,
+    /// Size in bytes at which the file is moved to `<path>.1` and a new one
+    /// is started
+    max_size: Option<u64>,
+}
+
+impl CsvLog {
+    /// Opens `path` for appending, writing the header if the file is new or
+    /// empty. `fans` is the number of fan columns.
+    pub fn open(path: &Path, fans: usize, max_size: Option<u64>) -> io::Result<CsvLog> {
+        let mut log = CsvLog {
+            path: path.to_path_buf(),
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+            fans,
+            max_size,
+        };
+        if log.file.metadata()?.len() == 0 {
+            log.header()?;
+        }
+        Ok(log)
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        let mut header = "timestamp,temperature_c,memory_temperature_c,core_clock_mhz,memory_clock_mhz,power_w,gpu_util_percent,memory_util_percent,encoder_util_percent,decoder_util_percent".to_string();
+        for fan in 0..self.fans {
+            header.push_str(&format!(",fan{}_percent", fan));
+        }
+        writeln!(self.file, "{}", header)
+    }
+
+    /// Appends a row for `sample`, flushed right away so a crash loses
+    /// nothing. Missing readings are left empty.
+    pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        if self
+            .max_size
+            .is_some_and(|max| self.file.metadata().is_ok_and(|meta| meta.len() >= max))
+        {
+            self.rotate()?;
+        }
+
+        let cell = |value: Option<u32>| value.map_or(String::new(), |value| value.to_string());
+        let mut row = [
+            humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            cell(sample.temperature),
+            cell(sample.memory_temperature),
+            cell(sample.graphics_clock),
+            cell(sample.memory_clock),
+            sample
+                .power_usage
+                .map_or(String::new(), |mw| format!("{:.1}", mw as f64 / 1000.0)),
+            cell(sample.gpu_utilization),
+            cell(sample.memory_utilization),
+            cell(sample.encoder_utilization),
+            cell(sample.decoder_utilization),
+        ]
+        .join(",");
+        for fan in 0..self.fans {
+            row.push(',');
+            row.push_str(&cell(sample.fan_speeds.get(fan).copied().flatten()));
+        }
+        writeln!(self.file, "{}", row)?;
+        self.file.flush()
+    }
+
+    /// Moves the current file to `<path>.1`, replacing an older one, and
+    /// starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, &rotated)?;
+        info!(
+            "Moved {} to {}",
+            self.path.display(),
+            rotated.to_string_lossy()
+        );
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.header()
+    }
+}
+
+/// How many readings the statistics printed by `run` cover by default.
+pub const DEFAULT_STATS_WINDOW: usize = 3600;
+
+/// The latest readings of one value, at most `capacity` of them so a long
+/// session does not keep growing.
+struct Window {
+    values: VecDeque<u32>,
+    capacity: usize,
+}
+
+impl Window {
+    fn new(capacity: usize) -> Window {
+        Window {
+            values: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, value: Option<u32>) {
+        let Some(value) = value else {
+            return;
+        };
+        if self.values.len() == self.capacity {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    /// Prints the minimum, average and maximum, with the readings divided by
+    /// `scale` and shown with `precision` decimals.
+    fn print(&self, name: &str, unit: &str, scale: f64, precision: usize) {
+        let (Some(min), Some(max)) = (self.values.iter().min(), self.values.iter().max()) else {
+            println!("{}: no readings", name);
+            return;
+        };
+        let sum: u64 = self.values.iter().map(|value| u64::from(*value)).sum();
+        let average = sum as f64 / self.values.len() as f64;
+        println!(
+            "{} over the last {} readings: min {:.*} {unit}, avg {:.*} {unit}, max {:.*} {unit}",
+            name,
+            self.values.len(),
+            precision,
+            *min as f64 / scale,
+            precision.max(1),
+            average / scale,
+            precision,
+            *max as f64 / scale,
+        );
+    }
+}
+
+/// The first and the latest reading of a counter that only grows.
+#[derive(Default)]
+struct Counter {
+    first: Option<u64>,
+    latest: Option<u64>,
+}
+
+impl Counter {
+    fn push(&mut self, value: Option<u64>) {
+        if self.first.is_none() {
+            self.first = value;
+        }
+        if value.is_some() {
+            self.latest = value;
+        }
+    }
+
+    /// Prints how much the counter, a time in ns, grew between the first and
+    /// the latest reading, nothing if it was never read.
+    fn print(&self, name: &str) {
+        if let (Some(first), Some(latest)) = (self.first, self.latest) {
+            println!(
+                "{} while monitoring: {}",
+                name,
+                format_nanoseconds(latest.saturating_sub(first))
+            );
+        }
+    }
+}
+
+/// Running minimum, average and maximum of the power draw, temperature and
+/// core clock, over a bounded number of the latest readings, and the time the
+/// clocks were held down by the power limit or temperature.
+pub struct Stats {
+    power_usage: Window,
+    temperature: Window,
+    graphics_clock: Window,
+    power_violation_time: Counter,
+    thermal_violation_time: Counter,
+}
+
+impl Stats {
+    pub fn new(window: usize) -> Stats {
+        Stats {
+            power_usage: Window::new(window),
+            temperature: Window::new(window),
+            graphics_clock: Window::new(window),
+            power_violation_time: Counter::default(),
+            thermal_violation_time: Counter::default(),
+        }
+    }
+
+    pub fn add(&mut self, sample: &Sample) {
+        self.power_usage.push(sample.power_usage);
+        self.temperature.push(sample.temperature);
+        self.graphics_clock.push(sample.graphics_clock);
+        self.power_violation_time.push(sample.power_violation_time);
+        self.thermal_violation_time
+            .push(sample.thermal_violation_time);
+    }
+
+    pub fn print(&self) {
+        self.power_usage.print("Power draw", "W", 1000.0, 1);
+        self.temperature.print("Temperature", "°C", 1.0, 0);
+        self.graphics_clock.print("Core clock", "MHz", 1.0, 0);
+        self.power_violation_time
+            .print("Time throttled by the power limit");
+        self.thermal_violation_time
+            .print("Time throttled by the temperature");
+    }
+}
+
+/// GPU utilization in percent from which a reading counts towards
+/// `Efficiency`. Idle clocks and power say nothing about efficiency.
+pub const EFFICIENCY_MIN_UTILIZATION: u32 = 50;
+
+/// Core clock per watt of power draw over consecutive windows of readings
+/// taken under load. NVML cannot count frames, so the clock the GPU sustains
+/// at a given power stands in for performance per watt when comparing
+/// undervolts, clock locks and power limits.
+pub struct Efficiency {
+    size: usize,
+    /// Sums of the core clock in MHz and the power draw in mW
+    clock: u64,
+    power: u64,
+    readings: usize,
+    previous: Option<f64>,
+}
+
+impl Efficiency {
+    /// Reports every `size` readings under load.
+    pub fn new(size: usize) -> Efficiency {
+        Efficiency {
+            size,
+            clock: 0,
+            power: 0,
+            readings: 0,
+            previous: None,
+        }
+    }
+
+    /// Adds a reading, printing the efficiency of the window and how it
+    /// changed since the previous one when the window is full.
+    pub fn add(&mut self, sample: &Sample) {
+        let (Some(clock), Some(power), Some(utilization)) = (
+            sample.graphics_clock,
+            sample.power_usage.filter(|mw| *mw > 0),
+            sample.gpu_utilization,
+        ) else {
+            return;
+        };
+        if utilization < EFFICIENCY_MIN_UTILIZATION {
+            return;
+        }
+        self.clock += u64::from(clock);
+        self.power += u64::from(power);
+        self.readings += 1;
+        if self.readings < self.size {
+            return;
+        }
+
+        let clock = self.clock as f64 / self.readings as f64;
+        let watts = self.power as f64 / self.readings as f64 / 1000.0;
+        let efficiency = clock / watts;
+        let trend = match self.previous {
+            Some(previous) => {
+                let change = (efficiency / previous - 1.0) * 100.0;
+                format!(
+                    " ({:+.1}%, {} than the previous window)",
+                    change,
+                    if change >= 0.0 { "better" } else { "worse" }
+                )
+            }
+            None => String::new(),
+        };
+        println!(
+            "Efficiency over {} readings under load: {:.0} MHz at {:.1} W, {:.2} MHz/W{}",
+            self.readings, clock, watts, efficiency, trend
+        );
+        *self = Efficiency {
+            previous: Some(efficiency),
+            ..Efficiency::new(self.size)
+        };
+    }
+}
+
+/// Prints a telemetry line for `device` every `interval` until SIGINT or
+/// SIGTERM is received, also appending it to `csv` if given. XID errors are
+/// logged as they arrive, and reset the GPU to its defaults if
+/// `revert_on_xid` is set. With `efficiency_window`, the core clock per watt
+/// is printed every that many readings under load. On exit, the minimum,
+/// average and maximum power draw, temperature and core clock of the last
+/// `stats_window` readings and the time the GPU was throttled while
+/// monitoring are printed.
+pub fn run(
+    nvml: &Nvml,
+    device: &Device,
+    interval: Duration,
+    revert_on_xid: bool,
+    mut csv: Option<CsvLog>,
+    stats_window: usize,
+    efficiency_window: Option<usize>,
+) -> std::io::Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&stop))?;
+
+    let xids = XidWatch::register(nvml, [device]);
+    let mut stats = Stats::new(stats_window);
+    let mut efficiency = efficiency_window.map(Efficiency::new);
+    while !stop.load(Ordering::Relaxed) {
+        let sample = Sample::read(device);
+        println!("{}", sample);
+        stats.add(&sample);
+        if let Some(efficiency) = &mut efficiency {
+            efficiency.add(&sample);
+        }
+        if let Some(log) = &mut csv {
+            if let Err(e) = log.write(&sample) {
+                warn!("Failed to write to {}: {}", log.path.display(), e);
+            }
+        }
+        sleep_unless_stopped(interval, &stop);
+        if let Some(xids) = &xids {
+            xids.drain(revert_on_xid);
+        }
+    }
+
+    println!();
+    stats.print();
+    Ok(())
+}
+
+/// Sleeps for `duration` in short slices so a pending stop request is noticed
+/// promptly.
+pub fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,301 @@
+// Helpers for NVML functions that `nvml-wrapper` does not expose. These load
+// `libnvidia-ml.so` directly and look the functions up by name, so a driver
+// that lacks a function produces an error instead of a crash.
+
+use libloading::{Library, Symbol};
+use log::debug;
+use nvml_wrapper::error::nvml_try;
+use nvml_wrapper::Device;
+use nvml_wrapper_sys::bindings::{
+    field_id, nvmlDeviceAttributes_t, nvmlDevice_t, nvmlFieldValue_t, nvmlReturn_t,
+    nvmlTemperatureThresholds_t,
+};
+use serde::Serialize;
+use std::os::raw::{c_int, c_uint};
+use std::sync::OnceLock;
+
+const NVML_LIB: &str = "libnvidia-ml.so.1";
+
+type GetMinMaxVfOffsetFn =
+    unsafe extern "C" fn(nvmlDevice_t, *mut c_int, *mut c_int) -> nvmlReturn_t;
+type GetIndexFn = unsafe extern "C" fn(nvmlDevice_t, *mut c_uint) -> nvmlReturn_t;
+type GetMigDeviceHandleFn =
+    unsafe extern "C" fn(nvmlDevice_t, c_uint, *mut nvmlDevice_t) -> nvmlReturn_t;
+type GetTemperatureThresholdFn =
+    unsafe extern "C" fn(nvmlDevice_t, nvmlTemperatureThresholds_t, *mut c_uint) -> nvmlReturn_t;
+type SetTemperatureThresholdFn =
+    unsafe extern "C" fn(nvmlDevice_t, nvmlTemperatureThresholds_t, *mut c_int) -> nvmlReturn_t;
+type GetAttributesFn =
+    unsafe extern "C" fn(nvmlDevice_t, *mut nvmlDeviceAttributes_t) -> nvmlReturn_t;
+type GetFieldValuesFn =
+    unsafe extern "C" fn(nvmlDevice_t, c_int, *mut nvmlFieldValue_t) -> nvmlReturn_t;
+
+/// Returns the NVML library, loading it on first use. The monitor and daemon
+/// loops query the helpers repeatedly, so the handle is shared for the
+/// lifetime of the process.
+fn library() -> Result<&'static Library, String> {
+    static LIBRARY: OnceLock<Result<Library, String>> = OnceLock::new();
+    LIBRARY
+        .get_or_init(|| {
+            unsafe { Library::new(NVML_LIB) }
+                .map_err(|e| format!("Failed to load {}: {}", NVML_LIB, e))
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+/// Converts an NVML return code into a `Result`, logging it at debug level.
+fn check(call: &str, ret: nvmlReturn_t) -> Result<(), String> {
+    debug!("{}: return code {}", call, ret);
+    nvml_try(ret).map_err(|e| e.to_string())
+}
+
+/// Looks up `name` (without the trailing NUL) in the NVML library.
+///
+/// # Safety
+/// `T` must match the C signature of the function.
+unsafe fn symbol<'lib, T>(lib: &'lib Library, name: &str) -> Result<Symbol<'lib, T>, String> {
+    lib.get(format!("{}\0", name).as_bytes())
+        .map_err(|_| format!("{} is not supported by this driver", name))
+}
+
+/// Returns the NVML handle of `device`, checked against this module's copy of
+/// the library by asking it for the device's index. A mismatch means
+/// `nvml-wrapper` loaded a different NVML than `NVML_LIB`, and passing its
+/// handles on would fail in confusing ways.
+fn raw_device_handle(lib: &Library, device: &Device) -> Result<nvmlDevice_t, String> {
+    let unusable = || {
+        "NVML device handle from nvml-wrapper is not usable, unsupported nvml-wrapper version"
+            .to_string()
+    };
+    let expected = device.index().map_err(|e| e.to_string())?;
+    unsafe {
+        let handle = device.handle();
+        let get: Symbol<GetIndexFn> = symbol(lib, "nvmlDeviceGetIndex")?;
+        let mut index: c_uint = 0;
+        check("nvmlDeviceGetIndex", get(handle, &mut index)).map_err(|_| unusable())?;
+        if index != expected {
+            return Err(unusable());
+        }
+        Ok(handle)
+    }
+}
+
+/// Returns the range of core clock offsets in MHz the driver accepts for
+/// `device`. Offsets outside it are ignored rather than rejected.
+pub fn get_gpc_clock_vf_offset_range(device: &Device) -> Result<(i32, i32), String> {
+    get_vf_offset_range(device, "nvmlDeviceGetGpcClkMinMaxVfOffset")
+}
+
+/// Returns the range of memory clock offsets in MHz the driver accepts for
+/// `device`.
+pub fn get_mem_clock_vf_offset_range(device: &Device) -> Result<(i32, i32), String> {
+    get_vf_offset_range(device, "nvmlDeviceGetMemClkMinMaxVfOffset")
+}
+
+fn get_vf_offset_range(device: &Device, name: &str) -> Result<(i32, i32), String> {
+    let lib = library()?;
+    unsafe {
+        let get: Symbol<GetMinMaxVfOffsetFn> = symbol(lib, name)?;
+        let (mut min, mut max): (c_int, c_int) = (0, 0);
+        check(
+            name,
+            get(raw_device_handle(lib, device)?, &mut min, &mut max),
+        )?;
+        Ok((min, max))
+    }
+}
+
+/// A MIG instance of a GPU and the share of the GPU it was given.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigInstance {
+    pub gpu_instance_id: u32,
+    pub compute_instance_id: u32,
+    /// Memory in MiB
+    pub memory: u64,
+    /// Slices of the GPU's memory and engines held by the GPU instance
+    pub gpu_slices: u32,
+    /// Slices of the GPU instance's SMs held by the compute instance
+    pub compute_slices: u32,
+    pub multiprocessors: u32,
+}
+
+/// Lists the MIG instances of a GPU with MIG mode enabled.
+pub fn get_mig_instances(device: &Device) -> Result<Vec<MigInstance>, String> {
+    let lib = library()?;
+    unsafe {
+        let max_count: Symbol<GetIndexFn> = symbol(lib, "nvmlDeviceGetMaxMigDeviceCount")?;
+        let get_handle: Symbol<GetMigDeviceHandleFn> =
+            symbol(lib, "nvmlDeviceGetMigDeviceHandleByIndex")?;
+        let gpu_instance_id: Symbol<GetIndexFn> = symbol(lib, "nvmlDeviceGetGpuInstanceId")?;
+        let compute_instance_id: Symbol<GetIndexFn> =
+            symbol(lib, "nvmlDeviceGetComputeInstanceId")?;
+        let attributes: Symbol<GetAttributesFn> = symbol(lib, "nvmlDeviceGetAttributes_v2")?;
+        let handle = raw_device_handle(lib, device)?;
+
+        let mut count: c_uint = 0;
+        check(
+            "nvmlDeviceGetMaxMigDeviceCount",
+            max_count(handle, &mut count),
+        )?;
+
+        let mut instances = Vec::new();
+        for index in 0..count {
+            // Slots without an instance report NOT_FOUND.
+            let mut mig: nvmlDevice_t = std::ptr::null_mut();
+            let ret = get_handle(handle, index, &mut mig);
+            if ret == nvml_wrapper_sys::bindings::nvmlReturn_enum_NVML_ERROR_NOT_FOUND {
+                continue;
+            }
+            check("nvmlDeviceGetMigDeviceHandleByIndex", ret)?;
+
+            let mut gi: c_uint = 0;
+            check("nvmlDeviceGetGpuInstanceId", gpu_instance_id(mig, &mut gi))?;
+            let mut ci: c_uint = 0;
+            check(
+                "nvmlDeviceGetComputeInstanceId",
+                compute_instance_id(mig, &mut ci),
+            )?;
+            let mut attrs: nvmlDeviceAttributes_t = std::mem::zeroed();
+            check("nvmlDeviceGetAttributes_v2", attributes(mig, &mut attrs))?;
+
+            instances.push(MigInstance {
+                gpu_instance_id: gi,
+                compute_instance_id: ci,
+                memory: attrs.memorySizeMB,
+                gpu_slices: attrs.gpuInstanceSliceCount,
+                compute_slices: attrs.computeInstanceSliceCount,
+                multiprocessors: attrs.multiprocessorCount,
+            });
+        }
+        Ok(instances)
+    }
+}
+
+/// One end of the range the GPU's target temperature may be set within, or
+/// the target itself. `nvml-wrapper` only exposes the fixed slowdown and
+/// shutdown thresholds.
+#[derive(Clone, Copy, Debug)]
+pub enum TargetTemperatureLimit {
+    Min,
+    Max,
+    Current,
+}
+
+impl TargetTemperatureLimit {
+    fn threshold(self) -> nvmlTemperatureThresholds_t {
+        match self {
+            TargetTemperatureLimit::Min => {
+                nvml_wrapper_sys::bindings::nvmlTemperatureThresholds_enum_NVML_TEMPERATURE_THRESHOLD_ACOUSTIC_MIN
+            }
+            TargetTemperatureLimit::Max => {
+                nvml_wrapper_sys::bindings::nvmlTemperatureThresholds_enum_NVML_TEMPERATURE_THRESHOLD_ACOUSTIC_MAX
+            }
+            TargetTemperatureLimit::Current => {
+                nvml_wrapper_sys::bindings::nvmlTemperatureThresholds_enum_NVML_TEMPERATURE_THRESHOLD_ACOUSTIC_CURR
+            }
+        }
+    }
+}
+
+/// Reads a target temperature limit in °C via `nvmlDeviceGetTemperatureThreshold`.
+pub fn get_target_temperature_limit(
+    device: &Device,
+    limit: TargetTemperatureLimit,
+) -> Result<u32, String> {
+    let lib = library()?;
+    unsafe {
+        let get: Symbol<GetTemperatureThresholdFn> =
+            symbol(lib, "nvmlDeviceGetTemperatureThreshold")?;
+        let mut celsius: c_uint = 0;
+        check(
+            "nvmlDeviceGetTemperatureThreshold",
+            get(
+                raw_device_handle(lib, device)?,
+                limit.threshold(),
+                &mut celsius,
+            ),
+        )?;
+        Ok(celsius)
+    }
+}
+
+/// Sets a target temperature limit in °C via `nvmlDeviceSetTemperatureThreshold`.
+pub fn set_target_temperature_limit(
+    device: &Device,
+    limit: TargetTemperatureLimit,
+    celsius: u32,
+) -> Result<(), String> {
+    set_temperature_threshold(device, limit.threshold(), celsius)
+}
+
+/// Sets the GPU max temperature in °C, above which the GPU slows its clocks
+/// down, via `nvmlDeviceSetTemperatureThreshold`. `nvml-wrapper` can read it
+/// but not set it; many GPUs refuse the change.
+pub fn set_gpu_max_temperature(device: &Device, celsius: u32) -> Result<(), String> {
+    set_temperature_threshold(
+        device,
+        nvml_wrapper_sys::bindings::nvmlTemperatureThresholds_enum_NVML_TEMPERATURE_THRESHOLD_GPU_MAX,
+        celsius,
+    )
+}
+
+fn set_temperature_threshold(
+    device: &Device,
+    threshold: nvmlTemperatureThresholds_t,
+    celsius: u32,
+) -> Result<(), String> {
+    let lib = library()?;
+    unsafe {
+        let set: Symbol<SetTemperatureThresholdFn> =
+            symbol(lib, "nvmlDeviceSetTemperatureThreshold")?;
+        let mut celsius = c_int::try_from(celsius).map_err(|e| e.to_string())?;
+        check(
+            "nvmlDeviceSetTemperatureThreshold",
+            set(raw_device_handle(lib, device)?, threshold, &mut celsius),
+        )
+    }
+}
+
+/// Reads the memory temperature in °C via `nvmlDeviceGetFieldValues`, or
+/// `None` if the GPU has no memory temperature sensor. Mostly GPUs with
+/// GDDR6X or HBM memory have one; `nvml-wrapper` has no query for it.
+pub fn get_memory_temperature(device: &Device) -> Result<Option<u32>, String> {
+    let lib = library()?;
+    unsafe {
+        let get: Symbol<GetFieldValuesFn> = symbol(lib, "nvmlDeviceGetFieldValues")?;
+        let mut value: nvmlFieldValue_t = std::mem::zeroed();
+        value.fieldId = field_id::NVML_FI_DEV_MEMORY_TEMP;
+        let ret = get(raw_device_handle(lib, device)?, 1, &mut value);
+        for (call, ret) in [
+            ("nvmlDeviceGetFieldValues", ret),
+            ("NVML_FI_DEV_MEMORY_TEMP", value.nvmlReturn),
+        ] {
+            if ret == nvml_wrapper_sys::bindings::nvmlReturn_enum_NVML_ERROR_NOT_SUPPORTED {
+                debug!("{}: not supported", call);
+                return Ok(None);
+            }
+            check(call, ret)?;
+        }
+        match value.valueType {
+            nvml_wrapper_sys::bindings::nvmlValueType_enum_NVML_VALUE_TYPE_UNSIGNED_INT => {
+                Ok(Some(value.value.uiVal))
+            }
+            nvml_wrapper_sys::bindings::nvmlValueType_enum_NVML_VALUE_TYPE_SIGNED_INT => {
+                u32::try_from(value.value.siVal)
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            }
+            nvml_wrapper_sys::bindings::nvmlValueType_enum_NVML_VALUE_TYPE_UNSIGNED_LONG_LONG => {
+                u32::try_from(value.value.ullVal)
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            }
+            other => Err(format!(
+                "NVML_FI_DEV_MEMORY_TEMP returned unexpected value type {}",
+                other
+            )),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Draws curves such as the V/F curve and a configured fan curve as PNG line
+// charts, so tuned curves can be inspected or shared as an image.
+
+use plotters::prelude::*;
+use std::path::Path;
+
+/// Size of one chart; several charts are stacked vertically.
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 450;
+
+/// Color of the curves.
+const LINE: RGBColor = RGBColor(0x76, 0xb9, 0x00);
+
+/// A line chart of one series of points.
+pub struct Chart {
+    pub title: String,
+    pub x_label: String,
+    pub y_label: String,
+    pub points: Vec<(f64, f64)>,
+    /// Range of the y axis, fitted to the points if `None`
+    pub y_range: Option<(f64, f64)>,
+}
+
+/// The smallest and largest of `values` with a margin of 5% of the span on
+/// each side, `(0, 1)` if there are none.
+fn bounds(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values
+        .fold(None, |bounds: Option<(f64, f64)>, value| match bounds {
+            Some((min, max)) => Some((min.min(value), max.max(value))),
+            None => Some((value, value)),
+        })
+        .unwrap_or((0.0, 1.0));
+    let margin = if max > min { (max - min) * 0.05 } else { 1.0 };
+    (min - margin, max + margin)
+}
+
+impl Chart {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        area: &DrawingArea<DB, plotters::coord::Shift>,
+    ) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+        let (x_min, x_max) = bounds(self.points.iter().map(|(x, _)| *x));
+        let (y_min, y_max) = self
+            .y_range
+            .unwrap_or_else(|| bounds(self.points.iter().map(|(_, y)| *y)));
+        let mut chart = ChartBuilder::on(area)
+            .caption(&self.title, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+        chart
+            .configure_mesh()
+            .x_desc(self.x_label.as_str())
+            .y_desc(self.y_label.as_str())
+            .draw()?;
+        chart.draw_series(LineSeries::new(
+            self.points.iter().copied(),
+            LINE.stroke_width(2),
+        ))?;
+        chart.draw_series(
+            self.points
+                .iter()
+                .map(|point| Circle::new(*point, 3, LINE.filled())),
+        )?;
+        Ok(())
+    }
+}
+
+/// Renders `charts` stacked vertically into one PNG image at `path`.
+pub fn render(path: &Path, charts: &[Chart]) -> Result<(), String> {
+    let height = HEIGHT * charts.len().max(1) as u32;
+    let root = BitMapBackend::new(path, (WIDTH, height)).into_drawing_area();
+    root.fill(&WHITE).map_err(|e| e.to_string())?;
+    for (chart, area) in charts.iter().zip(root.split_evenly((charts.len(), 1))) {
+        chart.draw(&area).map_err(|e| e.to_string())?;
+    }
+    root.present().map_err(|e| e.to_string())
+}
@@ -0,0 +1,40 @@
+use log::debug;
+use std::fs;
+use std::path::Path;
+
+/// Where the kernel lists the power supplies.
+pub const DEFAULT_PATH: &str = "/sys/class/power_supply";
+
+/// Reads an attribute of the power supply at `supply`, trimmed.
+fn attribute(supply: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(supply.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// Whether the machine runs on battery, judged from the power supplies under
+/// `path`. That is the case when no mains supply is online and a battery is
+/// discharging. Machines without a battery, or where `path` cannot be read,
+/// count as being on AC.
+pub fn on_battery(path: &Path) -> bool {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Failed to read {}: {}", path.display(), e);
+            return false;
+        }
+    };
+
+    let mut discharging = false;
+    for entry in entries.flatten() {
+        let supply = entry.path();
+        match attribute(&supply, "type").as_deref() {
+            Some("Mains") if attribute(&supply, "online").as_deref() == Some("1") => return false,
+            Some("Battery") => {
+                discharging |= attribute(&supply, "status").as_deref() == Some("Discharging")
+            }
+            _ => {}
+        }
+    }
+    discharging
+}
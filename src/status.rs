@@ -0,0 +1,1042 @@
+use crate::monitor::{format_nanoseconds, or_na};
+use crate::nvml_raw::{self, MigInstance, TargetTemperatureLimit};
+use crate::table::Table;
+use crate::xid;
+use log::warn;
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+use nvml_wrapper::enum_wrappers::device::{
+    Clock, ComputeMode, EccCounter, MemoryError, PerformancePolicy, TemperatureThreshold,
+};
+use nvml_wrapper::enums::device::FanControlPolicy;
+use nvml_wrapper::error::NvmlError;
+use nvml_wrapper::Device;
+use serde::Serialize;
+
+/// Snapshot of a GPU's tunable settings as reported by `get`. Fields are
+/// `None` (`null` in JSON) when the query failed; the failures are kept in
+/// `errors` for the human-readable output.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GpuStatus {
+    /// Current performance state number (0 for P0)
+    pub performance_state: Option<u32>,
+    /// Core clock offset in MHz
+    pub freq_offset: Option<i32>,
+    /// Memory clock offset in MHz
+    pub mem_offset: Option<i32>,
+    /// Core clock offsets the driver accepts, in MHz
+    pub freq_offset_range: Option<OffsetRange>,
+    /// Memory clock offsets the driver accepts, in MHz
+    pub mem_offset_range: Option<OffsetRange>,
+    /// Core clock the offset results in
+    pub graphics_clock: ClockStatus,
+    /// Memory clock the offset results in
+    pub memory_clock: ClockStatus,
+    /// Highest boost clock plus the core clock offset, roughly where the
+    /// offset lands under load
+    pub projected_boost_clock: Option<u32>,
+    /// Application clocks, `None` on GPUs without them
+    pub application_clocks: Option<ApplicationClocks>,
+    /// Whether the GPU has application clocks, which mostly only datacenter
+    /// GPUs do
+    pub application_clocks_supported: Option<bool>,
+    /// Memory junction temperature in °C
+    pub memory_temperature: Option<u32>,
+    /// Whether the GPU has a memory temperature sensor
+    pub memory_temperature_supported: Option<bool>,
+    /// The temperature thresholds the GPU reports
+    pub temperature_thresholds: Vec<TemperatureThresholdStatus>,
+    /// Enforced power limit in mW
+    pub power_limit: Option<u32>,
+    /// Power limit set by the user in mW, which the enforced one can be
+    /// capped below
+    pub requested_power_limit: Option<u32>,
+    /// Factory power limit in mW
+    pub default_power_limit: Option<u32>,
+    /// Supported power limit range in mW
+    pub power_limit_range: Option<PowerLimitRange>,
+    /// `default`, `exclusive-process`, `prohibited` or `exclusive-thread`
+    pub compute_mode: Option<&'static str>,
+    pub persistence_mode: Option<bool>,
+    /// Why the clocks are held below their maximum, empty when they are not
+    pub throttle_reasons: Option<Vec<&'static str>>,
+    /// Whether locked or application clocks currently hold the clocks down.
+    /// NVML cannot report the locked range itself.
+    pub clocks_locked: Option<bool>,
+    /// Time in ns the power limit held the clocks down since the driver was
+    /// loaded
+    pub power_violation_time: Option<u64>,
+    /// Time in ns the temperature held the clocks down since the driver was
+    /// loaded, not counted by most GPUs
+    pub thermal_violation_time: Option<u64>,
+    pub pcie_link: PcieLink,
+    /// Whether the GPU is partitioned into MIG instances
+    pub mig_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mig_instances: Option<Vec<MigInstance>>,
+    /// Whether the GPU reports XID errors to `monitor` and `daemon`
+    pub xid_errors_supported: Option<bool>,
+    pub fans: Vec<FanStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vf_curve: Option<Vec<VfPoint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ecc: Option<EccStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supported_clocks: Option<Vec<SupportedClocks>>,
+    #[serde(skip)]
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PowerLimitRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Serialize)]
+pub struct OffsetRange {
+    pub min: i32,
+    pub max: i32,
+}
+
+/// Clock frequencies in MHz
+#[derive(Serialize)]
+pub struct ClockStatus {
+    pub current: Option<u32>,
+    /// Highest boost clock
+    pub max: Option<u32>,
+}
+
+/// The clocks in MHz that compute applications run at on GPUs with
+/// application clocks, and the ones `reset-app-clocks` returns to.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplicationClocks {
+    pub graphics: u32,
+    pub memory: u32,
+    pub default_graphics: Option<u32>,
+    pub default_memory: Option<u32>,
+}
+
+impl ApplicationClocks {
+    /// Reads the application clocks of `device`, `None` if it has none.
+    pub fn read(device: &Device) -> Result<Option<ApplicationClocks>, NvmlError> {
+        let current = |clock| {
+            crate::retried("nvmlDeviceGetApplicationsClock", || {
+                device.applications_clock(clock)
+            })
+        };
+        let default = |clock| {
+            crate::retried("nvmlDeviceGetDefaultApplicationsClock", || {
+                device.default_applications_clock(clock)
+            })
+            .ok()
+        };
+        let graphics = match current(Clock::Graphics) {
+            Ok(graphics) => graphics,
+            Err(NvmlError::NotSupported) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(ApplicationClocks {
+            graphics,
+            memory: current(Clock::Memory)?,
+            default_graphics: default(Clock::Graphics),
+            default_memory: default(Clock::Memory),
+        }))
+    }
+}
+
+/// One point of the GPU core clock curve: the clock range of a P-state and
+/// the offset applied to it, all in MHz. NVML exposes the curve per P-state
+/// and does not report the voltages behind it.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VfPoint {
+    pub pstate: u32,
+    pub min_clock: u32,
+    pub max_clock: u32,
+    pub offset: i32,
+    pub min_offset: i32,
+    pub max_offset: i32,
+}
+
+impl VfPoint {
+    /// Reads the clock curve of `device`, one point per supported P-state
+    /// from the fastest to the slowest.
+    pub fn read_curve(device: &Device) -> Result<Vec<VfPoint>, String> {
+        let read = || -> Result<Vec<VfPoint>, NvmlError> {
+            let pstates = crate::retried("nvmlDeviceGetSupportedPerformanceStates", || {
+                device.supported_performance_states()
+            })?;
+            pstates
+                .into_iter()
+                .map(|pstate| {
+                    let (min_clock, max_clock) =
+                        crate::retried("nvmlDeviceGetMinMaxClockOfPState", || {
+                            device.min_max_clock_of_pstate(Clock::Graphics, pstate)
+                        })?;
+                    let offset = crate::retried("nvmlDeviceGetClockOffsets", || {
+                        device.clock_offset(Clock::Graphics, pstate)
+                    })?;
+                    Ok(VfPoint {
+                        pstate: pstate.as_c(),
+                        min_clock,
+                        max_clock,
+                        offset: offset.clock_offset_mhz,
+                        min_offset: offset.min_clock_offset_mhz,
+                        max_offset: offset.max_clock_offset_mhz,
+                    })
+                })
+                .collect()
+        };
+        read().map_err(|e| match e {
+            NvmlError::FailedToLoadSymbol(_) | NvmlError::NotSupported => {
+                "V/F curve editing not supported on this driver".to_string()
+            }
+            e => format!("{:?}", e),
+        })
+    }
+}
+
+/// PCIe link generation and width, as running and the most the GPU and its
+/// slot support. Fields are `None` when the GPU cannot report them.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PcieLink {
+    pub generation: Option<u32>,
+    pub width: Option<u32>,
+    pub max_generation: Option<u32>,
+    pub max_width: Option<u32>,
+}
+
+impl PcieLink {
+    /// Reads the link of `device`, leaving out what it cannot report.
+    pub fn read(device: &Device) -> PcieLink {
+        PcieLink {
+            generation: crate::retried("nvmlDeviceGetCurrPcieLinkGeneration", || {
+                device.current_pcie_link_gen()
+            })
+            .ok(),
+            width: crate::retried("nvmlDeviceGetCurrPcieLinkWidth", || {
+                device.current_pcie_link_width()
+            })
+            .ok(),
+            max_generation: crate::retried("nvmlDeviceGetMaxPcieLinkGeneration", || {
+                device.max_pcie_link_gen()
+            })
+            .ok(),
+            max_width: crate::retried("nvmlDeviceGetMaxPcieLinkWidth", || {
+                device.max_pcie_link_width()
+            })
+            .ok(),
+        }
+    }
+
+    /// Describes the link like `Gen 1 x16 (max Gen 4 x16, running below it)`,
+    /// `None` if nothing could be read. GPUs lower the generation to save
+    /// power while idle, so only a link below its maximum under load is a
+    /// problem.
+    pub fn describe(&self) -> Option<String> {
+        let link = |generation: Option<u32>, width: Option<u32>| match (generation, width) {
+            (Some(generation), Some(width)) => Some(format!("Gen {} x{}", generation, width)),
+            (Some(generation), None) => Some(format!("Gen {}", generation)),
+            (None, Some(width)) => Some(format!("x{}", width)),
+            (None, None) => None,
+        };
+        let below = |current: Option<u32>, max: Option<u32>| {
+            current.zip(max).is_some_and(|(current, max)| current < max)
+        };
+        let below =
+            below(self.generation, self.max_generation) || below(self.width, self.max_width);
+        match (
+            link(self.generation, self.width),
+            link(self.max_generation, self.max_width),
+        ) {
+            (Some(current), Some(max)) if below => {
+                Some(format!("{} (max {}, running below it)", current, max))
+            }
+            (Some(current), Some(max)) => Some(format!("{} (max {})", current, max)),
+            (Some(current), None) => Some(current),
+            (None, Some(max)) => Some(format!("max {}", max)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// A temperature threshold in °C
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemperatureThresholdStatus {
+    /// `shutdown`, `slowdown`, `memory max`, `max`, `target min`, `target`
+    /// or `target max`
+    pub name: &'static str,
+    pub celsius: u32,
+    /// Whether NVML offers no way to change the threshold. The others may
+    /// still be refused by the GPU.
+    pub read_only: bool,
+}
+
+#[derive(Serialize)]
+pub struct FanStatus {
+    pub index: u32,
+    /// Fan speed in percent
+    pub speed: Option<u32>,
+    /// `manual` or `automatic`
+    pub control: Option<&'static str>,
+}
+
+/// A status tagged with the GPU's NVML index, as printed by `get --all`.
+#[derive(Serialize)]
+pub struct IndexedStatus {
+    pub index: u32,
+    #[serde(flatten)]
+    pub status: GpuStatus,
+}
+
+impl IndexedStatus {
+    /// Lays out the main settings of `statuses` as a table with one row per
+    /// GPU, reporting failed queries on stderr.
+    pub fn table(statuses: &[IndexedStatus]) -> Table {
+        let mut table = Table::new(&[
+            "GPU",
+            "P-state",
+            "Core offset",
+            "Mem offset",
+            "Core clock",
+            "Mem clock",
+            "Power limit",
+            "Fans",
+            "Throttle",
+        ]);
+        for IndexedStatus { index, status } in statuses {
+            let fans: Vec<String> = status
+                .fans
+                .iter()
+                .map(|fan| or_na(fan.speed, "%"))
+                .collect();
+            table.row(vec![
+                index.to_string(),
+                or_na(
+                    status.performance_state.map(|state| format!("P{}", state)),
+                    "",
+                ),
+                or_na(status.freq_offset, " MHz"),
+                or_na(status.mem_offset, " MHz"),
+                or_na(status.graphics_clock.current, " MHz"),
+                or_na(status.memory_clock.current, " MHz"),
+                or_na(status.power_limit.map(|limit| limit / 1000), " W"),
+                if fans.is_empty() {
+                    "N/A".to_string()
+                } else {
+                    fans.join(" ")
+                },
+                or_na(
+                    status
+                        .throttle_reasons
+                        .as_deref()
+                        .map(describe_throttle_reasons),
+                    "",
+                ),
+            ]);
+            for error in &status.errors {
+                warn!("GPU {}: {}", index, error);
+            }
+        }
+        table
+    }
+}
+
+/// Identifying details of a GPU, as printed by `list`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub index: u32,
+    pub name: Option<String>,
+    pub uuid: Option<String>,
+    pub pci_bus_id: Option<String>,
+    /// Total memory in MiB
+    pub memory: Option<u64>,
+}
+
+impl DeviceInfo {
+    pub fn read(index: u32, device: &Device) -> DeviceInfo {
+        DeviceInfo {
+            index,
+            name: device.name().ok(),
+            uuid: device.uuid().ok(),
+            pci_bus_id: device.pci_info().map(|pci| pci.bus_id).ok(),
+            memory: device
+                .memory_info()
+                .map(|memory| memory.total / (1024 * 1024))
+                .ok(),
+        }
+    }
+
+    /// Lays out `devices` as a table with one row per GPU.
+    pub fn table(devices: &[DeviceInfo]) -> Table {
+        let mut table = Table::new(&["GPU", "Name", "UUID", "PCI bus ID", "VRAM"]);
+        let unknown = |value: &Option<String>| value.clone().unwrap_or("unknown".to_string());
+        for device in devices {
+            table.row(vec![
+                device.index.to_string(),
+                unknown(&device.name),
+                unknown(&device.uuid),
+                unknown(&device.pci_bus_id),
+                device
+                    .memory
+                    .map_or("unknown".to_string(), |memory| format!("{} MiB", memory)),
+            ]);
+        }
+        table
+    }
+}
+
+/// Reads the temperature thresholds of `device`, leaving out those it does
+/// not report. Only the max temperature (`--max-temp-limit`) and the target
+/// temperature range can be set through NVML.
+fn temperature_thresholds(device: &Device) -> Vec<TemperatureThresholdStatus> {
+    let mut thresholds = Vec::new();
+    for (name, threshold, read_only) in [
+        ("shutdown", TemperatureThreshold::Shutdown, true),
+        ("slowdown", TemperatureThreshold::Slowdown, true),
+        ("memory max", TemperatureThreshold::MemoryMax, true),
+        ("max", TemperatureThreshold::GpuMax, false),
+    ] {
+        if let Ok(celsius) = crate::retried("nvmlDeviceGetTemperatureThreshold", || {
+            device.temperature_threshold(threshold)
+        }) {
+            thresholds.push(TemperatureThresholdStatus {
+                name,
+                celsius,
+                read_only,
+            });
+        }
+    }
+    for (name, limit) in [
+        ("target min", TargetTemperatureLimit::Min),
+        ("target", TargetTemperatureLimit::Current),
+        ("target max", TargetTemperatureLimit::Max),
+    ] {
+        if let Ok(celsius) = nvml_raw::get_target_temperature_limit(device, limit) {
+            thresholds.push(TemperatureThresholdStatus {
+                name,
+                celsius,
+                read_only: false,
+            });
+        }
+    }
+    thresholds
+}
+
+/// Collects the failures of the queries made while building a status.
+#[derive(Default)]
+struct Errors(Vec<String>);
+
+impl Errors {
+    /// Runs the NVML call `call` that queries `what`, retrying it as
+    /// configured with `--retry` and recording the error on failure.
+    fn nvml<T>(
+        &mut self,
+        what: &str,
+        call: &str,
+        query: impl FnMut() -> Result<T, NvmlError>,
+    ) -> Option<T> {
+        self.check(what, crate::retried(call, query))
+    }
+
+    /// Unwraps the result of a query that makes several NVML calls, each
+    /// retried on its own, recording the error on failure.
+    fn check<T>(&mut self, what: &str, result: Result<T, NvmlError>) -> Option<T> {
+        result
+            .map_err(|e| self.0.push(format!("Failed to get {}: {:?}", what, e)))
+            .ok()
+    }
+
+    /// Unwraps a raw NVML helper result, recording the error on failure.
+    fn raw<T>(&mut self, what: &str, result: Result<T, String>) -> Option<T> {
+        result
+            .map_err(|e| self.0.push(format!("Failed to get {}: {}", what, e)))
+            .ok()
+    }
+}
+
+/// ECC memory error counters. The counts are `None` when ECC is disabled.
+#[derive(Serialize)]
+pub struct EccStatus {
+    pub enabled: bool,
+    /// Errors since the driver was loaded
+    pub volatile: Option<EccCounts>,
+    /// Errors over the lifetime of the GPU
+    pub aggregate: Option<EccCounts>,
+}
+
+#[derive(Serialize)]
+pub struct EccCounts {
+    pub corrected: Option<u64>,
+    pub uncorrected: Option<u64>,
+}
+
+/// The core clocks the GPU can be locked to at one memory clock, all in MHz
+/// and from fastest to slowest.
+#[derive(Serialize)]
+pub struct SupportedClocks {
+    pub memory: u32,
+    pub graphics: Option<Vec<u32>>,
+}
+
+/// Optional sections of the status that are only queried on request.
+#[derive(Default)]
+pub struct Details {
+    pub vf_curve: bool,
+    pub ecc: bool,
+    pub supported_clocks: bool,
+}
+
+impl GpuStatus {
+    /// Queries every setting from `device`, plus the optional sections
+    /// requested in `details`.
+    pub fn read(device: &Device, details: &Details) -> GpuStatus {
+        let mut errors = Errors::default();
+
+        let performance_state = errors
+            .nvml(
+                "GPU performance state",
+                "nvmlDeviceGetPerformanceState",
+                || device.performance_state(),
+            )
+            .map(|state| state.as_c())
+            .filter(|state| *state <= 15);
+        let freq_offset = errors.nvml(
+            "GPU core clock offset",
+            "nvmlDeviceGetGpcClkVfOffset",
+            || device.gpc_clock_vf_offset(),
+        );
+        let mem_offset = errors.nvml(
+            "GPU memory clock offset",
+            "nvmlDeviceGetMemClkVfOffset",
+            || device.mem_clock_vf_offset(),
+        );
+        let offset_range = |(min, max)| OffsetRange { min, max };
+        let freq_offset_range = errors
+            .raw(
+                "GPU core clock offset range",
+                nvml_raw::get_gpc_clock_vf_offset_range(device),
+            )
+            .map(offset_range);
+        let mem_offset_range = errors
+            .raw(
+                "GPU memory clock offset range",
+                nvml_raw::get_mem_clock_vf_offset_range(device),
+            )
+            .map(offset_range);
+        let mut clock = |clock: Clock, name: &str| ClockStatus {
+            current: errors.nvml(
+                &format!("GPU {} clock", name),
+                "nvmlDeviceGetClockInfo",
+                || device.clock_info(clock),
+            ),
+            max: errors.nvml(
+                &format!("GPU max {} clock", name),
+                "nvmlDeviceGetMaxClockInfo",
+                || device.max_clock_info(clock),
+            ),
+        };
+        let graphics_clock = clock(Clock::Graphics, "core");
+        let projected_boost_clock = graphics_clock
+            .max
+            .zip(freq_offset)
+            .map(|(max, offset)| crate::projected_boost_clock(max, offset));
+        let memory_clock = clock(Clock::Memory, "memory");
+        let application_clocks =
+            errors.check("GPU application clocks", ApplicationClocks::read(device));
+        let memory_temperature = errors.raw(
+            "GPU memory temperature",
+            nvml_raw::get_memory_temperature(device),
+        );
+        let temperature_thresholds = temperature_thresholds(device);
+        let power_limit = errors.nvml("GPU power limit", "nvmlDeviceGetEnforcedPowerLimit", || {
+            device.enforced_power_limit()
+        });
+        let requested_power_limit = errors.nvml(
+            "GPU requested power limit",
+            "nvmlDeviceGetPowerManagementLimit",
+            || device.power_management_limit(),
+        );
+        let default_power_limit = errors.nvml(
+            "GPU default power limit",
+            "nvmlDeviceGetPowerManagementDefaultLimit",
+            || device.power_management_limit_default(),
+        );
+        let power_limit_range = errors
+            .nvml(
+                "GPU power limit constraints",
+                "nvmlDeviceGetPowerManagementLimitConstraints",
+                || device.power_management_limit_constraints(),
+            )
+            .map(|constraints| PowerLimitRange {
+                min: constraints.min_limit,
+                max: constraints.max_limit,
+            });
+
+        let compute_mode = errors
+            .nvml("GPU compute mode", "nvmlDeviceGetComputeMode", || {
+                device.compute_mode()
+            })
+            .map(compute_mode_name);
+        let persistence_mode = errors.nvml(
+            "GPU persistence mode",
+            "nvmlDeviceGetPersistenceMode",
+            || device.is_in_persistent_mode(),
+        );
+        let throttle_reasons = errors.nvml(
+            "GPU throttle reasons",
+            "nvmlDeviceGetCurrentClocksThrottleReasons",
+            || throttle_reasons(device),
+        );
+        let clocks_locked = throttle_reasons
+            .as_ref()
+            .map(|reasons| reasons.contains(&APPLICATIONS_CLOCKS_SETTING));
+        let power_violation_time = errors
+            .nvml(
+                "GPU power violation time",
+                "nvmlDeviceGetViolationStatus",
+                || violation_time(device, PerformancePolicy::Power),
+            )
+            .flatten();
+        let thermal_violation_time = errors
+            .nvml(
+                "GPU thermal violation time",
+                "nvmlDeviceGetViolationStatus",
+                || violation_time(device, PerformancePolicy::Thermal),
+            )
+            .flatten();
+        let pcie_link = PcieLink::read(device);
+        let mig_mode = errors.check("GPU MIG mode", crate::mig_enabled(device));
+        let mig_instances = if mig_mode == Some(true) {
+            errors.raw("GPU MIG instances", nvml_raw::get_mig_instances(device))
+        } else {
+            None
+        };
+        let xid_errors_supported = errors.nvml(
+            "GPU XID error support",
+            "nvmlDeviceGetSupportedEventTypes",
+            || xid::supported(device),
+        );
+
+        let mut fans = Vec::new();
+        for fan in errors
+            .nvml("GPU fan count", "nvmlDeviceGetNumFans", || {
+                device.num_fans()
+            })
+            .map_or(0..0, |count| 0..count)
+        {
+            let speed = errors.nvml(
+                &format!("GPU fan {} speed", fan),
+                "nvmlDeviceGetFanSpeed_v2",
+                || device.fan_speed(fan),
+            );
+            let control = errors
+                .nvml(
+                    &format!("GPU fan {} control policy", fan),
+                    "nvmlDeviceGetFanControlPolicy_v2",
+                    || device.fan_control_policy(fan),
+                )
+                .map(|policy| match policy {
+                    FanControlPolicy::Manual => "manual",
+                    FanControlPolicy::TemperatureContinousSw => "automatic",
+                });
+            fans.push(FanStatus {
+                index: fan,
+                speed,
+                control,
+            });
+        }
+
+        let vf_curve = if details.vf_curve {
+            errors.raw("GPU V/F curve", VfPoint::read_curve(device))
+        } else {
+            None
+        };
+
+        let ecc = if details.ecc {
+            errors
+                .nvml("GPU ECC mode", "nvmlDeviceGetEccMode", || {
+                    device.is_ecc_enabled()
+                })
+                .map(|mode| {
+                    let mut counts = |counter: EccCounter, name: &str| {
+                        mode.currently_enabled.then(|| EccCounts {
+                            corrected: errors.nvml(
+                                &format!("{} corrected ECC errors", name),
+                                "nvmlDeviceGetTotalEccErrors",
+                                || device.total_ecc_errors(MemoryError::Corrected, counter),
+                            ),
+                            uncorrected: errors.nvml(
+                                &format!("{} uncorrected ECC errors", name),
+                                "nvmlDeviceGetTotalEccErrors",
+                                || device.total_ecc_errors(MemoryError::Uncorrected, counter),
+                            ),
+                        })
+                    };
+                    EccStatus {
+                        enabled: mode.currently_enabled,
+                        volatile: counts(EccCounter::Volatile, "volatile"),
+                        aggregate: counts(EccCounter::Aggregate, "aggregate"),
+                    }
+                })
+        } else {
+            None
+        };
+
+        let supported_clocks = if details.supported_clocks {
+            errors
+                .nvml(
+                    "GPU supported memory clocks",
+                    "nvmlDeviceGetSupportedMemoryClocks",
+                    || device.supported_memory_clocks(),
+                )
+                .map(|memory_clocks| {
+                    memory_clocks
+                        .into_iter()
+                        .map(|memory| SupportedClocks {
+                            memory,
+                            graphics: errors.nvml(
+                                &format!("GPU supported core clocks at {} MHz memory", memory),
+                                "nvmlDeviceGetSupportedGraphicsClocks",
+                                || device.supported_graphics_clocks(memory),
+                            ),
+                        })
+                        .collect()
+                })
+        } else {
+            None
+        };
+
+        GpuStatus {
+            performance_state,
+            freq_offset,
+            mem_offset,
+            freq_offset_range,
+            mem_offset_range,
+            graphics_clock,
+            memory_clock,
+            projected_boost_clock,
+            application_clocks_supported: application_clocks
+                .as_ref()
+                .map(|clocks| clocks.is_some()),
+            application_clocks: application_clocks.flatten(),
+            memory_temperature: memory_temperature.flatten(),
+            memory_temperature_supported: memory_temperature.map(|celsius| celsius.is_some()),
+            temperature_thresholds,
+            power_limit,
+            requested_power_limit,
+            default_power_limit,
+            power_limit_range,
+            compute_mode,
+            persistence_mode,
+            throttle_reasons,
+            clocks_locked,
+            power_violation_time,
+            thermal_violation_time,
+            pcie_link,
+            mig_mode,
+            mig_instances,
+            xid_errors_supported,
+            fans,
+            vf_curve,
+            ecc,
+            supported_clocks,
+            errors: errors.0,
+        }
+    }
+
+    /// Prints the status as human-readable lines, with failed queries
+    /// reported on stderr.
+    pub fn print(&self) {
+        if let Some(state) = self.performance_state {
+            println!("GPU performance state: P{}", state);
+        }
+        if let Some(freq_offset) = self.freq_offset {
+            println!("GPU core clock offset: {} MHz", freq_offset);
+        }
+        if let Some(mem_offset) = self.mem_offset {
+            println!("GPU memory clock offset: {} MHz", mem_offset);
+        }
+        for (name, range) in [
+            ("core", &self.freq_offset_range),
+            ("memory", &self.mem_offset_range),
+        ] {
+            if let Some(range) = range {
+                println!(
+                    "GPU {} clock offset range: {:+} to {:+} MHz",
+                    name, range.min, range.max
+                );
+            }
+        }
+        for (name, clock) in [
+            ("core", &self.graphics_clock),
+            ("memory", &self.memory_clock),
+        ] {
+            match (clock.current, clock.max) {
+                (Some(current), Some(max)) => {
+                    println!("GPU {} clock: {} MHz (max {} MHz)", name, current, max)
+                }
+                (Some(current), None) => println!("GPU {} clock: {} MHz", name, current),
+                (None, Some(max)) => println!("GPU max {} clock: {} MHz", name, max),
+                (None, None) => {}
+            }
+        }
+        if let (Some(projected), Some(max), Some(offset)) = (
+            self.projected_boost_clock,
+            self.graphics_clock.max,
+            self.freq_offset,
+        ) {
+            println!(
+                "GPU projected boost clock: {} MHz (max {} MHz {:+} MHz offset)",
+                projected, max, offset
+            );
+        }
+        match (&self.application_clocks, self.application_clocks_supported) {
+            (Some(clocks), _) => {
+                print!(
+                    "GPU application clocks: {} MHz core, {} MHz memory",
+                    clocks.graphics, clocks.memory
+                );
+                match (clocks.default_graphics, clocks.default_memory) {
+                    (Some(graphics), Some(memory)) => {
+                        println!(" (default {} MHz core, {} MHz memory)", graphics, memory)
+                    }
+                    _ => println!(),
+                }
+            }
+            (None, Some(false)) => println!("GPU application clocks: not supported"),
+            _ => {}
+        }
+        match (self.memory_temperature, self.memory_temperature_supported) {
+            (Some(celsius), _) => println!("GPU memory temperature: {} °C", celsius),
+            (None, Some(false)) => println!("GPU memory temperature: not supported"),
+            _ => {}
+        }
+        if !self.temperature_thresholds.is_empty() {
+            println!("GPU temperature thresholds:");
+        }
+        for threshold in &self.temperature_thresholds {
+            println!(
+                "  {}: {} °C{}",
+                threshold.name,
+                threshold.celsius,
+                if threshold.read_only {
+                    " (read-only)"
+                } else {
+                    ""
+                }
+            );
+        }
+        if let Some(power_limit) = self.power_limit {
+            println!("GPU power limit: {} W", power_limit / 1000);
+        }
+        if let Some(limit) = self.requested_power_limit {
+            println!("GPU requested power limit: {} W", limit / 1000);
+        }
+        if let Some(limit) = self.default_power_limit {
+            println!("GPU default power limit: {} W", limit / 1000);
+        }
+        if let Some(range) = &self.power_limit_range {
+            println!(
+                "GPU power limit range: {}-{} W",
+                range.min / 1000,
+                range.max / 1000
+            );
+        }
+        if let Some(mode) = self.compute_mode {
+            println!("GPU compute mode: {}", mode);
+        }
+        if let Some(enabled) = self.persistence_mode {
+            println!(
+                "GPU persistence mode: {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+        if let Some(reasons) = &self.throttle_reasons {
+            println!(
+                "GPU throttle reasons: {}",
+                describe_throttle_reasons(reasons)
+            );
+        }
+        if let Some(locked) = self.clocks_locked {
+            println!(
+                "GPU locked clocks: {}",
+                if locked {
+                    "limiting the clocks"
+                } else {
+                    "not limiting the clocks"
+                }
+            );
+        }
+        for (name, time) in [
+            ("power limit", self.power_violation_time),
+            ("temperature", self.thermal_violation_time),
+        ] {
+            if let Some(time) = time {
+                println!(
+                    "GPU time throttled by the {}: {}",
+                    name,
+                    format_nanoseconds(time)
+                );
+            }
+        }
+        if let Some(link) = self.pcie_link.describe() {
+            println!("GPU PCIe link: {}", link);
+        }
+        if self.mig_mode == Some(true) {
+            println!("GPU MIG mode: enabled");
+        }
+        for instance in self.mig_instances.iter().flatten() {
+            println!(
+                "  GPU instance {}, compute instance {}: {} GPU slices, {} compute slices, {} SMs, {} MiB",
+                instance.gpu_instance_id,
+                instance.compute_instance_id,
+                instance.gpu_slices,
+                instance.compute_slices,
+                instance.multiprocessors,
+                instance.memory
+            );
+        }
+        if let Some(supported) = self.xid_errors_supported {
+            println!(
+                "GPU XID error reporting: {}",
+                if supported {
+                    "supported"
+                } else {
+                    "not supported"
+                }
+            );
+        }
+        for fan in &self.fans {
+            if let Some(speed) = fan.speed {
+                println!("GPU fan {} speed: {}%", fan.index, speed);
+            }
+            if let Some(control) = fan.control {
+                println!("GPU fan {} control: {}", fan.index, control);
+            }
+        }
+        if let Some(points) = &self.vf_curve {
+            println!("GPU V/F curve:");
+            for point in points {
+                println!(
+                    "  P{}: {}-{} MHz, offset {:+} MHz ({:+} to {:+} MHz allowed)",
+                    point.pstate,
+                    point.min_clock,
+                    point.max_clock,
+                    point.offset,
+                    point.min_offset,
+                    point.max_offset
+                );
+            }
+        }
+        if let Some(ecc) = &self.ecc {
+            if !ecc.enabled {
+                println!("ECC not enabled");
+            }
+            for (name, counts) in [("volatile", &ecc.volatile), ("aggregate", &ecc.aggregate)] {
+                if let Some(counts) = counts {
+                    println!(
+                        "ECC {} errors: {} corrected, {} uncorrected",
+                        name,
+                        counts.corrected.map_or("unknown".into(), |n| n.to_string()),
+                        counts
+                            .uncorrected
+                            .map_or("unknown".into(), |n| n.to_string()),
+                    );
+                }
+            }
+        }
+        if let Some(clocks) = &self.supported_clocks {
+            println!("GPU supported clocks:");
+            for clocks in clocks {
+                match &clocks.graphics {
+                    Some(graphics) => {
+                        let graphics: Vec<String> =
+                            graphics.iter().map(ToString::to_string).collect();
+                        println!(
+                            "  Memory {} MHz: core {} MHz",
+                            clocks.memory,
+                            graphics.join(", ")
+                        );
+                    }
+                    None => println!("  Memory {} MHz", clocks.memory),
+                }
+            }
+        }
+        for error in &self.errors {
+            warn!("{}", error);
+        }
+    }
+}
+
+/// The name of a compute mode as used in the config file.
+pub fn compute_mode_name(mode: ComputeMode) -> &'static str {
+    match mode {
+        ComputeMode::Default => "default",
+        ComputeMode::ExclusiveThread => "exclusive-thread",
+        ComputeMode::Prohibited => "prohibited",
+        ComputeMode::ExclusiveProcess => "exclusive-process",
+    }
+}
+
+/// The throttle reason reported while locked or application clocks hold the
+/// clocks down.
+const APPLICATIONS_CLOCKS_SETTING: &str = "applications clocks setting";
+
+/// The reasons `device` is currently running below its maximum clocks, as
+/// readable names.
+pub fn throttle_reasons(device: &Device) -> Result<Vec<&'static str>, NvmlError> {
+    let reasons = device.current_throttle_reasons()?;
+    Ok([
+        (ThrottleReasons::GPU_IDLE, "GPU idle"),
+        (
+            ThrottleReasons::APPLICATIONS_CLOCKS_SETTING,
+            APPLICATIONS_CLOCKS_SETTING,
+        ),
+        (ThrottleReasons::SW_POWER_CAP, "SW power cap"),
+        (ThrottleReasons::HW_SLOWDOWN, "HW slowdown"),
+        (ThrottleReasons::SYNC_BOOST, "sync boost"),
+        (ThrottleReasons::SW_THERMAL_SLOWDOWN, "SW thermal slowdown"),
+        (ThrottleReasons::HW_THERMAL_SLOWDOWN, "HW thermal slowdown"),
+        (
+            ThrottleReasons::HW_POWER_BRAKE_SLOWDOWN,
+            "HW power brake slowdown",
+        ),
+        (
+            ThrottleReasons::DISPLAY_CLOCK_SETTING,
+            "display clock setting",
+        ),
+    ]
+    .into_iter()
+    .filter(|(reason, _)| reasons.contains(*reason))
+    .map(|(_, name)| name)
+    .collect())
+}
+
+/// The time in ns `policy` held the clocks of `device` below the requested
+/// ones since the driver was loaded, or `None` if the GPU does not count it.
+/// Unlike the throttle reasons, this covers the time between two readings.
+pub fn violation_time(
+    device: &Device,
+    policy: PerformancePolicy,
+) -> Result<Option<u64>, NvmlError> {
+    match device.violation_status(policy) {
+        Ok(time) => Ok(Some(time.violation_time)),
+        Err(NvmlError::NotSupported) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Joins throttle reasons for display, `None` when there are none.
+pub fn describe_throttle_reasons(reasons: &[&str]) -> String {
+    if reasons.is_empty() {
+        "None".to_string()
+    } else {
+        reasons.join(", ")
+    }
+}
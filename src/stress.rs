@@ -0,0 +1,140 @@
+// Generating GPU load takes CUDA or a graphics API, neither of which Zelos
+// depends on. A stress run instead watches the GPU while an external
+// workload such as a game, a render or a benchmark runs, and sums up how the
+// overclock held up under it.
+
+use crate::monitor::{sleep_unless_stopped, Sample};
+use crate::xid::XidWatch;
+use log::{error, warn};
+use nvml_wrapper::{Device, Nvml};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// GPU utilization in percent from which the GPU counts as under load.
+const LOAD_THRESHOLD: u32 = 50;
+
+/// What was observed during a stress run.
+#[derive(Default)]
+pub struct Report {
+    /// How long the GPU was watched
+    pub watched: Duration,
+    /// How much of that time the GPU was under load
+    pub loaded: Duration,
+    pub max_temperature: Option<u32>,
+    pub max_graphics_clock: Option<u32>,
+    /// Highest power draw in milliwatts
+    pub max_power_usage: Option<u32>,
+    /// Every throttle reason seen while under load
+    pub throttle_reasons: Vec<&'static str>,
+    pub xid_errors: Vec<String>,
+    /// Readings in which every query failed, a sign of a hung GPU
+    pub failed_readings: u32,
+}
+
+impl Report {
+    /// Whether the GPU made it through the run without XID errors or
+    /// unanswered queries.
+    pub fn stable(&self) -> bool {
+        self.xid_errors.is_empty() && self.failed_readings == 0
+    }
+
+    fn add(&mut self, sample: &Sample, interval: Duration) {
+        if sample.temperature.is_none() && sample.gpu_utilization.is_none() {
+            self.failed_readings += 1;
+            return;
+        }
+        self.max_temperature = self.max_temperature.max(sample.temperature);
+        self.max_graphics_clock = self.max_graphics_clock.max(sample.graphics_clock);
+        self.max_power_usage = self.max_power_usage.max(sample.power_usage);
+        if sample
+            .gpu_utilization
+            .is_some_and(|utilization| utilization >= LOAD_THRESHOLD)
+        {
+            self.loaded += interval;
+            for reason in sample.throttle_reasons.iter().flatten() {
+                if !self.throttle_reasons.contains(reason) {
+                    self.throttle_reasons.push(reason);
+                }
+            }
+        }
+    }
+
+    /// Prints the observations followed by the verdict.
+    pub fn print(&self) {
+        println!(
+            "Watched the GPU for {} s, {} s of it under load",
+            self.watched.as_secs(),
+            self.loaded.as_secs()
+        );
+        if let Some(temperature) = self.max_temperature {
+            println!("Max temperature: {} °C", temperature);
+        }
+        if let Some(clock) = self.max_graphics_clock {
+            println!("Max core clock: {} MHz", clock);
+        }
+        if let Some(power) = self.max_power_usage {
+            println!("Peak power draw: {:.1} W", power as f64 / 1000.0);
+        }
+        println!(
+            "Throttle reasons under load: {}",
+            crate::status::describe_throttle_reasons(&self.throttle_reasons)
+        );
+        if self.failed_readings > 0 {
+            println!("Unanswered readings: {}", self.failed_readings);
+        }
+        if self.xid_errors.is_empty() {
+            println!("XID errors: none");
+        }
+        for xid in &self.xid_errors {
+            println!("XID error: {}", xid);
+        }
+        println!(
+            "Verdict: {}",
+            if self.stable() { "stable" } else { "unstable" }
+        );
+    }
+}
+
+/// Watches `device` for `duration`, or until SIGINT or SIGTERM is received,
+/// printing a telemetry line every `interval` and collecting XID errors.
+pub fn run(
+    nvml: &Nvml,
+    device: &Device,
+    duration: Duration,
+    interval: Duration,
+) -> std::io::Result<Report> {
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&stop))?;
+
+    let xids = XidWatch::register(nvml, [device]);
+    let mut report = Report::default();
+    let start = Instant::now();
+    while !stop.load(Ordering::Relaxed) && start.elapsed() < duration {
+        let sample = Sample::read(device);
+        println!("{}", sample);
+        report.add(&sample, interval);
+
+        match &xids {
+            Some(xids) => match xids.next(interval) {
+                Ok(Some(event)) => {
+                    error!("{}", event);
+                    report.xid_errors.push(event.to_string());
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to read XID errors: {:?}", e),
+            },
+            None => sleep_unless_stopped(interval, &stop),
+        }
+    }
+    report.watched = start.elapsed();
+
+    if report.loaded.is_zero() {
+        warn!(
+            "The GPU never reached {}% utilization. Start a workload such as a game or benchmark while `stress` runs.",
+            LOAD_THRESHOLD
+        );
+    }
+    Ok(report)
+}
@@ -0,0 +1,222 @@
+// Terminal dashboard for machines without a graphical session. Shows live
+// telemetry graphs and lets the power limit and core clock offset be adjusted
+// with the keyboard, applying them through `Sets::apply`.
+
+use crate::monitor::{or_na, Sample};
+use crate::{PowerLimit, Sets, DEFAULT_MAX_OFFSET};
+use nvml_wrapper::Device;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::Stylize;
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of samples kept for the graphs
+const HISTORY: usize = 300;
+/// Core clock offset change per key press, in MHz
+const OFFSET_STEP: i32 = 15;
+/// Power limit change per key press, in mW
+const POWER_STEP: u32 = 5000;
+
+struct Dashboard<'a, 'nvml> {
+    device: &'a mut Device<'nvml>,
+    title: String,
+    history: VecDeque<Sample>,
+    /// Enforced power limit in mW
+    power_limit: Option<u32>,
+    power_range: Option<(u32, u32)>,
+    freq_offset: Option<i32>,
+    /// Values chosen with the keyboard but not applied yet
+    pending_power_limit: Option<u32>,
+    pending_freq_offset: Option<i32>,
+    message: String,
+}
+
+/// Runs the dashboard for `device`, taking a sample every `interval`, until
+/// the user quits.
+pub fn run(device: &mut Device, interval: Duration) -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = Dashboard::new(device).run(&mut terminal, interval);
+    ratatui::restore();
+    result
+}
+
+impl<'a, 'nvml> Dashboard<'a, 'nvml> {
+    fn new(device: &'a mut Device<'nvml>) -> Self {
+        let title = format!("Zelos: {}", crate::describe_device(device));
+        let power_range = device
+            .power_management_limit_constraints()
+            .ok()
+            .map(|constraints| (constraints.min_limit, constraints.max_limit));
+        Dashboard {
+            device,
+            title,
+            history: VecDeque::with_capacity(HISTORY),
+            power_limit: None,
+            power_range,
+            freq_offset: None,
+            pending_power_limit: None,
+            pending_freq_offset: None,
+            message: String::new(),
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal, interval: Duration) -> std::io::Result<()> {
+        let mut next_sample = Instant::now();
+        loop {
+            if Instant::now() >= next_sample {
+                self.sample();
+                next_sample = Instant::now() + interval;
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(next_sample.saturating_duration_since(Instant::now()))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Left => self.adjust_freq_offset(-OFFSET_STEP),
+                KeyCode::Right => self.adjust_freq_offset(OFFSET_STEP),
+                KeyCode::Down => self.adjust_power_limit(-(POWER_STEP as i64)),
+                KeyCode::Up => self.adjust_power_limit(POWER_STEP as i64),
+                KeyCode::Enter => self.apply(),
+                _ => {}
+            }
+        }
+    }
+
+    fn sample(&mut self) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(Sample::read(self.device));
+        self.power_limit = self.device.enforced_power_limit().ok();
+        self.freq_offset = self.device.gpc_clock_vf_offset().ok();
+    }
+
+    fn adjust_freq_offset(&mut self, delta: i32) {
+        let current = self.pending_freq_offset.or(self.freq_offset).unwrap_or(0);
+        let limit = DEFAULT_MAX_OFFSET as i32;
+        self.pending_freq_offset = Some((current + delta).clamp(-limit, limit));
+    }
+
+    fn adjust_power_limit(&mut self, delta: i64) {
+        let Some(current) = self.pending_power_limit.or(self.power_limit) else {
+            return;
+        };
+        let (min, max) = self.power_range.unwrap_or((0, u32::MAX));
+        let limit = (current as i64 + delta).clamp(min as i64, max as i64);
+        self.pending_power_limit = Some(limit as u32);
+    }
+
+    fn apply(&mut self) {
+        let sets = Sets {
+            freq_offset: self.pending_freq_offset,
+            power_limit: self.pending_power_limit.map(PowerLimit::Milliwatts),
+            ..Default::default()
+        };
+        if sets.freq_offset.is_none() && sets.power_limit.is_none() {
+            self.message = "Nothing to apply.".to_string();
+            return;
+        }
+        self.message = match sets.apply(self.device, false) {
+            Ok(()) => {
+                self.pending_freq_offset = None;
+                self.pending_power_limit = None;
+                "Successfully set GPU parameters.".to_string()
+            }
+            Err(e) => e.to_string(),
+        };
+        self.sample();
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [title, graphs, controls] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(8),
+            Constraint::Length(5),
+        ])
+        .areas(frame.area());
+        frame.render_widget(Line::from(self.title.as_str()).bold(), title);
+
+        let latest = self.history.back();
+        let graph_areas: [_; 4] = Layout::vertical([Constraint::Fill(1); 4]).areas(graphs);
+        let series: [(String, Vec<Option<u64>>); 4] = [
+            (
+                format!(
+                    "Temperature: {}",
+                    or_na(latest.and_then(|s| s.temperature), " °C")
+                ),
+                self.series(|s| s.temperature.map(u64::from)),
+            ),
+            (
+                format!(
+                    "Core clock: {}",
+                    or_na(latest.and_then(|s| s.graphics_clock), " MHz")
+                ),
+                self.series(|s| s.graphics_clock.map(u64::from)),
+            ),
+            (
+                format!(
+                    "Power: {}",
+                    or_na(latest.and_then(|s| s.power_usage).map(|mw| mw / 1000), " W")
+                ),
+                self.series(|s| s.power_usage.map(|mw| u64::from(mw / 1000))),
+            ),
+            (
+                format!(
+                    "Utilization: {}",
+                    or_na(latest.and_then(|s| s.gpu_utilization), "%")
+                ),
+                self.series(|s| s.gpu_utilization.map(u64::from)),
+            ),
+        ];
+        for ((name, data), area) in series.into_iter().zip(graph_areas) {
+            let sparkline = Sparkline::default()
+                .block(Block::bordered().title(name))
+                .data(data);
+            frame.render_widget(sparkline, area);
+        }
+
+        let watts = |mw: Option<u32>| or_na(mw.map(|mw| mw / 1000), " W");
+        let megahertz = |offset: Option<i32>| match offset {
+            Some(offset) => format!("{:+} MHz", offset),
+            None => "N/A".to_string(),
+        };
+        let mut settings = format!("Power limit: {}", watts(self.power_limit));
+        if self.pending_power_limit.is_some() {
+            settings += &format!(" -> {}", watts(self.pending_power_limit));
+        }
+        settings += &format!("   Core offset: {}", megahertz(self.freq_offset));
+        if self.pending_freq_offset.is_some() {
+            settings += &format!(" -> {}", megahertz(self.pending_freq_offset));
+        }
+        let text = vec![
+            Line::from(settings),
+            Line::from(format!(
+                "←/→ core offset ±{} MHz   ↓/↑ power limit ±{} W   Enter apply   q quit",
+                OFFSET_STEP,
+                POWER_STEP / 1000
+            )),
+            Line::from(self.message.as_str()),
+        ];
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title("Settings")),
+            controls,
+        );
+    }
+
+    /// The history of one reading, oldest first.
+    fn series(&self, value: impl Fn(&Sample) -> Option<u64>) -> Vec<Option<u64>> {
+        self.history.iter().map(value).collect()
+    }
+}
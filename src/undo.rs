@@ -0,0 +1,27 @@
+// Keeps the values `set` replaced, so `undo` can put them back without the
+// user having to remember what the GPU was set to before.
+
+use crate::{GpuSelector, Snapshot};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Where `set` saves the values it is about to replace.
+pub const STATE_FILE: &str = "/var/lib/zelos/last.json";
+
+/// Saves the previous values of each GPU to `path`, replacing those of the
+/// last `set`.
+pub fn save(path: &Path, snapshots: &HashMap<GpuSelector, Snapshot>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(snapshots).map_err(io::Error::other)?;
+    fs::write(path, json)
+}
+
+/// Reads the previous values saved by the last `set`.
+pub fn load(path: &Path) -> io::Result<HashMap<GpuSelector, Snapshot>> {
+    let json = fs::read_to_string(path)?;
+    serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,126 @@
+use crate::monitor::sleep_unless_stopped;
+use log::{error, info};
+use nvml_wrapper::enum_wrappers::device::TemperatureSensor;
+use nvml_wrapper::Device;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings for watching a freshly applied overclock.
+pub struct StabilityWatch {
+    /// How long to watch before the settings are considered stable
+    pub duration: Duration,
+    /// How long telemetry queries may keep failing before the GPU is
+    /// considered hung
+    pub timeout: Duration,
+    /// Temperature in °C above which the settings are reverted
+    pub max_temp: u32,
+}
+
+/// Outcome of a stability watch.
+pub enum Verdict {
+    Stable,
+    Interrupted,
+    /// The settings were reset to their defaults
+    Reverted,
+}
+
+impl StabilityWatch {
+    /// Polls the GPU's utilization and temperature, resetting it to its
+    /// defaults as soon as it overheats or stops answering queries.
+    pub fn run(&self, device: &mut Device) -> std::io::Result<Verdict> {
+        let stop = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&stop))?;
+
+        info!(
+            "Watching GPU stability for {} s (Ctrl-C to stop)...",
+            self.duration.as_secs()
+        );
+
+        let deadline = Instant::now() + self.duration;
+        let mut failing_since: Option<Instant> = None;
+        while Instant::now() < deadline {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(Verdict::Interrupted);
+            }
+
+            let reading = device
+                .utilization_rates()
+                .and_then(|_| device.temperature(TemperatureSensor::Gpu));
+            let problem = match reading {
+                Ok(temperature) if temperature > self.max_temp => Some(format!(
+                    "GPU temperature {}°C exceeded {}°C",
+                    temperature, self.max_temp
+                )),
+                Ok(_) => {
+                    failing_since = None;
+                    None
+                }
+                Err(e) => {
+                    let since = *failing_since.get_or_insert_with(Instant::now);
+                    (since.elapsed() >= self.timeout).then(|| {
+                        format!(
+                            "GPU stopped responding for {} s ({:?})",
+                            self.timeout.as_secs(),
+                            e
+                        )
+                    })
+                }
+            };
+
+            if let Some(reason) = problem {
+                error!("{}, reverting GPU settings.", reason);
+                crate::reset(device);
+                return Ok(Verdict::Reverted);
+            }
+
+            sleep_unless_stopped(POLL_INTERVAL, &stop);
+        }
+
+        Ok(Verdict::Stable)
+    }
+}
+
+/// Settings for holding back a change until the GPU is idle, so it does not
+/// hit a running workload.
+pub struct IdleWait {
+    /// GPU utilization in percent below which the GPU counts as idle
+    pub threshold: u32,
+    /// How long to wait for the GPU to become idle
+    pub timeout: Duration,
+}
+
+impl IdleWait {
+    /// Polls the GPU's utilization until it drops below the threshold.
+    /// Returns `false` if it did not within the timeout.
+    pub fn run(&self, device: &Device) -> bool {
+        let deadline = Instant::now() + self.timeout;
+        let mut waiting = false;
+        loop {
+            match device.utilization_rates() {
+                Ok(utilization) if utilization.gpu < self.threshold => return true,
+                Ok(utilization) if !waiting => {
+                    info!(
+                        "GPU utilization is {}%, waiting up to {} s for it to drop below {}%...",
+                        utilization.gpu,
+                        self.timeout.as_secs(),
+                        self.threshold
+                    );
+                    waiting = true;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Failed to get GPU utilization: {:?}", e);
+                    return false;
+                }
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::Traced;
+use log::{error, warn};
+use nvml_wrapper::bitmasks::event::EventTypes;
+use nvml_wrapper::enums::event::XidError;
+use nvml_wrapper::error::NvmlError;
+use nvml_wrapper::{Device, EventSet, Nvml};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// A critical XID error reported by the driver, the clearest sign that an
+/// overclock is unstable.
+pub struct XidEvent<'nvml> {
+    pub device: Device<'nvml>,
+    pub xid: Option<u64>,
+    pub time: SystemTime,
+}
+
+impl fmt::Display for XidEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] ", humantime::format_rfc3339_seconds(self.time))?;
+        match self.xid {
+            Some(xid) => write!(f, "XID {} error", xid),
+            None => write!(f, "XID error of unknown type"),
+        }
+    }
+}
+
+/// Receives the XID errors of the registered GPUs.
+pub struct XidWatch<'nvml> {
+    events: EventSet<'nvml>,
+}
+
+impl<'nvml> XidWatch<'nvml> {
+    /// Registers for the XID errors of `devices`. Returns `None` with a
+    /// warning if the driver cannot report them, which is common on consumer
+    /// cards.
+    pub fn register<'a>(
+        nvml: &'nvml Nvml,
+        devices: impl IntoIterator<Item = &'a Device<'nvml>>,
+    ) -> Option<XidWatch<'nvml>>
+    where
+        'nvml: 'a,
+    {
+        let mut events = nvml
+            .create_event_set()
+            .traced("nvmlEventSetCreate")
+            .map_err(|e| warn!("XID error reporting is not available: {:?}", e))
+            .ok()?;
+        for device in devices {
+            events = device
+                .register_events(EventTypes::CRITICAL_XID_ERROR, events)
+                .map_err(|e| warn!("XID error reporting is not available: {:?}", e.error))
+                .ok()?;
+        }
+        Some(XidWatch { events })
+    }
+
+    /// Waits up to `timeout` for the next XID error. Returns `Ok(None)` if
+    /// none arrived in time.
+    pub fn next(&self, timeout: Duration) -> Result<Option<XidEvent<'nvml>>, NvmlError> {
+        match self.events.wait(timeout.as_millis() as u32) {
+            Ok(event) => Ok(Some(XidEvent {
+                device: event.device,
+                xid: match event.event_data {
+                    Some(XidError::Value(xid)) => Some(xid),
+                    Some(XidError::Unknown) | None => None,
+                },
+                time: SystemTime::now(),
+            })),
+            Err(NvmlError::Timeout) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Logs the XID errors that arrived since the last call, resetting the
+    /// affected GPU to its defaults when `revert` is set.
+    pub fn drain(&self, revert: bool) {
+        loop {
+            match self.next(Duration::ZERO) {
+                Ok(Some(mut event)) => {
+                    let index = event
+                        .device
+                        .index()
+                        .map_or("?".to_string(), |index| index.to_string());
+                    error!("{} on GPU {}", event, index);
+                    if revert {
+                        warn!("Reverting GPU {} settings.", index);
+                        crate::reset(&mut event.device);
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to read XID errors: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Whether `device` can report XID errors.
+pub fn supported(device: &Device) -> Result<bool, NvmlError> {
+    match device.supported_event_types() {
+        Ok(events) => Ok(events.contains(EventTypes::CRITICAL_XID_ERROR)),
+        Err(NvmlError::NotSupported) => Ok(false),
+        Err(e) => Err(e),
+    }
+}